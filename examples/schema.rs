@@ -5,8 +5,7 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
-use astrpt::msg::{ExecuteMsg, QueryMsg};
-use astroport::router::{InstantiateMsg, MigrateMsg,};
+use astrpt::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Schema {
@@ -1,36 +1,51 @@
-use astroport::asset::{addr_opt_validate, AssetInfo};
+use std::collections::HashMap;
+
+use astroport::asset::{addr_opt_validate, native_asset, token_asset, token_asset_info, AssetInfo};
 use astroport::pair::PoolResponse;
 use astroport::querier::query_supply;
 
 
-use astroport_pcl_common::utils::check_cw20_in_pool;
 use cosmwasm_std::{
-    entry_point, from_json, to_json_binary, Addr, Binary, Deps, DepsMut, Env, MessageInfo, Reply, Response, StdError, StdResult, SubMsgResponse, SubMsgResult
+    entry_point, from_json, to_json_binary, Addr, Binary, Deps, DepsMut, Env, MessageInfo, Order, Reply, Response, StdError, StdResult, Storage, SubMsgResponse, SubMsgResult
 };
 use cw2::{get_contract_version, set_contract_version};
 use cw20::Cw20ReceiveMsg;
-use cw_utils::{must_pay, parse_instantiate_response_data};
-
+use cw_utils::{must_pay, one_coin, parse_instantiate_response_data};
 
 
-use astroport::router::{
-    InstantiateMsg, MigrateMsg,
-};
-
-use crate::msg::{ExecuteMsg,QueryMsg,Cw20HookMsg};
+use crate::msg::{ExecuteMsg,QueryMsg,Cw20HookMsg,InstantiateMsg,MigrateMsg,RoutesResponse};
 
 use crate::error::ContractError;
-use crate::handlers::{execute_create_pair, execute_provide_liquidity, execute_swap_operations, execute_withdraw_liquidity, generate_key_from_asset_info, DENOM, execute_modify_position};
+use crate::handlers::{assert_deadline, execute_create_pair, execute_provide_liquidity, assert_split_routes, execute_split_swap, execute_swap, execute_swap_best_route, execute_swap_exact_out, execute_swap_operations, execute_withdraw_liquidity, resolve_pool_key, execute_modify_position, execute_update_config, execute_update_manager_config, assert_max_referral_commission, execute_set_pool_status, pool_status};
 
-use crate::query::{query_compute_d, query_lp_price, simulate_swap_operations,query_config};
-use crate::state::{ PAIR_BALANCES, POOLS, QUEUED_MINT};
+use crate::dca::{execute_cancel_dca, execute_create_dca, execute_dca_tranche};
+use crate::flash_loan::{
+    assert_flash_loan_fee, execute_complete_flash_loan, execute_flash_loan,
+};
+use crate::limit_orders::{
+    execute_cancel_limit_order, execute_limit_orders, execute_place_limit_order,
+};
+use crate::query::{
+    query_compute_d, query_config, query_dca_schedules, query_dca_schedules_by_owner,
+    query_lp_price, query_pools_by_asset, query_pools_paginated,
+    find_routes, query_limit_orders_by_owner, query_limit_orders_by_pool,
+    query_reverse_simulation, query_simulate_swap_pools, query_suggest_split,
+    simulate_reverse_swap_operations, simulate_swap_operations, simulate_swap_operations_detailed,
+};
+use crate::state::{
+    dca_schedules, index_pool_assets, limit_orders, load_pool, pair_key, pool_key, ManagerConfig, CONFIG, CURVE_PARAMS,
+    FLASH_LOAN, LAST_REPLY_ID, LEGACY_QUEUED_MINT, LP_TOKEN_POOLS, PAIR_BALANCES, PAIR_POOLS, POOLS,
+    POOL_COUNT, QUEUED_MINTS,
+};
 
 /// Contract name that is used for migration.
 const CONTRACT_NAME: &str = "pina-colada";
 /// Contract version that is used for migration.
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
-pub const AFTER_SWAP_REPLY_ID: u64 = 1;
+/// LP token instantiation replies use ids starting from this value, one id per pool.
+/// Lower ids are reserved for other submessages.
+pub const INSTANTIATE_TOKEN_REPLY_ID_START: u64 = 1_000;
 
 /// Creates a new contract with the specified parameters in the [`InstantiateMsg`].
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -38,35 +53,85 @@ pub fn instantiate(
     deps: DepsMut,
     _env: Env,
     _info: MessageInfo,
-    _msg: InstantiateMsg,
+    msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
 
+    assert_flash_loan_fee(msg.flash_loan_fee_bps)?;
+    assert_max_referral_commission(msg.max_referral_commission)?;
+    let config = ManagerConfig {
+        owner: deps.api.addr_validate(&msg.owner)?,
+        token_code_id: msg.token_code_id,
+        allowed_denoms: msg.allowed_denoms,
+        fee_address: addr_opt_validate(deps.api, &msg.fee_address)?,
+        guardian: addr_opt_validate(deps.api, &msg.guardian)?,
+        flash_loan_fee_bps: msg.flash_loan_fee_bps,
+        max_referral_commission: msg.max_referral_commission,
+    };
+    CONFIG.save(deps.storage, &config)?;
 
-
-    Ok(Response::default())
+    Ok(Response::new()
+        .add_attribute("action", "instantiate")
+        .add_attribute("owner", config.owner))
 }
 
 /// Exposes all the execute functions available in the contract.
 ///
 /// ## Variants
 /// * **ExecuteMsg::Receive(msg)** Receives a message of type [`Cw20ReceiveMsg`] and processes
-/// it depending on the received template.
-///
-/// * **ExecuteMsg::ExecuteSwapOperations {
-///             operations,
-///             minimum_receive,
-///             to
-///         }** Performs swap operations with the specified parameters.
-///
-/// * **ExecuteMsg::ExecuteSwapOperation { operation, to }** Execute a single swap operation.
-///
-/// * **ExecuteMsg::AssertMinimumReceive {
-///             asset_info,
-///             prev_balance,
-///             minimum_receive,
-///             receiver
-///         }** Checks if an ask amount is higher than or equal to the minimum amount to receive.
+///   it depending on the received template.
+///
+/// * **ExecuteMsg::ExecuteSwapOperations { operations, minimum_receive, minimum_receive_per_hop, to, max_spread, belief_price, deadline, callback, referral_address, referral_commission }**
+///   Performs swap operations with the specified parameters.
+///
+/// * **ExecuteMsg::Swap { offer_asset, ask_asset_info, belief_price, max_spread, to, pool_id, callback, referral_address, referral_commission }**
+///   Performs a single swap in the pool holding the offer and ask assets.
+///
+/// * **ExecuteMsg::SwapBestRoute { ask_asset_info, max_hops, minimum_receive, to, max_spread }**
+///   Swaps the sent funds along the best route to the ask asset.
+///
+/// * **ExecuteMsg::ExecuteSplitSwap { routes, minimum_receive, to, max_spread }** Splits the
+///   sent funds across several routes by weight.
+///
+/// * **ExecuteMsg::SwapExactOut { operations, ask_amount, max_offer, to, max_spread }** Swaps
+///   the sent funds for an exact amount and refunds the rest.
+///
+/// * **ExecuteMsg::PlaceLimitOrder { ask_asset_info, limit_price, pool_id, expiry }** Escrows
+///   the sent funds in a limit order.
+///
+/// * **ExecuteMsg::CancelLimitOrder { order_id }** Cancels a limit order and refunds it.
+///
+/// * **ExecuteMsg::ExecuteLimitOrders { pool_key, order_ids }** Fills limit orders for a keeper tip.
+///
+/// * **ExecuteMsg::CreateDca { operations, tranches, interval, max_price, max_spread, keeper_tip_bps }**
+///   Escrows the sent funds and swaps them along a route in equal tranches.
+///
+/// * **ExecuteMsg::ExecuteDcaTranche { schedule_id }** Swaps the next due tranche for a keeper tip.
+///
+/// * **ExecuteMsg::CancelDca { schedule_id, to }** Cancels a DCA schedule and withdraws the rest.
+///
+/// * **ExecuteMsg::CreatePair { asset_infos, pair_type, token_code_id, lp_token, init_params }**
+///   Creates a new xyk, stableswap or concentrated pool managed by this contract.
+///
+/// * **ExecuteMsg::ProvideLiquidity { assets, slippage_tolerance, auto_stake, receiver, pool_id, deadline }**
+///   Provides liquidity to the pool derived from the given assets.
+///
+/// * **ExecuteMsg::ModifyPosition { assets, position_id, modification_type, slippage_tolerance, pool_id, deadline }**
+///   Increases, decreases or rebalances a position escrowed apart from the pool reserves.
+///
+/// * **ExecuteMsg::SetPoolStatus { pool_key, status }** Pauses, restricts or resumes a pool.
+///
+/// * **ExecuteMsg::UpdateConfig { pool_key, params }** Updates the parameters of a pool.
+///
+/// * **ExecuteMsg::UpdateManagerConfig { owner, token_code_id, allowed_denoms, fee_address, guardian, flash_loan_fee_bps, max_referral_commission }**
+///   Updates the manager configuration.
+///
+/// * **ExecuteMsg::FlashLoan { pool_key, assets, callback }** Lends pool reserves to a contract
+///   that repays them with a fee in the same transaction.
+///
+/// * **ExecuteMsg::CompleteFlashLoan {}** Checks that a flash loan was repaid.
+///
+/// Nothing but the repayment check can be executed while a flash loan is in progress.
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
     mut deps:DepsMut,
@@ -74,17 +139,32 @@ pub fn execute(
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
+    if !matches!(msg, ExecuteMsg::CompleteFlashLoan {}) && FLASH_LOAN.may_load(deps.storage)?.is_some() {
+        return Err(ContractError::FlashLoanInProgress {});
+    }
+
     match msg {
         ExecuteMsg::Receive(msg) => receive_cw20(&mut deps, env,info, msg),
         ExecuteMsg::ExecuteSwapOperations {
             operations,
             minimum_receive,
+            minimum_receive_per_hop,
             to,
             max_spread,
+            belief_price,
+            deadline,
+            callback,
+            referral_address,
+            referral_commission,
         } => {
-            assert_eq!(operations[0].clone().offer_asset_info,AssetInfo::NativeToken { denom: String::from(DENOM) });
-            let amount=must_pay(&info,DENOM).unwrap();
-            assert!(!amount.is_zero(),"Cannot Swap with Zero Input");
+            assert_deadline(&env, deadline)?;
+            // Native funds must match the denom offered by the first hop
+            let denom = match operations.first().map(|op| &op.offer_asset_info) {
+                Some(AssetInfo::NativeToken { denom }) => denom.clone(),
+                Some(AssetInfo::Token { .. }) => return Err(ContractError::NonNativeOffer {}),
+                None => return Err(ContractError::MustProvideOperations {}),
+            };
+            let amount = must_pay(&info, &denom)?;
             execute_swap_operations(
                 &mut deps,
                 env,
@@ -92,28 +172,244 @@ pub fn execute(
                 operations,
                 amount,
                 minimum_receive,
+                minimum_receive_per_hop,
                 to,
                 max_spread,
+                belief_price,
+                callback,
+                referral_address,
+                referral_commission,
             )
         },         
-        ExecuteMsg::CreatePair{asset_infos,token_code_id: _,init_params}=>execute_create_pair(&mut deps, env, info,init_params,asset_infos),
+        ExecuteMsg::Swap {
+            offer_asset,
+            ask_asset_info,
+            belief_price,
+            max_spread,
+            to,
+            pool_id,
+            callback,
+            referral_address,
+            referral_commission,
+        } => {
+            let denom = match &offer_asset.info {
+                AssetInfo::NativeToken { denom } => denom,
+                AssetInfo::Token { .. } => return Err(ContractError::NonNativeOffer {}),
+            };
+            must_pay(&info, denom)?;
+            offer_asset.assert_sent_native_token_balance(&info)?;
+            execute_swap(
+                &mut deps,
+                env,
+                info.sender,
+                offer_asset,
+                ask_asset_info,
+                pool_id,
+                belief_price,
+                max_spread,
+                to,
+                callback,
+                referral_address,
+                referral_commission,
+            )
+        }
+        ExecuteMsg::SwapBestRoute {
+            ask_asset_info,
+            max_hops,
+            minimum_receive,
+            to,
+            max_spread,
+        } => {
+            let coin = one_coin(&info)?;
+            execute_swap_best_route(
+                &mut deps,
+                env,
+                info.sender,
+                native_asset(coin.denom, coin.amount),
+                ask_asset_info,
+                max_hops,
+                minimum_receive,
+                to,
+                max_spread,
+            )
+        }
+        ExecuteMsg::ExecuteSplitSwap {
+            routes,
+            minimum_receive,
+            to,
+            max_spread,
+        } => {
+            let (offer_asset_info, _) = assert_split_routes(deps.api, &routes)?;
+            let denom = match offer_asset_info {
+                AssetInfo::NativeToken { denom } => denom,
+                AssetInfo::Token { .. } => return Err(ContractError::NonNativeOffer {}),
+            };
+            let amount = must_pay(&info, &denom)?;
+            execute_split_swap(
+                &mut deps,
+                env,
+                info.sender,
+                routes,
+                amount,
+                minimum_receive,
+                to,
+                max_spread,
+            )
+        }
+        ExecuteMsg::SwapExactOut {
+            operations,
+            ask_amount,
+            max_offer,
+            to,
+            max_spread,
+        } => {
+            let denom = match operations.first().map(|op| &op.offer_asset_info) {
+                Some(AssetInfo::NativeToken { denom }) => denom.clone(),
+                Some(AssetInfo::Token { .. }) => return Err(ContractError::NonNativeOffer {}),
+                None => return Err(ContractError::MustProvideOperations {}),
+            };
+            let amount = must_pay(&info, &denom)?;
+            execute_swap_exact_out(
+                &mut deps,
+                env,
+                info.sender,
+                operations,
+                amount,
+                ask_amount,
+                max_offer,
+                to,
+                max_spread,
+            )
+        }
+        ExecuteMsg::PlaceLimitOrder {
+            ask_asset_info,
+            limit_price,
+            pool_id,
+            expiry,
+        } => {
+            let coin = one_coin(&info)?;
+            execute_place_limit_order(
+                &mut deps,
+                env,
+                info.sender,
+                native_asset(coin.denom, coin.amount),
+                ask_asset_info,
+                limit_price,
+                pool_id,
+                expiry,
+            )
+        }
+        ExecuteMsg::CancelLimitOrder { order_id } => {
+            execute_cancel_limit_order(&mut deps, info, order_id)
+        }
+        ExecuteMsg::ExecuteLimitOrders { pool_key, order_ids } => {
+            execute_limit_orders(&mut deps, env, info, pool_key, order_ids)
+        }
+        ExecuteMsg::CreateDca {
+            operations,
+            tranches,
+            interval,
+            max_price,
+            max_spread,
+            keeper_tip_bps,
+        } => {
+            let denom = match operations.first().map(|op| &op.offer_asset_info) {
+                Some(AssetInfo::NativeToken { denom }) => denom.clone(),
+                Some(AssetInfo::Token { .. }) => return Err(ContractError::NonNativeOffer {}),
+                None => return Err(ContractError::MustProvideOperations {}),
+            };
+            let amount = must_pay(&info, &denom)?;
+            execute_create_dca(
+                &mut deps,
+                env,
+                info.sender,
+                operations,
+                amount,
+                tranches,
+                interval,
+                max_price,
+                max_spread,
+                keeper_tip_bps,
+            )
+        }
+        ExecuteMsg::ExecuteDcaTranche { schedule_id } => {
+            execute_dca_tranche(&mut deps, env, info, schedule_id)
+        }
+        ExecuteMsg::CancelDca { schedule_id, to } => {
+            execute_cancel_dca(&mut deps, info, schedule_id, to)
+        }
+        ExecuteMsg::CreatePair {
+            asset_infos,
+            pair_type,
+            token_code_id,
+            lp_token,
+            init_params,
+        } => execute_create_pair(
+            &mut deps,
+            env,
+            info,
+            init_params,
+            asset_infos,
+            pair_type,
+            token_code_id,
+            lp_token,
+        ),
         
-        ExecuteMsg::ProvideLiquidity{assets,slippage_tolerance,auto_stake,receiver}=>execute_provide_liquidity(&mut deps, env, info,assets,slippage_tolerance,auto_stake,receiver),
-       // ExecuteMsg::WithdrawLiquidity{assets,amount}=>execute_withdraw_liquidity(&mut deps,env,info.clone(),info.sender.clone(),amount,assets),
+        ExecuteMsg::ProvideLiquidity{assets,slippage_tolerance,auto_stake,receiver,pool_id,deadline}=>{
+            assert_deadline(&env, deadline)?;
+            execute_provide_liquidity(&mut deps, env, info,assets,slippage_tolerance,auto_stake,receiver,pool_id)
+        }
         ExecuteMsg::ModifyPosition {
             assets,
             position_id,
             modification_type,
             slippage_tolerance,
-        } => execute_modify_position(
+            pool_id,
+            deadline,
+        } => {
+            assert_deadline(&env, deadline)?;
+            execute_modify_position(
+                &mut deps,
+                env,
+                info,
+                assets,
+                position_id,
+                modification_type,
+                slippage_tolerance,
+                pool_id,
+            )
+        }
+        ExecuteMsg::SetPoolStatus { pool_key, status } => {
+            execute_set_pool_status(&mut deps, info, pool_key, status)
+        }
+        ExecuteMsg::UpdateConfig { pool_key, params } => {
+            execute_update_config(&mut deps, env, info, pool_key, params)
+        }
+        ExecuteMsg::UpdateManagerConfig {
+            owner,
+            token_code_id,
+            allowed_denoms,
+            fee_address,
+            guardian,
+            flash_loan_fee_bps,
+            max_referral_commission,
+        } => execute_update_manager_config(
             &mut deps,
-            env,
             info,
-            assets,
-            position_id,
-            modification_type,
-            slippage_tolerance,
+            owner,
+            token_code_id,
+            allowed_denoms,
+            fee_address,
+            guardian,
+            flash_loan_fee_bps,
+            max_referral_commission,
         ),
+        ExecuteMsg::FlashLoan {
+            pool_key,
+            assets,
+            callback,
+        } => execute_flash_loan(&mut deps, env, info, pool_key, assets, callback),
+        ExecuteMsg::CompleteFlashLoan {} => execute_complete_flash_loan(&mut deps, env, info),
     }  
 }
 
@@ -127,16 +423,22 @@ pub fn receive_cw20(
         Cw20HookMsg::ExecuteSwapOperations {
             operations,
             minimum_receive,
+            minimum_receive_per_hop,
             to,
             max_spread,
+            belief_price,
+            deadline,
+            callback,
+            referral_address,
+            referral_commission,
         } => {
-            //println!("{} is {}",info.sender.clone(),String::from("Test"));
-            
-            let pool_key=generate_key_from_asset_info([operations[0].clone().offer_asset_info,operations[0].clone().ask_asset_info].as_ref());
-            let config=POOLS.may_load(deps.storage, pool_key.clone()).unwrap();
+            assert_deadline(&env, deadline)?;
 
-            // Only asset contract can execute this message
-            check_cw20_in_pool(&config.unwrap(), &info.sender)?;
+            // Only the offered token can execute this message
+            let first = operations.first().ok_or(ContractError::MustProvideOperations {})?;
+            if first.offer_asset_info != token_asset_info(info.sender.clone()) {
+                return Err(ContractError::InvalidAsset(info.sender.to_string()));
+            }
 
             let _to_addr = addr_opt_validate(deps.api, &to)?;
             execute_swap_operations(
@@ -146,37 +448,184 @@ pub fn receive_cw20(
             operations,
             cw20_msg.amount,
             minimum_receive,
+            minimum_receive_per_hop,
             to,
             max_spread,
+            belief_price,
+            callback,
+            referral_address,
+            referral_commission,
             )
         },
         
-        Cw20HookMsg::WithdrawLiquidity { assets } => execute_withdraw_liquidity(deps,env,info.clone(),info.sender.clone(),cw20_msg.amount,assets)
+        Cw20HookMsg::Swap {
+            ask_asset_info,
+            belief_price,
+            max_spread,
+            to,
+            pool_id,
+            callback,
+            referral_address,
+            referral_commission,
+        } => execute_swap(
+            deps,
+            env,
+            Addr::unchecked(cw20_msg.sender),
+            token_asset(info.sender, cw20_msg.amount),
+            ask_asset_info,
+            pool_id,
+            belief_price,
+            max_spread,
+            to,
+            callback,
+            referral_address,
+            referral_commission,
+        ),
+        Cw20HookMsg::SwapBestRoute {
+            ask_asset_info,
+            max_hops,
+            minimum_receive,
+            to,
+            max_spread,
+        } => execute_swap_best_route(
+            deps,
+            env,
+            Addr::unchecked(cw20_msg.sender),
+            token_asset(info.sender, cw20_msg.amount),
+            ask_asset_info,
+            max_hops,
+            minimum_receive,
+            to,
+            max_spread,
+        ),
+        Cw20HookMsg::ExecuteSplitSwap {
+            routes,
+            minimum_receive,
+            to,
+            max_spread,
+        } => {
+            // Only the offered token can execute this message
+            let (offer_asset_info, _) = assert_split_routes(deps.api, &routes)?;
+            if offer_asset_info != token_asset_info(info.sender.clone()) {
+                return Err(ContractError::InvalidAsset(info.sender.to_string()));
+            }
+            execute_split_swap(
+                deps,
+                env,
+                Addr::unchecked(cw20_msg.sender),
+                routes,
+                cw20_msg.amount,
+                minimum_receive,
+                to,
+                max_spread,
+            )
+        }
+        Cw20HookMsg::SwapExactOut {
+            operations,
+            ask_amount,
+            max_offer,
+            to,
+            max_spread,
+        } => {
+            // Only the offered token can execute this message
+            let first = operations.first().ok_or(ContractError::MustProvideOperations {})?;
+            if first.offer_asset_info != token_asset_info(info.sender.clone()) {
+                return Err(ContractError::InvalidAsset(info.sender.to_string()));
+            }
+            execute_swap_exact_out(
+                deps,
+                env,
+                Addr::unchecked(cw20_msg.sender),
+                operations,
+                cw20_msg.amount,
+                ask_amount,
+                max_offer,
+                to,
+                max_spread,
+            )
+        }
+        Cw20HookMsg::PlaceLimitOrder {
+            ask_asset_info,
+            limit_price,
+            pool_id,
+            expiry,
+        } => execute_place_limit_order(
+            deps,
+            env,
+            Addr::unchecked(cw20_msg.sender),
+            token_asset(info.sender, cw20_msg.amount),
+            ask_asset_info,
+            limit_price,
+            pool_id,
+            expiry,
+        ),
+        Cw20HookMsg::CreateDca {
+            operations,
+            tranches,
+            interval,
+            max_price,
+            max_spread,
+            keeper_tip_bps,
+        } => {
+            // Only the offered token can execute this message
+            let first = operations.first().ok_or(ContractError::MustProvideOperations {})?;
+            if first.offer_asset_info != token_asset_info(info.sender.clone()) {
+                return Err(ContractError::InvalidAsset(info.sender.to_string()));
+            }
+            execute_create_dca(
+                deps,
+                env,
+                Addr::unchecked(cw20_msg.sender),
+                operations,
+                cw20_msg.amount,
+                tranches,
+                interval,
+                max_price,
+                max_spread,
+                keeper_tip_bps,
+            )
+        }
+        Cw20HookMsg::WithdrawLiquidity { assets, deadline } => {
+            assert_deadline(&env, deadline)?;
+            execute_withdraw_liquidity(
+                deps,
+                env,
+                info,
+                Addr::unchecked(cw20_msg.sender),
+                cw20_msg.amount,
+                assets,
+            )
+        }
     }
 }
 
+/// Handles LP token instantiation replies. Every pending instantiation is stored in
+/// [`QUEUED_MINTS`] under its own reply id, so each reply updates the right pool.
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn reply(deps: DepsMut, _env: Env, msg: Reply) -> Result<Response, ContractError> {
     match msg {
         Reply {
-            id: _instantiate_token_reply_id,
+            id,
             result:
                 SubMsgResult::Ok(SubMsgResponse {
                     data: Some(data), ..
                 }),
-        } => {
-            let pool_key= QUEUED_MINT.load(deps.storage).unwrap();
-            let config=POOLS.may_load(deps.storage, pool_key.clone()).unwrap();
+        } if id >= INSTANTIATE_TOKEN_REPLY_ID_START => {
+            let pool_key = QUEUED_MINTS
+                .may_load(deps.storage, id)?
+                .ok_or(ContractError::FailedToParseReply {})?;
+            let config=POOLS.may_load(deps.storage, pool_key.clone())?;
             let init_response = parse_instantiate_response_data(data.as_slice())
             .map_err(|e| StdError::generic_err(format!("{e}")))?;
             if let Some(mut config)=config{
                 config.pair_info.liquidity_token =
                 deps.api.addr_validate(&init_response.contract_address)?;
-                POOLS.save(deps.storage,pool_key ,&config)?;
-                QUEUED_MINT.remove(deps.storage);
+                POOLS.save(deps.storage,pool_key.clone() ,&config)?;
+                LP_TOKEN_POOLS.save(deps.storage, &config.pair_info.liquidity_token, &pool_key)?;
+                QUEUED_MINTS.remove(deps.storage, id);
                 Ok(Response::new()
+                .add_attribute("pool_key", pool_key)
                 .add_attribute("liquidity_token_addr", config.pair_info.liquidity_token))
-               //return  Err(ContractError::FailedToParseReply {})
             }else{
                 Err(ContractError::FailedToParseReply {})
             }
@@ -189,11 +638,40 @@ pub fn reply(deps: DepsMut, _env: Env, msg: Reply) -> Result<Response, ContractE
 
 /// Exposes all the queries available in the contract.
 /// ## Queries
-/// * **QueryMsg::Config {}** Returns general router parameters using a [`ConfigResponse`] object.
-/// * **QueryMsg::SimulateSwapOperations {
-///             offer_amount,
-///             operations,
-///         }** Simulates one or multiple swap operations and returns the end result in a [`SimulateSwapOperationsResponse`] object.
+/// * **QueryMsg::Config { pool_key }** Returns pool parameters using a [`ConfigResponse`] object.
+///
+/// * **QueryMsg::ManagerConfig {}** Returns the global manager configuration.
+///
+/// * **QueryMsg::ConfigByAssets / PoolByAssets / PairByAssets { asset_infos, pool_id }** Same
+///   as the `pool_key` variants, with the key derived from the pool assets and id.
+///
+/// * **QueryMsg::Pools { start_after, limit }** Returns pools in key order.
+///
+/// * **QueryMsg::PoolsByAsset { asset_info, start_after, limit }** Returns the pools that
+///   contain an asset in key order.
+///
+/// * **QueryMsg::PoolStatus { pool_key }** Returns the status of a pool.
+///
+/// * **QueryMsg::FindRoutes { offer_asset_info, ask_asset_info, offer_amount, max_hops }**
+///   Returns the best swap routes between two assets.
+///
+/// * **QueryMsg::SuggestSplit { offer_asset_info, ask_asset_info, offer_amount, max_hops }**
+///   Suggests how to split a swap across routes.
+///
+/// * **QueryMsg::SimulateSwapPools { offer_asset, ask_asset_info }** Simulates a swap in every
+///   pool of a pair.
+///
+/// * **QueryMsg::SimulateSwapOperations { offer_amount, operations, referral_commission }** Simulates
+///   one or multiple swap operations and returns the end result in a [`SimulateSwapOperationsResponse`] object.
+///
+/// * **QueryMsg::SimulateSwapOperationsDetailed { offer_amount, operations, referral_commission }** Simulates swap
+///   operations and returns the amounts, mid prices and price impact of every hop.
+///
+/// * **QueryMsg::ReverseSimulation { pool_key, ask_asset }** Returns the offer needed to
+///   receive an exact amount from a pool.
+///
+/// * **QueryMsg::SimulateReverseSwapOperations { ask_amount, operations }** Returns the offer
+///   needed to receive an exact amount from a route, per hop and in total.
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> Result<Binary, ContractError> {
     match msg {
@@ -201,21 +679,167 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> Result<Binary, ContractErro
         QueryMsg::SimulateSwapOperations {
             offer_amount,
             operations,
+            referral_commission,
         } => Ok(to_json_binary(&simulate_swap_operations(
             deps,
             env,
             offer_amount,
             operations,
+            referral_commission,
+        )?)?),
+        QueryMsg::SimulateSwapOperationsDetailed {
+            offer_amount,
+            operations,
+            referral_commission,
+        } => Ok(to_json_binary(&simulate_swap_operations_detailed(
+            deps,
+            env,
+            offer_amount,
+            operations,
+            referral_commission,
+        )?)?),
+        QueryMsg::ReverseSimulation { pool_key, ask_asset } => Ok(to_json_binary(
+            &query_reverse_simulation(deps, env, ask_asset, pool_key)?,
+        )?),
+        QueryMsg::SimulateReverseSwapOperations {
+            ask_amount,
+            operations,
+        } => Ok(to_json_binary(&simulate_reverse_swap_operations(
+            deps,
+            env,
+            ask_amount,
+            operations,
         )?)?),
         QueryMsg::Pool {pool_key} => Ok(to_json_binary(&query_pool(deps,pool_key)?)?),
-        QueryMsg::Pair {pool_key} => Ok(to_json_binary(&POOLS.load(deps.storage,pool_key)?.pair_info)?),
+        QueryMsg::Pair {pool_key} => Ok(to_json_binary(&load_pool(deps.storage, &pool_key)?.pair_info)?),
         QueryMsg::ComputeD { pool_key }=>Ok(to_json_binary(&query_compute_d(deps,env,pool_key)?)?),
         QueryMsg::Config {pool_key  }=> Ok(to_json_binary(&query_config(deps,env,pool_key)?)?),
         QueryMsg::LpPrice {pool_key  }=>Ok(to_json_binary(&query_lp_price(deps,env,pool_key)?)?),
+        QueryMsg::ManagerConfig {} => Ok(to_json_binary(&CONFIG.load(deps.storage)?)?),
+        QueryMsg::ConfigByAssets {
+            asset_infos,
+            pool_id,
+        } => {
+            let pool_key = resolve_pool_key(deps.storage, &asset_infos, pool_id)?;
+            Ok(to_json_binary(&query_config(deps, env, pool_key)?)?)
+        }
+        QueryMsg::PoolByAssets {
+            asset_infos,
+            pool_id,
+        } => {
+            let pool_key = resolve_pool_key(deps.storage, &asset_infos, pool_id)?;
+            Ok(to_json_binary(&query_pool(deps, pool_key)?)?)
+        }
+        QueryMsg::PairByAssets {
+            asset_infos,
+            pool_id,
+        } => {
+            let pool_key = resolve_pool_key(deps.storage, &asset_infos, pool_id)?;
+            Ok(to_json_binary(&POOLS.load(deps.storage, pool_key)?.pair_info)?)
+        }
+        QueryMsg::Pools { start_after, limit } => {
+            Ok(to_json_binary(&query_pools_paginated(deps, start_after, limit)?)?)
+        }
+        QueryMsg::PoolsByAsset {
+            asset_info,
+            start_after,
+            limit,
+        } => Ok(to_json_binary(&query_pools_by_asset(
+            deps,
+            asset_info,
+            start_after,
+            limit,
+        )?)?),
+        QueryMsg::PoolStatus { pool_key } => {
+            if !POOLS.has(deps.storage, pool_key.clone()) {
+                return Err(ContractError::PoolNotFound(pool_key));
+            }
+            Ok(to_json_binary(&pool_status(deps.storage, &pool_key)?)?)
+        }
+        QueryMsg::FindRoutes {
+            offer_asset_info,
+            ask_asset_info,
+            offer_amount,
+            max_hops,
+        } => Ok(to_json_binary(&RoutesResponse {
+            routes: find_routes(
+                deps,
+                env,
+                offer_asset_info,
+                ask_asset_info,
+                offer_amount,
+                max_hops,
+            )?,
+        })?),
+        QueryMsg::SuggestSplit {
+            offer_asset_info,
+            ask_asset_info,
+            offer_amount,
+            max_hops,
+        } => Ok(to_json_binary(&query_suggest_split(
+            deps,
+            env,
+            offer_asset_info,
+            ask_asset_info,
+            offer_amount,
+            max_hops,
+        )?)?),
+        QueryMsg::SimulateSwapPools {
+            offer_asset,
+            ask_asset_info,
+        } => Ok(to_json_binary(&query_simulate_swap_pools(
+            deps,
+            env,
+            offer_asset,
+            ask_asset_info,
+        )?)?),
+        QueryMsg::LimitOrder { order_id } => Ok(to_json_binary(
+            &limit_orders()
+                .may_load(deps.storage, order_id)?
+                .ok_or(ContractError::LimitOrderNotFound(order_id))?,
+        )?),
+        QueryMsg::LimitOrdersByOwner {
+            owner,
+            start_after,
+            limit,
+        } => Ok(to_json_binary(&query_limit_orders_by_owner(
+            deps,
+            owner,
+            start_after,
+            limit,
+        )?)?),
+        QueryMsg::LimitOrdersByPool {
+            pool_key,
+            start_after,
+            limit,
+        } => Ok(to_json_binary(&query_limit_orders_by_pool(
+            deps,
+            pool_key,
+            start_after,
+            limit,
+        )?)?),
+        QueryMsg::DcaSchedule { schedule_id } => Ok(to_json_binary(
+            &dca_schedules()
+                .may_load(deps.storage, schedule_id)?
+                .ok_or(ContractError::DcaScheduleNotFound(schedule_id))?,
+        )?),
+        QueryMsg::DcaSchedulesByOwner {
+            owner,
+            start_after,
+            limit,
+        } => Ok(to_json_binary(&query_dca_schedules_by_owner(
+            deps,
+            owner,
+            start_after,
+            limit,
+        )?)?),
+        QueryMsg::DcaSchedules { start_after, limit } => {
+            Ok(to_json_binary(&query_dca_schedules(deps, start_after, limit)?)?)
+        }
 }
 }
-fn query_pool(deps: Deps,pool_key:String)->StdResult<PoolResponse>{
-    let config= POOLS.load(deps.storage,pool_key.clone())?;
+fn query_pool(deps: Deps, pool_key: String) -> Result<PoolResponse, ContractError> {
+    let config = load_pool(deps.storage, &pool_key)?;
     let assets= PAIR_BALANCES.load(deps.storage,pool_key.clone())?;
     let total_share = query_supply(&deps.querier, config.pair_info.liquidity_token)?;
     let resp = PoolResponse {
@@ -231,12 +855,15 @@ fn query_pool(deps: Deps,pool_key:String)->StdResult<PoolResponse>{
 /// Manages contract migration.
 #[cfg(not(tarpaulin_include))]
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+pub fn migrate(mut deps: DepsMut, _env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
     let contract_version = get_contract_version(deps.storage)?;
 
     match contract_version.contract.as_ref() {
-        "router" => match contract_version.version.as_ref() {
-            "1.1.1" => {}
+        CONTRACT_NAME => match contract_version.version.as_ref() {
+            "0.1.0" | "0.2.0" => {
+                migrate_manager_config(deps.branch(), msg.config)?;
+                migrate_pool_keys(deps.storage)?;
+            }
             _ => return Err(ContractError::MigrationError {}),
         },
         _ => return Err(ContractError::MigrationError {}),
@@ -251,3 +878,92 @@ pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, C
         .add_attribute("new_contract_version", CONTRACT_VERSION))
 }
 
+/// Stores `config` as the manager configuration if the contract has none yet, as in 0.1.0.
+fn migrate_manager_config(
+    deps: DepsMut,
+    config: Option<ManagerConfig>,
+) -> Result<(), ContractError> {
+    if CONFIG.may_load(deps.storage)?.is_some() {
+        return Ok(());
+    }
+    let config = config.ok_or(ContractError::MissingManagerConfig {})?;
+
+    assert_flash_loan_fee(config.flash_loan_fee_bps)?;
+    assert_max_referral_commission(config.max_referral_commission)?;
+    let config = ManagerConfig {
+        owner: deps.api.addr_validate(config.owner.as_str())?,
+        fee_address: config
+            .fee_address
+            .map(|addr| deps.api.addr_validate(addr.as_str()))
+            .transpose()?,
+        guardian: config
+            .guardian
+            .map(|addr| deps.api.addr_validate(addr.as_str()))
+            .transpose()?,
+        ..config
+    };
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(())
+}
+
+/// Moves every pool stored under a 0.1.0 or 0.2.0 key, which identified a pool by its assets
+/// alone, to a key built by [`pool_key`] with a freshly assigned pool id, and indexes it.
+/// Positions are keyed by position id and don't reference pool keys, so they are left as is.
+pub fn migrate_pool_keys(storage: &mut dyn Storage) -> StdResult<()> {
+    // 0.1.0 kept a single pending LP token instantiation, give it a reply id of its own
+    if let Some(old_key) = LEGACY_QUEUED_MINT.may_load(storage)? {
+        let reply_id = LAST_REPLY_ID
+            .may_load(storage)?
+            .map_or(INSTANTIATE_TOKEN_REPLY_ID_START, |id| id + 1);
+        LAST_REPLY_ID.save(storage, &reply_id)?;
+        QUEUED_MINTS.save(storage, reply_id, &old_key)?;
+        LEGACY_QUEUED_MINT.remove(storage);
+    }
+
+    let pools = POOLS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    let mut new_keys = HashMap::new();
+    let mut pair_pools: HashMap<String, Vec<u64>> = HashMap::new();
+    for (pool_id, (old_key, config)) in (1..).zip(pools) {
+        let new_key = pool_key(&config.pair_info.asset_infos, pool_id);
+        pair_pools
+            .entry(pair_key(&config.pair_info.asset_infos))
+            .or_default()
+            .push(pool_id);
+        POOL_COUNT.save(storage, &pool_id)?;
+
+        POOLS.remove(storage, old_key.clone());
+        POOLS.save(storage, new_key.clone(), &config)?;
+        index_pool_assets(storage, &config.pair_info.asset_infos, &new_key, pool_id)?;
+        if let Some(balances) = PAIR_BALANCES.may_load(storage, old_key.clone())? {
+            PAIR_BALANCES.remove(storage, old_key.clone());
+            PAIR_BALANCES.save(storage, new_key.clone(), &balances)?;
+        }
+        if let Some(params) = CURVE_PARAMS.may_load(storage, old_key.clone())? {
+            CURVE_PARAMS.remove(storage, old_key.clone());
+            CURVE_PARAMS.save(storage, new_key.clone(), &params)?;
+        }
+        if config.pair_info.liquidity_token.as_str() != "" {
+            LP_TOKEN_POOLS.save(storage, &config.pair_info.liquidity_token, &new_key)?;
+        }
+        new_keys.insert(old_key, new_key);
+    }
+    for (pair, pool_ids) in pair_pools {
+        PAIR_POOLS.save(storage, pair, &pool_ids)?;
+    }
+
+    // LP token instantiations still waiting for a reply point at the old keys
+    let queued = QUEUED_MINTS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (id, old_key) in queued {
+        if let Some(new_key) = new_keys.get(&old_key) {
+            QUEUED_MINTS.save(storage, id, new_key)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,635 @@
+use astroport::asset::MINIMUM_LIQUIDITY_AMOUNT;
+use astroport::cosmwasm_ext::{AbsDiff, IntegerToDecimal};
+use astroport::factory::PairType;
+use astroport::pair::MIN_TRADE_SIZE;
+use astroport_pcl_common::state::Config;
+use astroport_pcl_common::utils::{
+    assert_slippage_tolerance, calc_provide_fee, compute_offer_amount, compute_swap,
+};
+use astroport_pcl_common::{calc_d, get_xcp};
+use cosmwasm_std::{Decimal, Decimal256, Env, StdError, Storage};
+use itertools::Itertools;
+
+use crate::error::ContractError;
+use crate::handlers::LP_TOKEN_PRECISION;
+use crate::state::{CurveParams, CURVE_PARAMS};
+
+/// Pair type name used for concentrated liquidity pools.
+pub const CONCENTRATED_PAIR_TYPE: &str = "concentrated";
+/// Maximum swap fee of xyk and stableswap pools in basis points.
+pub const MAX_FEE_BPS: u16 = 10_000;
+/// Maximum amplification coefficient of stableswap pools.
+pub const MAX_AMP: u64 = 1_000_000;
+/// Default slippage tolerance of liquidity provided to xyk pools, and default max spread
+/// of swaps that give a belief price without a max spread.
+pub(crate) const DEFAULT_SLIPPAGE: Decimal256 = Decimal256::raw(5_000_000_000_000_000);
+/// Maximum slippage tolerance accepted by xyk pools.
+pub(crate) const MAX_ALLOWED_SLIPPAGE: Decimal256 = Decimal256::raw(500_000_000_000_000_000);
+/// Number of coins in a pool.
+const N_COINS: Decimal256 = Decimal256::raw(2_000_000_000_000_000_000);
+/// Maximum number of Newton iterations in stableswap math.
+const MAX_ITER: usize = 64;
+/// Convergence tolerance of stableswap math.
+const TOL: Decimal256 = Decimal256::raw(1_000_000);
+
+/// The result of a swap computed by a pool curve.
+/// All amounts use the decimal representation of the assets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SwapOutcome {
+    /// The amount of ask asset sent to the trader
+    pub return_amount: Decimal256,
+    /// The difference between the ideal and the actual output
+    pub spread_amount: Decimal256,
+    /// The fee which stays in the pool
+    pub commission_amount: Decimal256,
+}
+
+/// Swap and liquidity math of a pool type. Pool balances are passed as decimals
+/// in the order of the pool's asset infos.
+pub trait PoolCurve {
+    /// Calculates the outcome of swapping `offer_amount` of the asset at `offer_ind`.
+    /// `xs` are the pool balances before the swap.
+    fn compute_swap(
+        &self,
+        config: &Config,
+        env: &Env,
+        xs: &[Decimal256],
+        offer_ind: usize,
+        offer_amount: Decimal256,
+    ) -> Result<SwapOutcome, ContractError>;
+
+    /// Calculates the offer needed to receive `ask_amount` of the asset at `ask_ind`
+    /// after fees. `xs` are the pool balances before the swap.
+    fn compute_offer(
+        &self,
+        config: &Config,
+        env: &Env,
+        xs: &[Decimal256],
+        ask_ind: usize,
+        ask_amount: Decimal256,
+    ) -> Result<ReverseSwapOutcome, ContractError>;
+
+    /// Updates the pool state after a swap. `xs` are the pool balances after the swap.
+    #[allow(clippy::too_many_arguments)]
+    fn after_swap(
+        &self,
+        _config: &mut Config,
+        _env: &Env,
+        _xs: &[Decimal256],
+        _offer_ind: usize,
+        _offer_amount: Decimal256,
+        _outcome: &SwapOutcome,
+        _total_share: Decimal256,
+    ) -> Result<(), ContractError> {
+        Ok(())
+    }
+
+    /// Calculates the LP shares minted for `deposits` and the slippage of the provision.
+    /// On the first provision the returned share excludes [`MINIMUM_LIQUIDITY_AMOUNT`],
+    /// which is minted to the pool manager itself.
+    fn compute_share(
+        &self,
+        config: &mut Config,
+        env: &Env,
+        xs: &[Decimal256],
+        deposits: &[Decimal256; 2],
+        total_share: Decimal256,
+        slippage_tolerance: Option<Decimal>,
+    ) -> Result<(Decimal256, Decimal256), ContractError>;
+
+    /// Updates the pool state after a withdrawal. `xs` are the pool balances after the withdrawal.
+    fn after_withdraw(
+        &self,
+        _config: &mut Config,
+        _env: &Env,
+        _xs: &[Decimal256],
+        _total_share: Decimal256,
+    ) -> Result<(), ContractError> {
+        Ok(())
+    }
+}
+
+/// The result of a reverse swap computed by a pool curve.
+/// All amounts use the decimal representation of the assets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReverseSwapOutcome {
+    /// The amount of offer asset needed
+    pub offer_amount: Decimal256,
+    /// The difference between the ideal and the actual output
+    pub spread_amount: Decimal256,
+    /// The fee which stays in the pool
+    pub commission_amount: Decimal256,
+}
+
+/// Returns the amount needed before an output fee of `fee` to end up with `amount`,
+/// or an error if the pool does not hold that much.
+fn before_fee(
+    amount: Decimal256,
+    fee: Decimal256,
+    ask_pool: Decimal256,
+) -> Result<Decimal256, ContractError> {
+    let after_fee = Decimal256::one() - fee;
+    if after_fee.is_zero() {
+        return Err(ContractError::InsufficientLiquidity {});
+    }
+    let before_fee = amount / after_fee;
+    if before_fee >= ask_pool {
+        return Err(ContractError::InsufficientLiquidity {});
+    }
+    Ok(before_fee)
+}
+
+/// Returns the curve of the pool stored under `pool_key`.
+pub fn pool_curve(
+    storage: &dyn Storage,
+    pool_key: &str,
+    config: &Config,
+) -> Result<Box<dyn PoolCurve>, ContractError> {
+    match &config.pair_info.pair_type {
+        PairType::Xyk {} => Ok(Box::new(XykCurve::new(
+            &CURVE_PARAMS.load(storage, pool_key.to_string())?,
+        ))),
+        PairType::Stable {} => Ok(Box::new(StableCurve::new(
+            &CURVE_PARAMS.load(storage, pool_key.to_string())?,
+        )?)),
+        PairType::Custom(name) if name == CONCENTRATED_PAIR_TYPE => Ok(Box::new(ConcentratedCurve)),
+        pair_type => Err(ContractError::UnsupportedPoolType(pair_type.to_string())),
+    }
+}
+
+/// Returns an error unless the pool is a concentrated liquidity pool.
+pub fn assert_concentrated(config: &Config) -> Result<(), ContractError> {
+    match &config.pair_info.pair_type {
+        PairType::Custom(name) if name == CONCENTRATED_PAIR_TYPE => Ok(()),
+        pair_type => Err(ContractError::UnsupportedPoolType(pair_type.to_string())),
+    }
+}
+
+/// Returns the initial share minus the minimum liquidity locked in the pool.
+fn initial_share(share: Decimal256) -> Result<Decimal256, ContractError> {
+    let share = share
+        .checked_sub(MINIMUM_LIQUIDITY_AMOUNT.to_decimal256(LP_TOKEN_PRECISION)?)
+        .map_err(|_| ContractError::MinimumLiquidityAmountError {})?;
+
+    // share cannot become zero after minimum liquidity subtraction
+    if share.is_zero() {
+        return Err(ContractError::MinimumLiquidityAmountError {});
+    }
+
+    Ok(share)
+}
+
+/// Converts a fee in basis points to a decimal.
+fn fee_from_bps(fee_bps: u16) -> Decimal256 {
+    Decimal256::from_ratio(fee_bps, MAX_FEE_BPS)
+}
+
+/// Concentrated liquidity (PCL) pool math from `astroport_pcl_common`.
+pub struct ConcentratedCurve;
+
+impl PoolCurve for ConcentratedCurve {
+    fn compute_swap(
+        &self,
+        config: &Config,
+        env: &Env,
+        xs: &[Decimal256],
+        offer_ind: usize,
+        offer_amount: Decimal256,
+    ) -> Result<SwapOutcome, ContractError> {
+        let swap_result = compute_swap(
+            xs,
+            offer_amount,
+            1 ^ offer_ind,
+            config,
+            env,
+            Decimal256::zero(),
+            Decimal256::zero(),
+        )?;
+
+        Ok(SwapOutcome {
+            return_amount: swap_result.dy,
+            spread_amount: swap_result.spread_fee,
+            commission_amount: swap_result.total_fee,
+        })
+    }
+
+    fn compute_offer(
+        &self,
+        config: &Config,
+        env: &Env,
+        xs: &[Decimal256],
+        ask_ind: usize,
+        ask_amount: Decimal256,
+    ) -> Result<ReverseSwapOutcome, ContractError> {
+        // compute_offer_amount charges the maximum fee and works on price scaled balances
+        let mut scaled_ask = ask_amount;
+        let mut scaled_pool = xs[ask_ind];
+        if ask_ind == 1 {
+            scaled_ask *= config.pool_state.price_state.price_scale;
+            scaled_pool *= config.pool_state.price_state.price_scale;
+        }
+        before_fee(scaled_ask, config.pool_params.out_fee.into(), scaled_pool)?;
+
+        let (offer_amount, spread_amount, commission_amount) =
+            compute_offer_amount(xs, ask_amount, ask_ind, config, env)?;
+
+        Ok(ReverseSwapOutcome {
+            offer_amount,
+            spread_amount,
+            commission_amount,
+        })
+    }
+
+    fn after_swap(
+        &self,
+        config: &mut Config,
+        env: &Env,
+        xs: &[Decimal256],
+        offer_ind: usize,
+        offer_amount: Decimal256,
+        outcome: &SwapOutcome,
+        total_share: Decimal256,
+    ) -> Result<(), ContractError> {
+        // Skip very small trade sizes which could significantly mess up the price due to rounding errors,
+        // especially if token precisions are 18.
+        if outcome.return_amount >= MIN_TRADE_SIZE && offer_amount >= MIN_TRADE_SIZE {
+            let last_price = if offer_ind == 0 {
+                offer_amount / outcome.return_amount
+            } else {
+                outcome.return_amount / offer_amount
+            };
+
+            // update_price() works only with internal representation
+            let mut xs = xs.to_vec();
+            xs[1] *= config.pool_state.price_state.price_scale;
+            config
+                .pool_state
+                .update_price(&config.pool_params, env, total_share, &xs, last_price)?;
+        }
+
+        Ok(())
+    }
+
+    fn compute_share(
+        &self,
+        config: &mut Config,
+        env: &Env,
+        xs: &[Decimal256],
+        deposits: &[Decimal256; 2],
+        total_share: Decimal256,
+        slippage_tolerance: Option<Decimal>,
+    ) -> Result<(Decimal256, Decimal256), ContractError> {
+        let mut new_xp = xs
+            .iter()
+            .enumerate()
+            .map(|(ind, amount)| *amount + deposits[ind])
+            .collect_vec();
+        new_xp[1] *= config.pool_state.price_state.price_scale;
+        let amp_gamma = config.pool_state.get_amp_gamma(env);
+        let new_d = calc_d(&new_xp, &amp_gamma)?;
+
+        let share = if total_share.is_zero() {
+            let xcp = get_xcp(new_d, config.pool_state.price_state.price_scale);
+            let mint_amount = initial_share(xcp)?;
+
+            config.pool_state.price_state.xcp_profit_real = Decimal256::one();
+            config.pool_state.price_state.xcp_profit = Decimal256::one();
+
+            mint_amount
+        } else {
+            let mut old_xp = xs.to_vec();
+            old_xp[1] *= config.pool_state.price_state.price_scale;
+            let old_d = calc_d(&old_xp, &amp_gamma)?;
+            let share = (total_share * new_d / old_d).saturating_sub(total_share);
+
+            let mut ideposits = *deposits;
+            ideposits[1] *= config.pool_state.price_state.price_scale;
+
+            share * (Decimal256::one() - calc_provide_fee(&ideposits, &new_xp, &config.pool_params))
+        };
+
+        // calculate accrued share
+        let share_ratio = share / (total_share + share);
+        let balanced_share = [
+            new_xp[0] * share_ratio,
+            new_xp[1] * share_ratio / config.pool_state.price_state.price_scale,
+        ];
+
+        let assets_diff = [
+            deposits[0].diff(balanced_share[0]),
+            deposits[1].diff(balanced_share[1]),
+        ];
+
+        let mut slippage = Decimal256::zero();
+
+        // If deposit doesn't diverge too much from the balanced share, we don't update the price
+        if assets_diff[0] >= MIN_TRADE_SIZE && assets_diff[1] >= MIN_TRADE_SIZE {
+            slippage = assert_slippage_tolerance(
+                deposits,
+                share,
+                &config.pool_state.price_state,
+                slippage_tolerance,
+            )?;
+
+            let last_price = assets_diff[0] / assets_diff[1];
+            config.pool_state.update_price(
+                &config.pool_params,
+                env,
+                total_share + share,
+                &new_xp,
+                last_price,
+            )?;
+        }
+
+        Ok((share, slippage))
+    }
+
+    fn after_withdraw(
+        &self,
+        config: &mut Config,
+        env: &Env,
+        xs: &[Decimal256],
+        total_share: Decimal256,
+    ) -> Result<(), ContractError> {
+        // decrease XCP
+        let mut xs = xs.to_vec();
+        xs[1] *= config.pool_state.price_state.price_scale;
+        let amp_gamma = config.pool_state.get_amp_gamma(env);
+        let d = calc_d(&xs, &amp_gamma)?;
+        config.pool_state.price_state.xcp_profit_real =
+            get_xcp(d, config.pool_state.price_state.price_scale) / total_share;
+
+        Ok(())
+    }
+}
+
+/// Constant product (x * y = k) pool math.
+pub struct XykCurve {
+    /// The swap fee charged on the output
+    pub fee: Decimal256,
+}
+
+impl XykCurve {
+    pub fn new(params: &CurveParams) -> Self {
+        Self {
+            fee: fee_from_bps(params.fee_bps),
+        }
+    }
+}
+
+impl PoolCurve for XykCurve {
+    fn compute_swap(
+        &self,
+        _config: &Config,
+        _env: &Env,
+        xs: &[Decimal256],
+        offer_ind: usize,
+        offer_amount: Decimal256,
+    ) -> Result<SwapOutcome, ContractError> {
+        let (offer_pool, ask_pool) = (xs[offer_ind], xs[1 ^ offer_ind]);
+
+        // ask_pool - (offer_pool * ask_pool) / (offer_pool + offer_amount)
+        let cp = offer_pool.checked_mul(ask_pool)?;
+        let new_ask_pool = cp
+            .checked_div(offer_pool.checked_add(offer_amount)?)
+            .map_err(|e| StdError::generic_err(e.to_string()))?;
+        let return_amount = ask_pool.saturating_sub(new_ask_pool);
+
+        // The spread is the difference between the output at the current price and the real output
+        let spread_amount = (offer_amount * ask_pool / offer_pool).saturating_sub(return_amount);
+        let commission_amount = return_amount * self.fee;
+
+        Ok(SwapOutcome {
+            return_amount: return_amount - commission_amount,
+            spread_amount,
+            commission_amount,
+        })
+    }
+
+    fn compute_offer(
+        &self,
+        _config: &Config,
+        _env: &Env,
+        xs: &[Decimal256],
+        ask_ind: usize,
+        ask_amount: Decimal256,
+    ) -> Result<ReverseSwapOutcome, ContractError> {
+        let (offer_pool, ask_pool) = (xs[1 ^ ask_ind], xs[ask_ind]);
+        let before_commission = before_fee(ask_amount, self.fee, ask_pool)?;
+
+        // (offer_pool * ask_pool) / (ask_pool - before_commission) - offer_pool
+        let cp = offer_pool.checked_mul(ask_pool)?;
+        let offer_amount = cp
+            .checked_div(ask_pool - before_commission)
+            .map_err(|e| StdError::generic_err(e.to_string()))?
+            .saturating_sub(offer_pool);
+
+        let spread_amount =
+            (offer_amount * ask_pool / offer_pool).saturating_sub(before_commission);
+
+        Ok(ReverseSwapOutcome {
+            offer_amount,
+            spread_amount,
+            commission_amount: before_commission - ask_amount,
+        })
+    }
+
+    fn compute_share(
+        &self,
+        _config: &mut Config,
+        _env: &Env,
+        xs: &[Decimal256],
+        deposits: &[Decimal256; 2],
+        total_share: Decimal256,
+        slippage_tolerance: Option<Decimal>,
+    ) -> Result<(Decimal256, Decimal256), ContractError> {
+        if total_share.is_zero() {
+            let share = initial_share((deposits[0] * deposits[1]).sqrt())?;
+            return Ok((share, Decimal256::zero()));
+        }
+
+        assert_deposit_ratio(xs, deposits, slippage_tolerance)?;
+
+        let share = std::cmp::min(
+            deposits[0] * total_share / xs[0],
+            deposits[1] * total_share / xs[1],
+        );
+
+        Ok((share, Decimal256::zero()))
+    }
+}
+
+/// Ensures the deposit ratio does not move the pool price more than the slippage tolerance allows.
+fn assert_deposit_ratio(
+    xs: &[Decimal256],
+    deposits: &[Decimal256; 2],
+    slippage_tolerance: Option<Decimal>,
+) -> Result<(), ContractError> {
+    let slippage_tolerance = slippage_tolerance
+        .map(Decimal256::from)
+        .unwrap_or(DEFAULT_SLIPPAGE);
+    if slippage_tolerance > MAX_ALLOWED_SLIPPAGE {
+        return Err(ContractError::AllowedSpreadAssertion {});
+    }
+    let one_minus_slippage_tolerance = Decimal256::one() - slippage_tolerance;
+    if deposits[0] * xs[1] * one_minus_slippage_tolerance > deposits[1] * xs[0]
+        || deposits[1] * xs[0] * one_minus_slippage_tolerance > deposits[0] * xs[1]
+    {
+        return Err(ContractError::MaxSlippageAssertion {});
+    }
+
+    Ok(())
+}
+
+/// Stableswap pool math with a constant amplification coefficient.
+pub struct StableCurve {
+    /// Amplification coefficient multiplied by the number of coins
+    pub ann: Decimal256,
+    /// The swap fee charged on the output
+    pub fee: Decimal256,
+}
+
+impl StableCurve {
+    pub fn new(params: &CurveParams) -> Result<Self, ContractError> {
+        let amp = params
+            .amp
+            .ok_or_else(|| StdError::generic_err("Stableswap pool has no amp"))?;
+
+        Ok(Self {
+            ann: Decimal256::from_atomics(amp, 0).map_err(|e| StdError::generic_err(e.to_string()))?
+                * N_COINS,
+            fee: fee_from_bps(params.fee_bps),
+        })
+    }
+
+    /// Calculates the stableswap invariant D, or an error if either side of the pool is empty
+    /// or too small to carry the precision the iteration needs.
+    pub fn calc_d(&self, xs: &[Decimal256]) -> Result<Decimal256, ContractError> {
+        if xs[0].is_zero() || xs[1].is_zero() {
+            return Err(ContractError::InsufficientLiquidity {});
+        }
+        let sum = xs[0] + xs[1];
+
+        let mut d = sum;
+        for _ in 0..MAX_ITER {
+            let d_p = d * d / (xs[0] * N_COINS) * d / (xs[1] * N_COINS);
+            let d_prev = d;
+            d = ((self.ann * sum + d_p * N_COINS) * d)
+                .checked_div((self.ann - Decimal256::one()) * d + (N_COINS + Decimal256::one()) * d_p)
+                .map_err(|_| ContractError::InsufficientLiquidity {})?;
+            if d.diff(d_prev) <= TOL {
+                return Ok(d);
+            }
+        }
+
+        Err(StdError::generic_err("Stableswap D calculation does not converge").into())
+    }
+
+    /// Calculates the new balance of one asset given the new balance `x` of the other one.
+    fn calc_y(&self, x: Decimal256, d: Decimal256) -> Result<Decimal256, ContractError> {
+        if x.is_zero() {
+            return Err(ContractError::InsufficientLiquidity {});
+        }
+        let c = d * d / (x * N_COINS) * d / (self.ann * N_COINS);
+        let b = x + d / self.ann;
+
+        let mut y = d;
+        for _ in 0..MAX_ITER {
+            let y_prev = y;
+            y = (y * y + c)
+                .checked_div((y * N_COINS + b).checked_sub(d)?)
+                .map_err(|_| ContractError::InsufficientLiquidity {})?;
+            if y.diff(y_prev) <= TOL {
+                return Ok(y);
+            }
+        }
+
+        Err(StdError::generic_err("Stableswap y calculation does not converge").into())
+    }
+}
+
+impl PoolCurve for StableCurve {
+    fn compute_swap(
+        &self,
+        _config: &Config,
+        _env: &Env,
+        xs: &[Decimal256],
+        offer_ind: usize,
+        offer_amount: Decimal256,
+    ) -> Result<SwapOutcome, ContractError> {
+        let ask_ind = 1 ^ offer_ind;
+        let d = self.calc_d(xs)?;
+        let new_ask_pool = self.calc_y(xs[offer_ind] + offer_amount, d)?;
+        let return_amount = xs[ask_ind].saturating_sub(new_ask_pool);
+
+        // Stable assets are expected to be swapped 1:1
+        let spread_amount = offer_amount.saturating_sub(return_amount);
+        let commission_amount = return_amount * self.fee;
+
+        Ok(SwapOutcome {
+            return_amount: return_amount - commission_amount,
+            spread_amount,
+            commission_amount,
+        })
+    }
+
+    fn compute_offer(
+        &self,
+        _config: &Config,
+        _env: &Env,
+        xs: &[Decimal256],
+        ask_ind: usize,
+        ask_amount: Decimal256,
+    ) -> Result<ReverseSwapOutcome, ContractError> {
+        let offer_ind = 1 ^ ask_ind;
+        let before_commission = before_fee(ask_amount, self.fee, xs[ask_ind])?;
+        let d = self.calc_d(xs)?;
+        let new_offer_pool = self.calc_y(xs[ask_ind] - before_commission, d)?;
+        let offer_amount = new_offer_pool.saturating_sub(xs[offer_ind]);
+
+        // Stable assets are expected to be swapped 1:1
+        Ok(ReverseSwapOutcome {
+            offer_amount,
+            spread_amount: offer_amount.saturating_sub(before_commission),
+            commission_amount: before_commission - ask_amount,
+        })
+    }
+
+    fn compute_share(
+        &self,
+        _config: &mut Config,
+        _env: &Env,
+        xs: &[Decimal256],
+        deposits: &[Decimal256; 2],
+        total_share: Decimal256,
+        slippage_tolerance: Option<Decimal>,
+    ) -> Result<(Decimal256, Decimal256), ContractError> {
+        let new_xs = [xs[0] + deposits[0], xs[1] + deposits[1]];
+
+        if total_share.is_zero() {
+            let share = initial_share(self.calc_d(&new_xs)?)?;
+            return Ok((share, Decimal256::zero()));
+        }
+
+        assert_deposit_ratio(xs, deposits, slippage_tolerance)?;
+
+        let old_d = self.calc_d(xs)?;
+        let new_d = self.calc_d(&new_xs)?;
+
+        // Charge the swap fee on the imbalanced part of the deposit
+        let imbalance_fee = self.fee / N_COINS;
+        let adjusted_xs = new_xs
+            .iter()
+            .zip(xs)
+            .map(|(new_x, old_x)| {
+                let ideal_x = new_d * *old_x / old_d;
+                *new_x - imbalance_fee * new_x.diff(ideal_x)
+            })
+            .collect_vec();
+        let adjusted_d = self.calc_d(&adjusted_xs)?;
+
+        let share = total_share * adjusted_d.saturating_sub(old_d) / old_d;
+
+        Ok((share, Decimal256::zero()))
+    }
+}
@@ -0,0 +1,173 @@
+use astroport::asset::{addr_opt_validate, Asset};
+use cosmwasm_std::{
+    attr, Addr, Decimal, DepsMut, Env, Fraction, MessageInfo, Response, Storage, Uint128,
+};
+
+use crate::error::ContractError;
+use crate::handlers::{assert_minimum_receive, assert_operations, send_asset, swap_route};
+use crate::msg::SwapOperation;
+use crate::state::{dca_schedules, DcaSchedule, DCA_SCHEDULE_COUNT};
+
+/// The highest share of a tranche output a schedule can pay its keeper, in basis points.
+pub const MAX_KEEPER_TIP_BPS: u16 = 100;
+
+/// Escrows `input_amount` of the first offer asset of `operations` and schedules it to be
+/// swapped in `tranches` equal parts, one every `interval` seconds. The first tranche is
+/// due right away.
+#[allow(clippy::too_many_arguments)]
+pub fn execute_create_dca(
+    deps: &mut DepsMut,
+    env: Env,
+    owner: Addr,
+    operations: Vec<SwapOperation>,
+    input_amount: Uint128,
+    tranches: u32,
+    interval: u64,
+    max_price: Decimal,
+    max_spread: Option<Decimal>,
+    keeper_tip_bps: u16,
+) -> Result<Response, ContractError> {
+    assert_operations(deps.api, &operations)?;
+    if tranches == 0 || interval == 0 || input_amount < Uint128::from(tranches) {
+        return Err(ContractError::InvalidDcaSchedule {});
+    }
+    if keeper_tip_bps > MAX_KEEPER_TIP_BPS {
+        return Err(ContractError::KeeperTipTooHigh {
+            tip_bps: keeper_tip_bps,
+            max_bps: MAX_KEEPER_TIP_BPS,
+        });
+    }
+    if max_price.is_zero() {
+        return Err(ContractError::InvalidLimitPrice {});
+    }
+
+    let id = DCA_SCHEDULE_COUNT.may_load(deps.storage)?.unwrap_or_default() + 1;
+    DCA_SCHEDULE_COUNT.save(deps.storage, &id)?;
+    let offer_asset = Asset {
+        info: operations[0].offer_asset_info.clone(),
+        amount: input_amount,
+    };
+    dca_schedules().save(
+        deps.storage,
+        id,
+        &DcaSchedule {
+            id,
+            owner: owner.clone(),
+            operations,
+            offer_asset,
+            tranche_amount: input_amount / Uint128::from(tranches),
+            tranches_left: tranches,
+            interval,
+            next_execution: env.block.time,
+            max_price,
+            max_spread,
+            keeper_tip_bps,
+            received_amount: Uint128::zero(),
+        },
+    )?;
+
+    Ok(Response::new().add_attributes(vec![
+        attr("action", "create_dca"),
+        attr("schedule_id", id.to_string()),
+        attr("owner", owner),
+        attr("tranches", tranches.to_string()),
+    ]))
+}
+
+/// Swaps the next due tranche of a schedule. The output goes to the owner less the keeper
+/// tip, and must be worth at least the tranche at the schedule's maximum price.
+pub fn execute_dca_tranche(
+    deps: &mut DepsMut,
+    env: Env,
+    info: MessageInfo,
+    schedule_id: u64,
+) -> Result<Response, ContractError> {
+    let mut schedule = load_schedule(deps.storage, schedule_id)?;
+    if env.block.time < schedule.next_execution {
+        return Err(ContractError::DcaTrancheNotDue(schedule.next_execution));
+    }
+
+    let amount = if schedule.tranches_left == 1 {
+        schedule.offer_asset.amount
+    } else {
+        schedule.tranche_amount
+    };
+    let ask_asset_info = schedule.operations[schedule.operations.len() - 1]
+        .ask_asset_info
+        .clone();
+    let return_amount = swap_route(
+        deps,
+        &env,
+        schedule.operations.clone(),
+        amount,
+        None,
+        schedule.max_spread,
+        None,
+    )?;
+    let tip = return_amount.multiply_ratio(schedule.keeper_tip_bps, 10_000u16);
+    let owner_amount = return_amount - tip;
+    let price_inv = schedule.max_price.inv().ok_or(ContractError::InvalidLimitPrice {})?;
+    assert_minimum_receive(amount * price_inv, owner_amount)?;
+
+    schedule.offer_asset.amount -= amount;
+    schedule.tranches_left -= 1;
+    schedule.next_execution = env.block.time.plus_seconds(schedule.interval);
+    schedule.received_amount += owner_amount;
+    if schedule.tranches_left == 0 {
+        dca_schedules().remove(deps.storage, schedule_id)?;
+    } else {
+        dca_schedules().save(deps.storage, schedule_id, &schedule)?;
+    }
+
+    let mut messages = vec![send_asset(
+        &schedule.owner,
+        ask_asset_info.clone(),
+        owner_amount,
+    )?];
+    if !tip.is_zero() {
+        messages.push(send_asset(&info.sender, ask_asset_info, tip)?);
+    }
+
+    Ok(Response::new().add_messages(messages).add_attributes(vec![
+        attr("action", "execute_dca_tranche"),
+        attr("schedule_id", schedule_id.to_string()),
+        attr("offer_amount", amount),
+        attr("return_amount", owner_amount),
+        attr("keeper_tip", tip),
+        attr("tranches_left", schedule.tranches_left.to_string()),
+    ]))
+}
+
+/// Cancels a schedule and sends the offer asset of the tranches left to `to`, or to the
+/// owner if omitted. Only the schedule owner can execute this.
+pub fn execute_cancel_dca(
+    deps: &mut DepsMut,
+    info: MessageInfo,
+    schedule_id: u64,
+    to: Option<String>,
+) -> Result<Response, ContractError> {
+    let schedule = load_schedule(deps.storage, schedule_id)?;
+    if schedule.owner != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    let recipient = addr_opt_validate(deps.api, &to)?.unwrap_or(schedule.owner);
+    dca_schedules().remove(deps.storage, schedule_id)?;
+
+    Ok(Response::new()
+        .add_message(send_asset(
+            &recipient,
+            schedule.offer_asset.info,
+            schedule.offer_asset.amount,
+        )?)
+        .add_attributes(vec![
+            attr("action", "cancel_dca"),
+            attr("schedule_id", schedule_id.to_string()),
+            attr("refund_amount", schedule.offer_asset.amount),
+        ]))
+}
+
+fn load_schedule(storage: &dyn Storage, schedule_id: u64) -> Result<DcaSchedule, ContractError> {
+    dca_schedules()
+        .may_load(storage, schedule_id)?
+        .ok_or(ContractError::DcaScheduleNotFound(schedule_id))
+}
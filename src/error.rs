@@ -1,8 +1,9 @@
 use astroport_pcl_common::consts::MIN_AMP_CHANGING_TIME;
-use cosmwasm_std::{Decimal,ConversionOverflowError, OverflowError, StdError, Uint128};
+use cosmwasm_std::{Decimal,ConversionOverflowError, OverflowError, StdError, Timestamp, Uint128};
 use astroport::{asset::MINIMUM_LIQUIDITY_AMOUNT, pair::MAX_FEE_SHARE_BPS};
 
 use astroport_pcl_common::error::PclError;
+use cw_utils::{Expiration, PaymentError};
 use thiserror::Error;
 #[derive(Error, Debug, PartialEq)]
 pub enum ContractError {
@@ -23,10 +24,15 @@ pub enum ContractError {
     #[error("Invalid rebalance parameters")]
     InvalidRebalance {},
 
+    #[error("Position {0} not found")]
+    PositionNotFound(String),
+
     #[error("Unauthorized")]
     Unauthorized{},
     #[error("Contract can't be migrated!")]
     MigrationError {},
+    #[error("A manager config must be provided to migrate from a version without one")]
+    MissingManagerConfig {},
     #[error("You need to provide init params")]
     InitParamsNotFound {},
     #[error("Initial liquidity must be more than {}", MINIMUM_LIQUIDITY_AMOUNT)]
@@ -47,6 +53,24 @@ pub enum ContractError {
     )]
     MinChangingTimeAssertion {},
 
+    #[error("Operation is not supported for {0} pools")]
+    UnsupportedPoolType(String),
+
+    #[error("Operation exceeds max slippage tolerance")]
+    MaxSlippageAssertion {},
+
+    #[error("Swaps are disabled for pool {0}")]
+    SwapsDisabled(String),
+
+    #[error("Providing liquidity is disabled for pool {0}")]
+    ProvideDisabled(String),
+
+    #[error("Withdrawing liquidity is disabled for pool {0}")]
+    WithdrawDisabled(String),
+
+    #[error("Pool not found: {0}")]
+    PoolNotFound(String),
+
     #[error("Doubling assets in asset infos")]
     DoublingAssets {},
 
@@ -59,6 +83,9 @@ pub enum ContractError {
     #[error("Operation exceeds max spread limit")]
     MaxSpreadAssertion {},
 
+    #[error("Operation exceeds max spread limit, realised price: {realised_price}")]
+    MaxSpreadExceeded { realised_price: Decimal },
+
     #[error("Provided spread amount exceeds allowed limit")]
     AllowedSpreadAssertion {},
 
@@ -80,21 +107,102 @@ pub enum ContractError {
         ask_asset: String,
     },
 
+    #[error("No route found from {offer_asset} to {ask_asset}")]
+    NoRouteFound {
+        offer_asset: String,
+        ask_asset: String,
+    },
+
+    #[error("All split routes must swap the same offer asset to the same ask asset")]
+    SplitRouteMismatch {},
+
+    #[error("At least one split route must have a non-zero weight")]
+    ZeroSplitWeights {},
+
+    #[error("Swap needs {offer_amount} of the offer asset, more than the maximum of {max_offer}")]
+    MaxOfferExceeded {
+        offer_amount: Uint128,
+        max_offer: Uint128,
+    },
+
+    #[error("Deadline has passed: {0}")]
+    DeadlinePassed(Expiration),
+
+    #[error("Limit price must be greater than zero")]
+    InvalidLimitPrice {},
+
+    #[error("Limit order {0} not found")]
+    LimitOrderNotFound(u64),
+
+    #[error("Limit order {order_id} does not belong to pool {pool_key}")]
+    LimitOrderNotInPool { order_id: u64, pool_key: String },
+
+    #[error("None of the limit orders can be filled")]
+    NoFillableOrders {},
+
+    #[error("A DCA schedule needs at least one tranche, a non-zero interval and a non-zero tranche amount")]
+    InvalidDcaSchedule {},
+
+    #[error("Keeper tip of {tip_bps} bps exceeds the maximum of {max_bps} bps")]
+    KeeperTipTooHigh { tip_bps: u16, max_bps: u16 },
+
+    #[error("DCA schedule {0} not found")]
+    DcaScheduleNotFound(u64),
+
+    #[error("The next tranche is due at {0}")]
+    DcaTrancheNotDue(Timestamp),
+
+    #[error("A swap with a callback sends its return to the callback contract and cannot set a recipient")]
+    CallbackWithRecipient {},
+
+    #[error("Flash loan fee of {fee_bps} bps exceeds the maximum of {max_bps} bps")]
+    FlashLoanFeeTooHigh { fee_bps: u16, max_bps: u16 },
+
+    #[error("A flash loan is in progress")]
+    FlashLoanInProgress {},
+
+    #[error("Flash loan of {asset} not repaid: expected a balance of {expected}, got {actual}")]
+    FlashLoanNotRepaid {
+        asset: String,
+        expected: Uint128,
+        actual: Uint128,
+    },
+
+    #[error("Referral commission {commission} exceeds the maximum of {max_commission}")]
+    ReferralCommissionTooHigh {
+        commission: Decimal,
+        max_commission: Decimal,
+    },
+
+    #[error("A referral commission needs a referral address")]
+    ReferralAddressMissing {},
+
     #[error("Must specify swap operations!")]
     MustProvideOperations {},
 
     #[error("Assertion failed; minimum receive amount: {receive}, swap amount: {amount}")]
     AssertionMinimumReceive { receive: Uint128, amount: Uint128 },
 
+    #[error("Got {minimums} per-hop minimums for {operations} swap operations")]
+    InvalidHopMinimums { operations: usize, minimums: usize },
+
     #[error("The swap operation limit was exceeded!")]
     SwapLimitExceeded {},
 
+    #[error("{0}")]
+    PaymentError(#[from] PaymentError),
+
+    #[error("Swaps paid with native funds must offer a native token; use the cw20 Receive hook instead")]
+    NonNativeOffer {},
+
     #[error("Native swap operations are not supported!")]
     NativeSwapNotSupported {},
     #[error("")]
     InvalidZeroAmount{},
     #[error("Invalid number of assets. This pair supports only {0} assets")]
     InvalidNumberOfAssets(usize),
+    #[error("Native denom {0} is not allowed in pools")]
+    DenomNotAllowed(String),
     #[error("Failed to Parse Reply")]
     FailedToParseReply{},
 }
\ No newline at end of file
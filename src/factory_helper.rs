@@ -2,22 +2,46 @@
 
 
 use anyhow::Result as AnyResult;
-use cosmwasm_std::{coins, Addr, Binary, Decimal};
+use cosmwasm_std::{coin, coins, to_json_binary, Addr, Binary, Decimal, Empty};
 use cw20::MinterResponse;
-use cw_multi_test::{App, AppResponse, ContractWrapper, Executor};
+use cw_multi_test::{App, AppResponse, Contract, ContractWrapper, Executor};
 
 use astroport::asset::{Asset, AssetInfo};
+use astroport::factory::PairType;
 
 use crate::msg::ExecuteMsg::{self, CreatePair};
+use crate::msg::{InstantiateMsg, LpTokenInfo, XykPoolParams};
+
+pub static DENOM: &str = "aarch";
+pub static GUARDIAN: &str = "guardian";
+pub static IBC_DENOM: &str =
+    "ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2";
+
 pub struct FactoryHelper {
     pub owner: Addr,   
     pub pool_manager:Addr,
     pub cw20_token_code_id: u64,
 }
 
+fn pool_manager_contract() -> Box<dyn Contract<Empty>> {
+    Box::new(
+        ContractWrapper::new_with_empty(
+            crate::contract::execute,
+            crate::contract::instantiate,
+            crate::contract::query,
+        )
+        .with_reply_empty(crate::contract::reply),
+    )
+}
+
 impl FactoryHelper {
-    pub fn init(router: &mut App, owner: &Addr,pool_manager:&Addr) -> Self {
-        
+    pub fn init(router: &mut App, owner: &Addr) -> Self {
+        Self::init_with_denoms(router, owner, &[DENOM, IBC_DENOM])
+    }
+
+    pub fn init_with_denoms(router: &mut App, owner: &Addr, allowed_denoms: &[&str]) -> Self {
+        let pool_manager_code_id = router.store_code(pool_manager_contract());
+
         let astro_token_contract = Box::new(ContractWrapper::new_with_empty(
             astroport_token::contract::execute,
             astroport_token::contract::instantiate,
@@ -25,10 +49,30 @@ impl FactoryHelper {
         ));
 
         let cw20_token_code_id = router.store_code(astro_token_contract);
+
+        let pool_manager = router
+            .instantiate_contract(
+                pool_manager_code_id,
+                owner.clone(),
+                &InstantiateMsg {
+                    owner: owner.to_string(),
+                    token_code_id: cw20_token_code_id,
+                    allowed_denoms: allowed_denoms.iter().map(|denom| denom.to_string()).collect(),
+                    fee_address: None,
+                    guardian: Some(GUARDIAN.to_string()),
+                    flash_loan_fee_bps: 0,
+                    max_referral_commission: Decimal::zero(),
+                },
+                &[],
+                "pool_manager",
+                None,
+            )
+            .unwrap();
+
         Self{
-            pool_manager:pool_manager.clone(),
+            pool_manager,
             owner:owner.clone(),
-            cw20_token_code_id:cw20_token_code_id
+            cw20_token_code_id
         }
     }
    
@@ -37,13 +81,47 @@ impl FactoryHelper {
         router: &mut App,
         sender: &Addr,
        
+        asset_infos: [AssetInfo; 2],
+        init_params: Option<Binary>,
+    ) -> AnyResult<Addr> {
+        self.create_pair_with_lp_token(router, sender, asset_infos, init_params, None, None)
+    }
+
+    pub fn create_pair_with_type(
+        &mut self,
+        router: &mut App,
+        sender: &Addr,
+        pair_type: PairType,
         asset_infos: [AssetInfo; 2],
         init_params: Option<Binary>,
     ) -> AnyResult<Addr> {
         let msg = CreatePair {
-            
             asset_infos: asset_infos.to_vec(),
-            token_code_id:self.cw20_token_code_id,
+            pair_type: Some(pair_type),
+            token_code_id: None,
+            lp_token: None,
+            init_params,
+        };
+
+        router.execute_contract(sender.clone(), self.pool_manager.clone(), &msg, &[])?;
+
+        Ok(self.pool_manager.clone())
+    }
+
+    pub fn create_pair_with_lp_token(
+        &mut self,
+        router: &mut App,
+        sender: &Addr,
+        asset_infos: [AssetInfo; 2],
+        init_params: Option<Binary>,
+        token_code_id: Option<u64>,
+        lp_token: Option<LpTokenInfo>,
+    ) -> AnyResult<Addr> {
+        let msg = CreatePair {
+            asset_infos: asset_infos.to_vec(),
+            pair_type: None,
+            token_code_id,
+            lp_token,
             init_params,
         };
 
@@ -58,24 +136,85 @@ impl FactoryHelper {
         
         Ok(self.pool_manager.clone())
     }
-    pub fn provide_liquidity_with_slip_tolerance(
+    /// Creates an xyk pool with a 0.3% fee.
+    pub fn create_xyk_pair(
+        &mut self,
+        router: &mut App,
+        sender: &Addr,
+        asset_infos: [AssetInfo; 2],
+    ) -> AnyResult<Addr> {
+        let init_params = to_json_binary(&XykPoolParams {
+            fee_bps: 30,
+            track_asset_balances: None,
+        })?;
+        self.create_pair_with_type(router, sender, PairType::Xyk {}, asset_infos, Some(init_params))
+    }
+
+    /// Creates an xyk pool with a 0.3% fee, mints `assets` to `sender` and provides them as
+    /// the initial liquidity. `sender` has to be the minter of the cw20 assets.
+    pub fn create_seeded_xyk_pool(
         &mut self,
         router: &mut App,
         sender: &Addr,
-        assets: &[Asset],
-        slippage_tolerance: Option<Decimal>,
+        assets: [Asset; 2],
     ) -> AnyResult<AppResponse> {
-       
+        let init_params = to_json_binary(&XykPoolParams {
+            fee_bps: 30,
+            track_asset_balances: None,
+        })?;
+        self.create_seeded_pool(router, sender, PairType::Xyk {}, init_params, assets)
+    }
+
+    /// Creates a pool of `pair_type`, mints `assets` to `sender` and provides them as the
+    /// initial liquidity. `sender` has to be the minter of the cw20 assets.
+    pub fn create_seeded_pool(
+        &mut self,
+        router: &mut App,
+        sender: &Addr,
+        pair_type: PairType,
+        init_params: Binary,
+        assets: [Asset; 2],
+    ) -> AnyResult<AppResponse> {
+        self.create_pair_with_type(
+            router,
+            sender,
+            pair_type,
+            [assets[0].info.clone(), assets[1].info.clone()],
+            Some(init_params),
+        )?;
+
+        let mut funds = vec![];
+        for asset in &assets {
+            match &asset.info {
+                AssetInfo::NativeToken { denom } => {
+                    mint_native(router, denom, asset.amount.u128(), sender)?;
+                    funds.push(coin(asset.amount.u128(), denom));
+                }
+                AssetInfo::Token { contract_addr } => {
+                    mint(router, sender, contract_addr, asset.amount.u128(), sender)?;
+                    router.execute_contract(
+                        sender.clone(),
+                        contract_addr.clone(),
+                        &cw20::Cw20ExecuteMsg::IncreaseAllowance {
+                            spender: self.pool_manager.to_string(),
+                            expires: None,
+                            amount: asset.amount,
+                        },
+                        &[],
+                    )?;
+                }
+            }
+        }
 
         let msg = ExecuteMsg::ProvideLiquidity {
             assets: assets.to_vec(),
-            slippage_tolerance,
+            slippage_tolerance: None,
             auto_stake: None,
             receiver: None,
+            pool_id: None,
+            deadline: None,
         };
-
-        
-        router.execute_contract(sender.clone(), self.pool_manager.clone(), &msg, &[])
+        router.execute_contract(sender.clone(), self.pool_manager.clone(), &msg, &funds)
     }
 }
   
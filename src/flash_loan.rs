@@ -0,0 +1,184 @@
+use astroport::asset::Asset;
+use astroport_pcl_common::state::Config;
+use cosmwasm_std::{attr, wasm_execute, Decimal, DepsMut, Env, MessageInfo, Response};
+
+use crate::error::ContractError;
+use crate::handlers::{pool_status, send_asset};
+use crate::msg::{
+    ExecuteMsg, FlashLoanCallback, FlashLoanCallbackExecuteMsg, FlashLoanCallbackMsg,
+};
+use crate::state::{FlashLoan, BALANCES, CONFIG, FLASH_LOAN, PAIR_BALANCES, POOLS};
+
+/// The highest flash loan fee the owner can set, in basis points.
+pub const MAX_FLASH_LOAN_FEE_BPS: u16 = 1_000;
+
+pub(crate) fn assert_flash_loan_fee(fee_bps: u16) -> Result<(), ContractError> {
+    if fee_bps > MAX_FLASH_LOAN_FEE_BPS {
+        return Err(ContractError::FlashLoanFeeTooHigh {
+            fee_bps,
+            max_bps: MAX_FLASH_LOAN_FEE_BPS,
+        });
+    }
+    Ok(())
+}
+
+/// Lends `assets` out of the reserves of `pool_key` to the callback contract and calls it.
+/// A final self-call to [`ExecuteMsg::CompleteFlashLoan`] checks that the manager got
+/// every asset back with the fee, so an unpaid loan reverts the whole transaction.
+pub fn execute_flash_loan(
+    deps: &mut DepsMut,
+    env: Env,
+    info: MessageInfo,
+    pool_key: String,
+    assets: Vec<Asset>,
+    callback: FlashLoanCallback,
+) -> Result<Response, ContractError> {
+    let reserves = PAIR_BALANCES
+        .may_load(deps.storage, pool_key.clone())?
+        .ok_or_else(|| ContractError::PoolNotFound(pool_key.clone()))?;
+    if !pool_status(deps.storage, &pool_key)?.allows_swaps() {
+        return Err(ContractError::SwapsDisabled(pool_key));
+    }
+    if assets.is_empty() {
+        return Err(ContractError::InvalidZeroAmount {});
+    }
+    for (i, asset) in assets.iter().enumerate() {
+        if assets[..i].iter().any(|prev| prev.info == asset.info) {
+            return Err(ContractError::DoublingAssets {});
+        }
+        let reserve = reserves
+            .iter()
+            .find(|reserve| reserve.info == asset.info)
+            .ok_or_else(|| ContractError::InvalidAsset(asset.info.to_string()))?;
+        if asset.amount.is_zero() {
+            return Err(ContractError::InvalidZeroAmount {});
+        }
+        if asset.amount > reserve.amount {
+            return Err(ContractError::InsufficientLiquidity {});
+        }
+    }
+
+    let fee_rate = Decimal::from_ratio(CONFIG.load(deps.storage)?.flash_loan_fee_bps, 10_000u16);
+    let mut fees = vec![];
+    let mut min_balances = vec![];
+    for asset in &assets {
+        let fee = asset.amount.mul_ceil(fee_rate);
+        let balance = asset
+            .info
+            .query_pool(&deps.querier, &env.contract.address)?;
+        fees.push(Asset {
+            info: asset.info.clone(),
+            amount: fee,
+        });
+        min_balances.push(Asset {
+            info: asset.info.clone(),
+            amount: balance.checked_add(fee)?,
+        });
+    }
+    FLASH_LOAN.save(
+        deps.storage,
+        &FlashLoan {
+            pool_key: pool_key.clone(),
+            fees: fees.clone(),
+            min_balances,
+        },
+    )?;
+
+    let borrower = deps.api.addr_validate(&callback.contract)?;
+    let mut messages = assets
+        .iter()
+        .map(|asset| send_asset(&borrower, asset.info.clone(), asset.amount))
+        .collect::<Result<Vec<_>, _>>()?;
+    messages.push(
+        wasm_execute(
+            &borrower,
+            &FlashLoanCallbackExecuteMsg::FlashLoanCallback(FlashLoanCallbackMsg {
+                sender: info.sender.to_string(),
+                assets: assets.clone(),
+                fees,
+                msg: callback.msg,
+            }),
+            vec![],
+        )?
+        .into(),
+    );
+    messages.push(
+        wasm_execute(
+            &env.contract.address,
+            &ExecuteMsg::CompleteFlashLoan {},
+            vec![],
+        )?
+        .into(),
+    );
+
+    Ok(Response::new().add_messages(messages).add_attributes(vec![
+        attr("action", "flash_loan"),
+        attr("sender", info.sender),
+        attr("borrower", borrower),
+        attr("pool_key", pool_key),
+        attr(
+            "assets",
+            assets.iter().map(|asset| asset.to_string()).collect::<Vec<_>>().join(","),
+        ),
+    ]))
+}
+
+/// Checks that the flash loan in progress was repaid with its fee and sends the fee to the
+/// manager's fee address, or adds it to the lending pool's reserves if there is none. Only
+/// the manager itself can execute this.
+pub fn execute_complete_flash_loan(
+    deps: &mut DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    if info.sender != env.contract.address {
+        return Err(ContractError::Unauthorized {});
+    }
+    let loan = FLASH_LOAN.load(deps.storage)?;
+    FLASH_LOAN.remove(deps.storage);
+
+    for min_balance in &loan.min_balances {
+        let balance = min_balance
+            .info
+            .query_pool(&deps.querier, &env.contract.address)?;
+        if balance < min_balance.amount {
+            return Err(ContractError::FlashLoanNotRepaid {
+                asset: min_balance.info.to_string(),
+                expected: min_balance.amount,
+                actual: balance,
+            });
+        }
+    }
+
+    let mut messages = vec![];
+    if let Some(fee_address) = CONFIG.load(deps.storage)?.fee_address {
+        for fee in loan.fees.iter().filter(|fee| !fee.amount.is_zero()) {
+            messages.push(send_asset(&fee_address, fee.info.clone(), fee.amount)?);
+        }
+    } else {
+        // The fee stays in the pool, so it accrues to the liquidity providers
+        let mut reserves = PAIR_BALANCES.load(deps.storage, loan.pool_key.clone())?;
+        for fee in &loan.fees {
+            if let Some(reserve) = reserves.iter_mut().find(|reserve| reserve.info == fee.info) {
+                reserve.amount = reserve.amount.checked_add(fee.amount)?;
+            }
+        }
+        PAIR_BALANCES.save(deps.storage, loan.pool_key.clone(), &reserves)?;
+
+        let config: Config = POOLS.load(deps.storage, loan.pool_key.clone())?;
+        if config.track_asset_balances {
+            for reserve in &reserves {
+                BALANCES.save(deps.storage, &reserve.info, &reserve.amount, env.block.height)?;
+            }
+        }
+    }
+
+    Ok(Response::new().add_messages(messages).add_attributes(vec![
+        attr("action", "complete_flash_loan"),
+        attr("pool_key", loan.pool_key),
+        attr(
+            "fees",
+            loan.fees.iter().map(|fee| fee.to_string()).collect::<Vec<_>>().join(","),
+        ),
+    ]))
+}
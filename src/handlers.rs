@@ -2,71 +2,121 @@ use astroport::asset::{
     addr_opt_validate, format_lp_token_name, Asset, AssetInfo, CoinsExt, Decimal256Ext, PairInfo,
     MINIMUM_LIQUIDITY_AMOUNT,
 };
-use astroport::cosmwasm_ext::{AbsDiff as _, DecimalToInteger, IntegerToDecimal};
+use astroport::cosmwasm_ext::{DecimalToInteger, IntegerToDecimal};
 use astroport::factory::PairType;
 use astroport::observation::PrecommitObservation;
 use astroport::pair::MIN_TRADE_SIZE;
 use astroport::querier::query_supply;
 use astroport::token::InstantiateMsg as TokenInstantiateMsg;
-use astroport::cosmwasm_ext::{AbsDiff, DecimalToInteger, IntegerToDecimal, DecimalExt};
 
-use astroport::pair_concentrated::{ConcentratedPoolParams, UpdatePoolParams};
+use astroport::pair_concentrated::{
+    ConcentratedPoolParams, ConcentratedPoolUpdateParams, UpdatePoolParams,
+};
 
 use astroport_pcl_common::state::{
     AmpGamma, Config, PoolParams, PoolState,  PriceState,
 };
 use astroport_pcl_common::utils::{
-    assert_max_spread, assert_slippage_tolerance, before_swap_check, calc_provide_fee,
-    check_asset_infos, check_assets, compute_swap, get_share_in_assets,
+    before_swap_check, check_asset_infos, check_assets, get_share_in_assets,
     mint_liquidity_token_message,
 };
-use astroport_pcl_common::{calc_d, get_xcp};
+use astroport_pcl_common::error::PclError;
 
 use std::str;
 
+use crate::contract::INSTANTIATE_TOKEN_REPLY_ID_START;
+use crate::curves::{
+    assert_concentrated, pool_curve, CONCENTRATED_PAIR_TYPE, DEFAULT_SLIPPAGE, MAX_ALLOWED_SLIPPAGE,
+    MAX_AMP, MAX_FEE_BPS,
+};
 use crate::error::ContractError;
-use crate::msg::SwapOperation;
+use crate::flash_loan::assert_flash_loan_fee;
+use crate::msg::{
+    LpTokenInfo, SplitRoute, StablePoolParams, SwapCallback, SwapCallbackExecuteMsg,
+    SwapCallbackMsg, SwapOperation, XykPoolParams,
+};
+use crate::query::{find_routes, simulate_reverse_swap_operations};
+use crate::utils::query_pools;
 use crate::state::{
     decrease_asset_balance, decrease_pair_balances, find_asset_index, increment_asset_balance,
-    increment_pair_balances, pair_key, BALANCES, PAIR_BALANCES, POOLS, QUEUED_MINT,Precisions
+    increment_pair_balances, index_pool_assets, load_pool, pair_key, pool_key, CurveParams, PoolStatus, LP_TOKEN_POOLS, PAIR_POOLS, POOL_COUNT, POOL_STATUS, BALANCES, CONFIG, CURVE_PARAMS, PAIR_BALANCES, POOLS, QUEUED_MINTS, LAST_REPLY_ID, POSITIONS, Position, Precisions
 };
 use crate::msg::PositionModification;
-use crate::state::{Position, POSITIONS};
 use cosmwasm_std::{
-    attr, from_json, to_json_binary, wasm_execute, wasm_instantiate, Addr, Api, BankMsg, Binary, Coin,
-    CosmosMsg, Decimal, Decimal256, DepsMut, Env, MessageInfo, Response, StdError, StdResult,
+    attr, from_json, to_json_binary, wasm_execute, Fraction, wasm_instantiate, Addr, Api, Attribute, BankMsg, Binary, Coin,
+    CosmosMsg, Decimal, Decimal256, DepsMut, Env, MessageInfo, Response, StdError, StdResult, Storage,
     SubMsg, Uint128, WasmMsg,
 };
 use cw20::{Cw20ExecuteMsg, MinterResponse};
+use cw_utils::Expiration;
 use itertools::Itertools;
 pub(crate) const LP_TOKEN_PRECISION: u8 = 6;
-const MAX_SWAP_OPERATIONS: usize = 10;
-const DUMMY_ADDRESS: &str = "PINA_COLADA";
-const INSTANTIATE_TOKEN_REPLY_ID: u64 = 1;
-/// Returns the end result of a simulation for one or multiple swap
-/// operations using a [`SimulateSwapOperationsResponse`] object.
-///
-/// * **offer_amount** amount of offer assets being swapped.
-///
-/// * **operations** is a vector that contains objects of type [`SwapOperation`].
-/// These are all the swap operations for which we perform a simulation.
-pub static DENOM: &str = "aarch";
+const LP_TOKEN_SYMBOL: &str = "pcLP";
+pub(crate) const MAX_SWAP_OPERATIONS: usize = 10;
+/// The highest referral commission the owner can allow on swaps.
+pub const MAX_REFERRAL_COMMISSION: Decimal = Decimal::percent(10);
+pub fn generate_key_from_asset_info(assets: &[AssetInfo]) -> String {
+    pair_key(assets)
+}
 
-pub fn generate_key_from_assets(assets: &[Asset]) -> String {
-    str::from_utf8(&pair_key(&[assets[0].clone().info, assets[1].clone().info]))
-        .unwrap()
-        .to_string()
+/// Returns the key of the pool holding `asset_infos` with id `pool_id`,
+/// or of the first pool created for the pair if no id is given.
+pub fn resolve_pool_key(
+    storage: &dyn Storage,
+    asset_infos: &[AssetInfo],
+    pool_id: Option<u64>,
+) -> Result<String, ContractError> {
+    let pool_id = match pool_id {
+        Some(pool_id) => pool_id,
+        None => PAIR_POOLS
+            .may_load(storage, pair_key(asset_infos))?
+            .and_then(|pool_ids| pool_ids.first().copied())
+            .ok_or_else(|| ContractError::PoolNotFound(pair_key(asset_infos)))?,
+    };
+
+    let key = pool_key(asset_infos, pool_id);
+    if !POOLS.has(storage, key.clone()) {
+        return Err(ContractError::PoolNotFound(key));
+    }
+
+    Ok(key)
 }
-pub fn generate_key_from_asset_info(assets: &[AssetInfo]) -> String {
-    str::from_utf8(&pair_key(&[assets[0].clone(), assets[1].clone()]))
-        .unwrap()
-        .to_string()
+
+/// Returns the status of a pool. Pools without a stored status are active.
+pub fn pool_status(storage: &dyn Storage, pool_key: &str) -> StdResult<PoolStatus> {
+    Ok(POOL_STATUS
+        .may_load(storage, pool_key.to_string())?
+        .unwrap_or_default())
 }
-pub fn send_native(to: &Addr, amount: Uint128) -> StdResult<CosmosMsg> {
+
+fn assert_provide_allowed(storage: &dyn Storage, pool_key: &str) -> Result<(), ContractError> {
+    if !pool_status(storage, pool_key)?.allows_provide() {
+        return Err(ContractError::ProvideDisabled(pool_key.to_string()));
+    }
+    Ok(())
+}
+
+fn assert_withdraw_allowed(storage: &dyn Storage, pool_key: &str) -> Result<(), ContractError> {
+    if !pool_status(storage, pool_key)?.allows_withdraw() {
+        return Err(ContractError::WithdrawDisabled(pool_key.to_string()));
+    }
+    Ok(())
+}
+/// Fails if the deadline of a message has passed.
+pub fn assert_deadline(env: &Env, deadline: Option<Expiration>) -> Result<(), ContractError> {
+    match deadline {
+        Some(deadline) if deadline.is_expired(&env.block) => {
+            Err(ContractError::DeadlinePassed(deadline))
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn send_native(to: &Addr, denom: &str, amount: Uint128) -> StdResult<CosmosMsg> {
     let msg = BankMsg::Send {
         to_address: to.into(),
         amount: ([Coin {
-            denom: String::from(DENOM),
+            denom: denom.to_string(),
             amount,
         }])
         .to_vec(),
@@ -76,7 +126,7 @@ pub fn send_native(to: &Addr, amount: Uint128) -> StdResult<CosmosMsg> {
 /// Validates swap operations.
 ///
 /// * **operations** is a vector that contains objects of type [`SwapOperation`]. These are all the swap operations we check.
-fn assert_operations(api: &dyn Api, operations: &[SwapOperation]) -> Result<(), ContractError> {
+pub(crate) fn assert_operations(api: &dyn Api, operations: &[SwapOperation]) -> Result<(), ContractError> {
     let operations_len = operations.len();
     if operations_len == 0 {
         return Err(ContractError::MustProvideOperations {});
@@ -129,12 +179,13 @@ pub fn execute_provide_liquidity(
     slippage_tolerance: Option<Decimal>,
     auto_stake: Option<bool>,
     receiver: Option<String>,
+    pool_id: Option<u64>,
 ) -> Result<Response, ContractError> {
-    let pool_key = generate_key_from_assets(&assets);
+    let asset_infos = assets.iter().map(|asset| asset.info.clone()).collect_vec();
+    let pool_key = resolve_pool_key(deps.storage, &asset_infos, pool_id)?;
+    assert_provide_allowed(deps.storage, &pool_key)?;
 
     let mut config = POOLS.load(deps.storage, pool_key.clone())?;
-    //println!("{:?} {}", config, String::from("CONFIG HERE "));
-    //println!("{:?}", assets.len());
 
     match assets.len() {
         0 => {
@@ -161,19 +212,16 @@ pub fn execute_provide_liquidity(
         }
     }
     // get assets indices
-    let first_asset_index = find_asset_index(deps, pool_key.clone(), assets[0].clone());
+    let first_asset_index = find_asset_index(deps, pool_key.clone(), assets[0].clone())?;
     let _second_asset_index = 1 ^ first_asset_index;
 
-    //println!("CHECKING ASSETS");
     check_assets(deps.api, &assets)?;
-    //println!("CHECKING SENT");
     info.funds
         .assert_coins_properly_sent(&assets, &config.pair_info.asset_infos)?;
 
     let precisions = Precisions::new(deps.storage)?;
 
-    //println!("QUERY POOLS");
-    let mut pools = query_pools(deps, &config, &precisions)?;
+    let pools = query_pools(deps, &pool_key, &precisions)?;
 
     if pools[0].info.equal(&assets[1].info) {
         assets.swap(0, 1);
@@ -184,25 +232,20 @@ pub fn execute_provide_liquidity(
         Decimal256::with_precision(assets[1].amount, precisions.get_precision(&assets[1].info)?)?,
     ];
 
-    //println!("QUERY SHARE");
-    //println!("{}", &config.pair_info.liquidity_token);
     let total_share = query_supply(&deps.querier, &config.pair_info.liquidity_token)?
         .to_decimal256(LP_TOKEN_PRECISION)?;
-    //println!("{}", total_share);
     // Initial provide can not be one-sided
     if total_share.is_zero() && (deposits[0].is_zero() || deposits[1].is_zero()) {
         return Err(ContractError::InvalidZeroAmount {});
     }
-    //println!("TRANSFERRING TOKENS");
     increment_pair_balances(
         deps,
         pool_key.clone(),
         [assets[0].amount, assets[1].amount].to_vec(),
-    );
+    )?;
 
     let mut messages = vec![];
-    for (i, pool) in pools.iter_mut().enumerate() {
-        //println!("{} {}", pool.amount, "the current pool amount");
+    for (i, pool) in pools.iter().enumerate() {
         // If the asset is a token contract, then we need to execute a TransferFrom msg to receive assets
         match &pool.info {
             AssetInfo::Token { contract_addr } => {
@@ -228,25 +271,18 @@ pub fn execute_provide_liquidity(
         }
     }
 
-    let mut new_xp = pools
-        .iter()
-        .enumerate()
-        .map(|(ind, pool)| pool.amount + deposits[ind])
-        .collect_vec();
-    //println!("{:?}", new_xp);
-    new_xp[1] *= config.pool_state.price_state.price_scale;
-    //println!("{:?}", new_xp);
-    let amp_gamma = config.pool_state.get_amp_gamma(&env);
-    let new_d = calc_d(&new_xp, &amp_gamma)?;
-
-    let share = if total_share.is_zero() {
-        //println!("total share is zero");
-        let xcp = get_xcp(new_d, config.pool_state.price_state.price_scale);
-        //println!("{:?}", xcp);
-        let mint_amount = xcp
-            .checked_sub(MINIMUM_LIQUIDITY_AMOUNT.to_decimal256(LP_TOKEN_PRECISION)?)
-            .map_err(|_| ContractError::MinimumLiquidityAmountError {})?;
-        //println!("{:?}", mint_amount);
+    let xs = pools.iter().map(|pool| pool.amount).collect_vec();
+    let curve = pool_curve(deps.storage, &pool_key, &config)?;
+    let (share, slippage) = curve.compute_share(
+        &mut config,
+        &env,
+        &xs,
+        &deposits,
+        total_share,
+        slippage_tolerance,
+    )?;
+
+    if total_share.is_zero() {
         messages.extend(mint_liquidity_token_message(
             deps.querier,
             &config,
@@ -255,70 +291,9 @@ pub fn execute_provide_liquidity(
             MINIMUM_LIQUIDITY_AMOUNT,
             false,
         )?);
-
-        // share cannot become zero after minimum liquidity subtraction
-        if mint_amount.is_zero() {
-            return Err(ContractError::MinimumLiquidityAmountError {});
-        }
-
-        config.pool_state.price_state.xcp_profit_real = Decimal256::one();
-        config.pool_state.price_state.xcp_profit = Decimal256::one();
-
-        mint_amount
-    } else {
-        //println!("total share note zero");
-        //println!("{:?}", pools);
-        let mut old_xp = pools.iter().map(|a| a.amount).collect_vec();
-
-        old_xp[1] *= config.pool_state.price_state.price_scale;
-        //println!("{:?}", old_xp);
-        let old_d = calc_d(&old_xp, &amp_gamma)?;
-        let share = (total_share * new_d / old_d).saturating_sub(total_share);
-
-        let mut ideposits = deposits;
-        ideposits[1] *= config.pool_state.price_state.price_scale;
-
-        share * (Decimal256::one() - calc_provide_fee(&ideposits, &new_xp, &config.pool_params))
-    };
-
-    // calculate accrued share
-    let share_ratio = share / (total_share + share);
-    //println!("share ratio");
-    //println!("{:?}", share_ratio);
-    let balanced_share = [new_xp[0] * share_ratio,
-        new_xp[1] * share_ratio / config.pool_state.price_state.price_scale];
-
-    let assets_diff = [deposits[0].diff(balanced_share[0]),
-        deposits[1].diff(balanced_share[1])];
-
-    let mut slippage = Decimal256::zero();
-
-    //println!("asset difference");
-    //println!("{:?}", balanced_share);
-
-    //println!("{:?}", assets_diff);
-    // If deposit doesn't diverge too much from the balanced share, we don't update the price
-    if assets_diff[0] >= MIN_TRADE_SIZE && assets_diff[1] >= MIN_TRADE_SIZE {
-        //println!("UPDATING PRICE");
-        slippage = assert_slippage_tolerance(
-            &deposits,
-            share,
-            &config.pool_state.price_state,
-            slippage_tolerance,
-        )?;
-
-        let last_price = assets_diff[0] / assets_diff[1];
-        config.pool_state.update_price(
-            &config.pool_params,
-            &env,
-            total_share + share,
-            &new_xp,
-            last_price,
-        )?;
     }
 
     let share_uint128 = share.to_uint(LP_TOKEN_PRECISION)?;
-    ////println!("UPDATING PRICE");
     // Mint LP tokens for the sender or for the receiver (if set)
     let receiver = addr_opt_validate(deps.api, &receiver)?.unwrap_or_else(|| info.sender.clone());
     let auto_stake = auto_stake.unwrap_or(false);
@@ -364,19 +339,17 @@ pub fn execute_withdraw_liquidity(
     info: MessageInfo,
     sender: Addr,
     amount: Uint128,
-    assets: Vec<Asset>,
+    _assets: Vec<Asset>,
 ) -> Result<Response, ContractError> {
-    let pool = generate_key_from_assets(&assets);
+    // The LP token identifies the pool, since a pair may have several pools
+    let pool = LP_TOKEN_POOLS
+        .may_load(deps.storage, &info.sender)?
+        .ok_or(ContractError::Unauthorized {})?;
+    assert_withdraw_allowed(deps.storage, &pool)?;
     let mut config = POOLS.load(deps.storage, pool.clone())?;
 
-    if info.sender != config.pair_info.liquidity_token {
-        return Err(ContractError::Unauthorized {});
-    }
-
     let precisions = Precisions::new(deps.storage)?;
-    let pools = query_pools(deps, &config, &precisions)?;
-
-    decrease_pair_balances(deps, pool.clone(), [amount, amount].to_vec());
+    let pools = query_pools(deps, &pool, &precisions)?;
 
     let total_share = query_supply(&deps.querier, &config.pair_info.liquidity_token)?;
     let mut messages = vec![];
@@ -392,30 +365,33 @@ pub fn execute_withdraw_liquidity(
         return Err(StdError::generic_err("Imbalanced withdraw is currently disabled").into());
     };
     */
-    //println!("CP");
-    // decrease XCP
     let mut xs = pools.iter().map(|a| a.amount).collect_vec();
-
     xs[0] -= refund_assets[0].amount;
     xs[1] -= refund_assets[1].amount;
-    xs[1] *= config.pool_state.price_state.price_scale;
-    let amp_gamma = config.pool_state.get_amp_gamma(&env);
-    let d = calc_d(&xs, &amp_gamma)?;
-    config.pool_state.price_state.xcp_profit_real =
-        get_xcp(d, config.pool_state.price_state.price_scale)
-            / (total_share - amount).to_decimal256(LP_TOKEN_PRECISION)?;
+    pool_curve(deps.storage, &pool, &config)?.after_withdraw(
+        &mut config,
+        &env,
+        &xs,
+        (total_share - amount).to_decimal256(LP_TOKEN_PRECISION)?,
+    )?;
 
     let refund_assets = refund_assets
         .into_iter()
         .map(|asset| {
-            let prec = precisions.get_precision(&asset.info).unwrap();
+            let prec = precisions.get_precision(&asset.info)?;
 
             Ok(Asset {
                 info: asset.info,
                 amount: asset.amount.to_uint(prec)?,
             })
         })
-        .collect::<StdResult<Vec<_>>>()?;
+        .collect::<Result<Vec<_>, ContractError>>()?;
+
+    decrease_pair_balances(
+        deps,
+        pool.clone(),
+        refund_assets.iter().map(|asset| asset.amount).collect(),
+    )?;
 
     messages.extend(
         refund_assets
@@ -447,7 +423,7 @@ pub fn execute_withdraw_liquidity(
         }
     }
 
-    //CONFIG.save(deps.storage, &config)?;
+    POOLS.save(deps.storage, pool, &config)?;
 
     Ok(Response::new().add_messages(messages).add_attributes(vec![
         attr("action", "withdraw_liquidity"),
@@ -461,9 +437,12 @@ pub fn execute_withdraw_liquidity(
 pub fn execute_create_pair(
     deps: &mut DepsMut,
     env: Env,
-    _info: MessageInfo,
+    info: MessageInfo,
     init_params: Option<Binary>,
     asset_infos: Vec<AssetInfo>,
+    pair_type: Option<PairType>,
+    token_code_id: Option<u64>,
+    lp_token: Option<LpTokenInfo>,
 ) -> Result<Response, ContractError> {
     if asset_infos.len() != 2 {
         return Err(StdError::generic_err("asset_infos must contain exactly two elements").into());
@@ -471,52 +450,102 @@ pub fn execute_create_pair(
 
     check_asset_infos(deps.api, &asset_infos)?;
 
-    let params: ConcentratedPoolParams =
-        from_json(init_params.ok_or(ContractError::InitParamsNotFound {})?)?;
-
-    if params.price_scale.is_zero() {
-        return Err(StdError::generic_err("Initial price scale can not be zero").into());
+    // Pairs may hold several pools, e.g. with different fees, each with its own id
+    let pool_id = POOL_COUNT.may_load(deps.storage)?.unwrap_or_default() + 1;
+    POOL_COUNT.save(deps.storage, &pool_id)?;
+    PAIR_POOLS.update(deps.storage, pair_key(&asset_infos), |pool_ids| -> StdResult<_> {
+        let mut pool_ids = pool_ids.unwrap_or_default();
+        pool_ids.push(pool_id);
+        Ok(pool_ids)
+    })?;
+    let key = pool_key(&asset_infos, pool_id);
+
+    let manager_config = CONFIG.load(deps.storage)?;
+    // Only the manager owner can pick another LP token code
+    let token_code_id = match token_code_id {
+        Some(code_id) if code_id != manager_config.token_code_id => {
+            if info.sender != manager_config.owner {
+                return Err(ContractError::Unauthorized {});
+            }
+            code_id
+        }
+        _ => manager_config.token_code_id,
+    };
+    for asset_info in &asset_infos {
+        if let AssetInfo::NativeToken { denom } = asset_info {
+            if !manager_config.allowed_denoms.contains(denom) {
+                return Err(ContractError::DenomNotAllowed(denom.clone()));
+            }
+        }
     }
 
-    Precisions::store_precisions(deps.branch(), &asset_infos)?;
+    let init_params = init_params.ok_or(ContractError::InitParamsNotFound {})?;
+    let pair_type =
+        pair_type.unwrap_or_else(|| PairType::Custom(CONCENTRATED_PAIR_TYPE.to_string()));
+    let (pool_params, pool_state, track_asset_balances) = match &pair_type {
+        PairType::Xyk {} => {
+            let params: XykPoolParams = from_json(&init_params)?;
+            save_curve_params(deps, &key, params.fee_bps, None)?;
 
-    let mut pool_params = PoolParams::default();
-    pool_params.update_params(UpdatePoolParams {
-        mid_fee: Some(params.mid_fee),
-        out_fee: Some(params.out_fee),
-        fee_gamma: Some(params.fee_gamma),
-        repeg_profit_threshold: Some(params.repeg_profit_threshold),
-        min_price_scale_delta: Some(params.min_price_scale_delta),
-        ma_half_time: Some(params.ma_half_time),
-    })?;
+            (PoolParams::default(), curve_pool_state(&env), params.track_asset_balances)
+        }
+        PairType::Stable {} => {
+            let params: StablePoolParams = from_json(&init_params)?;
+            save_curve_params(deps, &key, params.fee_bps, Some(params.amp))?;
 
-    let pool_state = PoolState {
-        initial: AmpGamma::default(),
-        future: AmpGamma::new(params.amp, params.gamma)?,
-        future_time: env.block.time.seconds(),
-        initial_time: 0,
-        price_state: PriceState {
-            oracle_price: params.price_scale.into(),
-            last_price: params.price_scale.into(),
-            price_scale: params.price_scale.into(),
-            last_price_update: env.block.time.seconds(),
-            xcp_profit: Decimal256::zero(),
-            xcp_profit_real: Decimal256::zero(),
-        },
+            (PoolParams::default(), curve_pool_state(&env), params.track_asset_balances)
+        }
+        PairType::Custom(name) if name == CONCENTRATED_PAIR_TYPE => {
+            let params: ConcentratedPoolParams = from_json(&init_params)?;
+
+            if params.price_scale.is_zero() {
+                return Err(StdError::generic_err("Initial price scale can not be zero").into());
+            }
+
+            let mut pool_params = PoolParams::default();
+            pool_params.update_params(UpdatePoolParams {
+                mid_fee: Some(params.mid_fee),
+                out_fee: Some(params.out_fee),
+                fee_gamma: Some(params.fee_gamma),
+                repeg_profit_threshold: Some(params.repeg_profit_threshold),
+                min_price_scale_delta: Some(params.min_price_scale_delta),
+                ma_half_time: Some(params.ma_half_time),
+            })?;
+
+            let pool_state = PoolState {
+                initial: AmpGamma::default(),
+                future: AmpGamma::new(params.amp, params.gamma)?,
+                future_time: env.block.time.seconds(),
+                initial_time: 0,
+                price_state: PriceState {
+                    oracle_price: params.price_scale.into(),
+                    last_price: params.price_scale.into(),
+                    price_scale: params.price_scale.into(),
+                    last_price_update: env.block.time.seconds(),
+                    xcp_profit: Decimal256::zero(),
+                    xcp_profit_real: Decimal256::zero(),
+                },
+            };
+
+            (pool_params, pool_state, params.track_asset_balances)
+        }
+        pair_type => return Err(ContractError::UnsupportedPoolType(pair_type.to_string())),
     };
 
+    Precisions::store_precisions(deps.branch(), &asset_infos)?;
+
     let config = Config {
         pair_info: PairInfo {
             contract_addr: env.contract.address.clone(),
             liquidity_token: Addr::unchecked(""),
             asset_infos: asset_infos.clone(),
-            pair_type: PairType::Custom("concentrated".to_string()),
+            pair_type,
         },
-        factory_addr: Addr::unchecked(DUMMY_ADDRESS),
+        factory_addr: env.contract.address.clone(),
         pool_params,
         pool_state,
         owner: None,
-        track_asset_balances: params.track_asset_balances.unwrap_or_default(),
+        track_asset_balances: track_asset_balances.unwrap_or_default(),
         fee_share: None,
     };
     let mut balances = Vec::new();
@@ -534,45 +563,278 @@ pub fn execute_create_pair(
         }
     }
 
-    let key = generate_key_from_asset_info(&asset_infos);
-    //println!("{:?}", key);
     POOLS.save(deps.storage, key.clone(), &config)?;
+    index_pool_assets(deps.storage, &config.pair_info.asset_infos, &key, pool_id)?;
     PAIR_BALANCES.save(deps.storage, key.clone(), &balances)?;
     //BufferManager::init(deps.storage, OBSERVATIONS, OBSERVATIONS_SIZE)?;
 
-    let token_name = format_lp_token_name(&asset_infos, &deps.querier)?;
+    let lp_token = lp_token.unwrap_or(LpTokenInfo {
+        name: None,
+        symbol: None,
+        marketing: None,
+    });
+    let token_name = match lp_token.name {
+        Some(name) => name,
+        None => format_lp_token_name(&asset_infos, &deps.querier)?,
+    };
+
+    let reply_id = LAST_REPLY_ID
+        .may_load(deps.storage)?
+        .map_or(INSTANTIATE_TOKEN_REPLY_ID_START, |id| id + 1);
+    LAST_REPLY_ID.save(deps.storage, &reply_id)?;
+    QUEUED_MINTS.save(deps.storage, reply_id, &key)?;
 
     // Create LP token
     let sub_msg = SubMsg::reply_on_success(
         wasm_instantiate(
-            2,
+            token_code_id,
             &TokenInstantiateMsg {
                 name: token_name,
-                symbol: "pcLP".to_string(),
+                symbol: lp_token.symbol.unwrap_or_else(|| LP_TOKEN_SYMBOL.to_string()),
                 decimals: LP_TOKEN_PRECISION,
                 initial_balances: vec![],
                 mint: Some(MinterResponse {
                     minter: env.contract.address.to_string(),
                     cap: None,
                 }),
-                marketing: None,
+                marketing: lp_token.marketing,
             },
             vec![],
             String::from("Pina Colada LP token"),
         )?,
-        INSTANTIATE_TOKEN_REPLY_ID,
+        reply_id,
     );
-    QUEUED_MINT.save(deps.storage, &key)?;
-    Ok(Response::new().add_submessage(sub_msg).add_attribute(
-        "asset_balances_tracking".to_owned(),
-        if config.track_asset_balances {
-            "enabled"
-        } else {
-            "disabled"
+    Ok(Response::new()
+        .add_submessage(sub_msg)
+        .add_attribute("pool_id", pool_id.to_string())
+        .add_attribute("pool_key", key)
+        .add_attribute(
+            "asset_balances_tracking".to_owned(),
+            if config.track_asset_balances {
+                "enabled"
+            } else {
+                "disabled"
+            }
+            .to_owned(),
+        ))
+}
+/// Changes the status of a pool. Only the manager owner or the guardian can do this.
+pub fn execute_set_pool_status(
+    deps: &mut DepsMut,
+    info: MessageInfo,
+    pool_key: String,
+    status: PoolStatus,
+) -> Result<Response, ContractError> {
+    let manager_config = CONFIG.load(deps.storage)?;
+    if info.sender != manager_config.owner && Some(&info.sender) != manager_config.guardian.as_ref()
+    {
+        return Err(ContractError::Unauthorized {});
+    }
+    if !POOLS.has(deps.storage, pool_key.clone()) {
+        return Err(ContractError::PoolNotFound(pool_key));
+    }
+
+    POOL_STATUS.save(deps.storage, pool_key.clone(), &status)?;
+
+    Ok(Response::new().add_attributes([
+        attr("action", "set_pool_status"),
+        attr("pool_key", pool_key),
+        attr("status", format!("{status:?}")),
+    ]))
+}
+
+/// Updates the global manager configuration. Only the owner can execute this.
+#[allow(clippy::too_many_arguments)]
+pub fn execute_update_manager_config(
+    deps: &mut DepsMut,
+    info: MessageInfo,
+    owner: Option<String>,
+    token_code_id: Option<u64>,
+    allowed_denoms: Option<Vec<String>>,
+    fee_address: Option<String>,
+    guardian: Option<String>,
+    flash_loan_fee_bps: Option<u16>,
+    max_referral_commission: Option<Decimal>,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut attrs = vec![attr("action", "update_manager_config")];
+    if let Some(owner) = owner {
+        config.owner = deps.api.addr_validate(&owner)?;
+        attrs.push(attr("owner", owner));
+    }
+    if let Some(code_id) = token_code_id {
+        config.token_code_id = code_id;
+        attrs.push(attr("token_code_id", code_id.to_string()));
+    }
+    if let Some(denoms) = allowed_denoms {
+        attrs.push(attr("allowed_denoms", denoms.join(",")));
+        config.allowed_denoms = denoms;
+    }
+    if let Some(fee_address) = fee_address {
+        config.fee_address = Some(deps.api.addr_validate(&fee_address)?);
+        attrs.push(attr("fee_address", fee_address));
+    }
+    if let Some(guardian) = guardian {
+        config.guardian = Some(deps.api.addr_validate(&guardian)?);
+        attrs.push(attr("guardian", guardian));
+    }
+    if let Some(fee_bps) = flash_loan_fee_bps {
+        assert_flash_loan_fee(fee_bps)?;
+        config.flash_loan_fee_bps = fee_bps;
+        attrs.push(attr("flash_loan_fee_bps", fee_bps.to_string()));
+    }
+    if let Some(commission) = max_referral_commission {
+        assert_max_referral_commission(commission)?;
+        config.max_referral_commission = commission;
+        attrs.push(attr("max_referral_commission", commission.to_string()));
+    }
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+pub(crate) fn assert_max_referral_commission(commission: Decimal) -> Result<(), ContractError> {
+    if commission > MAX_REFERRAL_COMMISSION {
+        return Err(ContractError::ReferralCommissionTooHigh {
+            commission,
+            max_commission: MAX_REFERRAL_COMMISSION,
+        });
+    }
+    Ok(())
+}
+
+/// Validates and stores the fee and amp of an xyk or stableswap pool.
+fn save_curve_params(
+    deps: &mut DepsMut,
+    pool_key: &str,
+    fee_bps: u16,
+    amp: Option<u64>,
+) -> Result<(), ContractError> {
+    if fee_bps == 0 || fee_bps > MAX_FEE_BPS {
+        return Err(ContractError::IncorrectPoolParam(
+            "fee_bps".to_string(),
+            "0".to_string(),
+            MAX_FEE_BPS.to_string(),
+        ));
+    }
+    if let Some(amp) = amp {
+        if amp == 0 || amp > MAX_AMP {
+            return Err(ContractError::IncorrectPoolParam(
+                "amp".to_string(),
+                "0".to_string(),
+                MAX_AMP.to_string(),
+            ));
         }
-        .to_owned(),
-    ))
+    }
+
+    CURVE_PARAMS.save(deps.storage, pool_key.to_string(), &CurveParams { fee_bps, amp })?;
+
+    Ok(())
+}
+
+/// Returns the placeholder PCL state stored for xyk and stableswap pools.
+fn curve_pool_state(env: &Env) -> PoolState {
+    PoolState {
+        initial: AmpGamma::default(),
+        future: AmpGamma::default(),
+        future_time: env.block.time.seconds(),
+        initial_time: 0,
+        price_state: PriceState {
+            price_scale: Decimal256::one(),
+            last_price_update: env.block.time.seconds(),
+            ..PriceState::default()
+        },
+    }
+}
+
+/// Updates the parameters of a single pool. Only the pool owner, or the manager
+/// owner for pools without one, can execute this.
+///
+/// * **pool_key** key of the pool to update.
+///
+/// * **params** new pool parameters, see [`ConcentratedPoolUpdateParams`].
+pub fn execute_update_config(
+    deps: &mut DepsMut,
+    env: Env,
+    info: MessageInfo,
+    pool_key: String,
+    params: ConcentratedPoolUpdateParams,
+) -> Result<Response, ContractError> {
+    let mut config = load_pool(deps.storage, &pool_key)?;
+    let manager_config = CONFIG.load(deps.storage)?;
+    assert_concentrated(&config)?;
+
+    if info.sender != *config.owner.as_ref().unwrap_or(&manager_config.owner) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut attrs = vec![attr("action", "update_config"), attr("pool_key", &pool_key)];
+    match params {
+        ConcentratedPoolUpdateParams::Update(update_params) => {
+            attrs.extend(config.pool_params.update_params(update_params)?);
+            attrs.push(attr("update_type", "update_params"));
+        }
+        ConcentratedPoolUpdateParams::Promote(promote_params) => {
+            config
+                .pool_state
+                .promote_params(&env, promote_params)
+                .map_err(|err| match err {
+                    PclError::MaxChangeAssertion(param, max_change) => {
+                        ContractError::MaxChangeAssertion(param, max_change)
+                    }
+                    PclError::MinChangingTimeAssertion {} => {
+                        ContractError::MinChangingTimeAssertion {}
+                    }
+                    err => err.into(),
+                })?;
+            attrs.push(attr("update_type", "promote_params"));
+        }
+        ConcentratedPoolUpdateParams::StopChangingAmpGamma {} => {
+            config.pool_state.stop_promotion(&env);
+            attrs.push(attr("update_type", "stop_changing_amp_gamma"));
+        }
+        ConcentratedPoolUpdateParams::EnableAssetBalancesTracking {} => {
+            if config.track_asset_balances {
+                return Err(
+                    StdError::generic_err("Asset balances tracking is already enabled").into(),
+                );
+            }
+            config.track_asset_balances = true;
+
+            let pools = PAIR_BALANCES.load(deps.storage, pool_key.clone())?;
+            for pool in pools {
+                BALANCES.save(deps.storage, &pool.info, &pool.amount, env.block.height)?;
+            }
+            attrs.push(attr("update_type", "enable_asset_balances_tracking"));
+        }
+        ConcentratedPoolUpdateParams::EnableFeeShare { .. }
+        | ConcentratedPoolUpdateParams::DisableFeeShare => {
+            return Err(
+                StdError::generic_err("Fee sharing is not supported by the pool manager").into(),
+            );
+        }
+    }
+
+    POOLS.save(deps.storage, pool_key, &config)?;
+
+    Ok(Response::new().add_attributes(attrs))
 }
+
+/// Increases, decreases or rebalances a position. Position funds are held by the manager in
+/// escrow: they are not part of the pool reserves, so they never take part in swaps. A position
+/// is created by its first increase and can only be modified by its owner afterwards.
+///
+/// * **Increase** pulls `assets` in and adds them to the position.
+///
+/// * **Decrease** removes `assets` from the position and sends them to the owner.
+///
+/// * **Rebalance** sets the position to `assets`, pulling in every missing amount and sending
+///   every surplus back to the owner.
+#[allow(clippy::too_many_arguments)]
 pub fn execute_modify_position(
     deps: &mut DepsMut,
     env: Env,
@@ -580,236 +842,659 @@ pub fn execute_modify_position(
     assets: Vec<Asset>,
     position_id: String,
     modification_type: PositionModification,
-    slippage_tolerance: Option<Decimal>,
+    _slippage_tolerance: Option<Decimal>,
+    pool_id: Option<u64>,
 ) -> Result<Response, ContractError> {
-    let pool_key = generate_key_from_assets(&assets);
-    let mut config = POOLS.load(deps.storage, pool_key.clone())?;
-    let mut pair_balances = PAIR_BALANCES.load(deps.storage, pool_key.clone())?;
-    
+    let asset_infos = assets.iter().map(|asset| asset.info.clone()).collect_vec();
+    let pool_key = resolve_pool_key(deps.storage, &asset_infos, pool_id)?;
     match modification_type {
-        PositionModification::Increase => {
-            // Check assets and sent funds
-            check_assets(deps.api, &assets)?;
-            info.funds.assert_coins_properly_sent(&assets, &config.pair_info.asset_infos)?;
-            
-            // Increment balances
-            for (i, asset) in assets.iter().enumerate() {
-                pair_balances[i].amount += asset.amount;
-            }
-            
-            // Save updated balances
-            PAIR_BALANCES.save(deps.storage, pool_key, &pair_balances)?;
+        PositionModification::Decrease => assert_withdraw_allowed(deps.storage, &pool_key)?,
+        PositionModification::Increase | PositionModification::Rebalance => {
+            assert_provide_allowed(deps.storage, &pool_key)?
+        }
+    }
+    let config = load_pool(deps.storage, &pool_key)?;
+    check_assets(deps.api, &assets)?;
+    if !assets.iter().map(|asset| &asset.info).all_unique() {
+        return Err(ContractError::DoublingAssets {});
+    }
+
+    let mut position = match POSITIONS.may_load(deps.storage, position_id.clone())? {
+        Some(position) if position.owner != info.sender || position.pool_key != pool_key => {
+            return Err(ContractError::Unauthorized {});
+        }
+        Some(position) => position,
+        None if modification_type == PositionModification::Increase => Position {
+            owner: info.sender.clone(),
+            pool_key: pool_key.clone(),
+            assets: config
+                .pair_info
+                .asset_infos
+                .iter()
+                .map(|info| Asset {
+                    info: info.clone(),
+                    amount: Uint128::zero(),
+                })
+                .collect(),
+            last_modified_block: env.block.height,
         },
-        PositionModification::Decrease => {
-            // Verify the decrease amounts are valid
-            for (i, asset) in assets.iter().enumerate() {
-                if asset.amount > pair_balances[i].amount {
+        None => return Err(ContractError::PositionNotFound(position_id)),
+    };
+
+    // Amounts the owner has to deposit and amounts the owner gets back, per asset
+    let mut deposits = vec![];
+    let mut refunds = vec![];
+    for asset in &assets {
+        let held = position
+            .assets
+            .iter_mut()
+            .find(|held| held.info == asset.info)
+            .ok_or_else(|| ContractError::InvalidAsset(asset.info.to_string()))?;
+        let (deposit, refund) = match modification_type {
+            PositionModification::Increase => (asset.amount, Uint128::zero()),
+            PositionModification::Decrease => {
+                if asset.amount > held.amount {
                     return Err(ContractError::InsufficientLiquidity {});
                 }
-                pair_balances[i].amount -= asset.amount;
-            }
-            
-            // Save updated balances
-            PAIR_BALANCES.save(deps.storage, pool_key, &pair_balances)?;
-            
-            // Send assets back to user
-            let messages: Vec<CosmosMsg> = assets
-                .iter()
-                .map(|asset| asset.into_msg(&info.sender))
-                .collect::<StdResult<_>>()?;
-        },
-        PositionModification::Rebalance => {
-            let total_value = pair_balances.iter()
-                .map(|asset| asset.amount)
-                .sum::<Uint128>();
-
-            // Check if new allocation maintains total value
-            let new_total = assets.iter()
-                .map(|asset| asset.amount)
-                .sum::<Uint128>();
-
-            if new_total != total_value {
-                return Err(ContractError::InvalidRebalance {});
+                (Uint128::zero(), asset.amount)
             }
+            PositionModification::Rebalance => (
+                asset.amount.saturating_sub(held.amount),
+                held.amount.saturating_sub(asset.amount),
+            ),
+        };
+        held.amount = held.amount.checked_add(deposit)?.checked_sub(refund)?;
+        deposits.push(Asset {
+            info: asset.info.clone(),
+            amount: deposit,
+        });
+        refunds.push(Asset {
+            info: asset.info.clone(),
+            amount: refund,
+        });
+    }
 
-            // Update balances
-            for (i, asset) in assets.iter().enumerate() {
-                pair_balances[i].amount = asset.amount;
-            }
-            
-            PAIR_BALANCES.save(deps.storage, pool_key, &pair_balances)?;
+    info.funds
+        .assert_coins_properly_sent(&deposits, &config.pair_info.asset_infos)?;
+    let mut messages: Vec<CosmosMsg> = vec![];
+    for deposit in deposits.iter().filter(|deposit| !deposit.amount.is_zero()) {
+        // Native deposits were already checked against the sent funds
+        if let AssetInfo::Token { contract_addr } = &deposit.info {
+            messages.push(
+                wasm_execute(
+                    contract_addr,
+                    &Cw20ExecuteMsg::TransferFrom {
+                        owner: info.sender.to_string(),
+                        recipient: env.contract.address.to_string(),
+                        amount: deposit.amount,
+                    },
+                    vec![],
+                )?
+                .into(),
+            );
         }
     }
+    for refund in refunds.into_iter().filter(|refund| !refund.amount.is_zero()) {
+        messages.push(send_asset(&info.sender, refund.info, refund.amount)?);
+    }
+
+    position.last_modified_block = env.block.height;
+    if position.assets.iter().all(|held| held.amount.is_zero()) {
+        POSITIONS.remove(deps.storage, position_id.clone());
+    } else {
+        POSITIONS.save(deps.storage, position_id.clone(), &position)?;
+    }
 
-    Ok(Response::new().add_attributes(vec![
+    Ok(Response::new().add_messages(messages).add_attributes(vec![
         attr("action", "modify_position"),
         attr("position_id", position_id),
+        attr("pool_key", pool_key),
         attr("modification_type", format!("{:?}", modification_type)),
     ]))
 }
 
-// Helper functions
+#[allow(clippy::too_many_arguments)]
+pub fn execute_swap_operations(
+    deps: &mut DepsMut,
+    env: Env,
+    sender: Addr,
+    operations: Vec<SwapOperation>,
+    input_amount: Uint128,
+    minimum_receive: Option<Uint128>,
+    minimum_receive_per_hop: Option<Vec<Uint128>>,
+    to: Option<String>,
+    max_spread: Option<Decimal>,
+    belief_price: Option<Decimal>,
+    callback: Option<SwapCallback>,
+    referral_address: Option<String>,
+    referral_commission: Option<Decimal>,
+) -> Result<Response, ContractError> {
+    assert_operations(deps.api, &operations)?;
+    let referral = assert_referral(deps, referral_address, referral_commission)?;
+    if let Some(minimums) = &minimum_receive_per_hop {
+        if minimums.len() != operations.len() {
+            return Err(ContractError::InvalidHopMinimums {
+                operations: operations.len(),
+                minimums: minimums.len(),
+            });
+        }
+    }
+
+    let target_asset_info = operations[operations.len() - 1].ask_asset_info.clone();
+    // A direct swap checks the belief price in the pool, a route checks it end to end
+    let is_route = operations.len() > 1;
+    let hop_belief_price = if is_route { None } else { belief_price };
+    let return_amount = swap_route(
+        deps,
+        &env,
+        operations,
+        input_amount,
+        hop_belief_price,
+        max_spread,
+        minimum_receive_per_hop.as_deref(),
+    )?;
+
+    if is_route && belief_price.is_some() {
+        assert_max_spread(
+            belief_price,
+            max_spread,
+            input_amount,
+            return_amount,
+            Uint128::zero(),
+        )?;
+    }
+    let (referral_message, referral_attrs, return_amount) =
+        take_referral(referral, &target_asset_info, return_amount)?;
+    if let Some(minimum_receive) = minimum_receive {
+        assert_minimum_receive(minimum_receive, return_amount)?;
+    }
+
+    let (_, messages) = deliver_return(
+        deps.api,
+        &sender,
+        to,
+        callback,
+        Asset {
+            info: target_asset_info,
+            amount: return_amount,
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_messages(referral_message)
+        .add_messages(messages)
+        .add_attributes(referral_attrs))
+}
+
+/// Checks a referral commission against the manager maximum. Returns the referrer and its
+/// commission, or `None` if no commission is taken.
+fn assert_referral(
+    deps: &DepsMut,
+    referral_address: Option<String>,
+    referral_commission: Option<Decimal>,
+) -> Result<Option<(Addr, Decimal)>, ContractError> {
+    let commission = referral_commission.unwrap_or_default();
+    let Some(referral_address) = referral_address else {
+        if !commission.is_zero() {
+            return Err(ContractError::ReferralAddressMissing {});
+        }
+        return Ok(None);
+    };
+    let max_commission = CONFIG.load(deps.storage)?.max_referral_commission;
+    if commission > max_commission {
+        return Err(ContractError::ReferralCommissionTooHigh {
+            commission,
+            max_commission,
+        });
+    }
+    if commission.is_zero() {
+        return Ok(None);
+    }
 
-fn calculate_deposits(
-    assets: &[Asset],
-    precisions: &Precisions,
-) -> Result<Vec<Decimal256>, ContractError> {
-    assets
-        .iter()
-        .map(|asset| {
-            let precision = precisions.get_precision(&asset.info)?;
-            Ok(Decimal256::with_precision(asset.amount, precision)?)
-        })
-        .collect()
+    Ok(Some((deps.api.addr_validate(&referral_address)?, commission)))
+}
+
+/// The share of a swap return paid to a referrer.
+pub(crate) fn referral_amount(return_amount: Uint128, commission: Decimal) -> Uint128 {
+    return_amount * commission
+}
+
+/// Takes the referral commission out of a swap return. Returns the message paying the
+/// referrer, the attributes reporting it and the return left for the recipient.
+fn take_referral(
+    referral: Option<(Addr, Decimal)>,
+    asset_info: &AssetInfo,
+    return_amount: Uint128,
+) -> StdResult<(Option<CosmosMsg>, Vec<Attribute>, Uint128)> {
+    let Some((referrer, commission)) = referral else {
+        return Ok((None, vec![], return_amount));
+    };
+    let amount = referral_amount(return_amount, commission);
+    if amount.is_zero() {
+        return Ok((None, vec![], return_amount));
+    }
+
+    Ok((
+        Some(send_asset(&referrer, asset_info.clone(), amount)?),
+        vec![
+            attr("referral_address", referrer),
+            attr("referral_amount", amount),
+        ],
+        return_amount - amount,
+    ))
+}
+
+/// Builds the messages delivering a swap return to `to`, or to the sender if omitted.
+/// With a callback the return goes to the callback contract instead, which is then
+/// executed with the callback payload and the received asset.
+fn deliver_return(
+    api: &dyn Api,
+    sender: &Addr,
+    to: Option<String>,
+    callback: Option<SwapCallback>,
+    asset: Asset,
+) -> Result<(Addr, Vec<CosmosMsg>), ContractError> {
+    let Some(callback) = callback else {
+        let recipient = addr_opt_validate(api, &to)?.unwrap_or_else(|| sender.clone());
+        let message = send_asset(&recipient, asset.info, asset.amount)?;
+        return Ok((recipient, vec![message]));
+    };
+    if to.is_some() {
+        return Err(ContractError::CallbackWithRecipient {});
+    }
+
+    let contract = api.addr_validate(&callback.contract)?;
+    let transfer = send_asset(&contract, asset.info.clone(), asset.amount)?;
+    let call = wasm_execute(
+        &contract,
+        &SwapCallbackExecuteMsg::SwapCallback(SwapCallbackMsg {
+            sender: sender.to_string(),
+            asset,
+            msg: callback.msg,
+        }),
+        vec![],
+    )?;
+
+    Ok((contract, vec![transfer, call.into()]))
+}
+
+/// Builds the message sending a swap return to the recipient.
+pub(crate) fn send_asset(recipient: &Addr, asset_info: AssetInfo, amount: Uint128) -> StdResult<CosmosMsg> {
+    Ok(match asset_info {
+        AssetInfo::Token { contract_addr } => CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: contract_addr.to_string(),
+            msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: recipient.to_string(),
+                amount,
+            })?,
+            funds: vec![],
+        }),
+        AssetInfo::NativeToken { denom } => send_native(recipient, &denom, amount)?,
+    })
 }
 
-fn calculate_shares_from_deposits(
-    deposits: &[Decimal256],
-    config: &Config,
+/// Swaps `amount` through every operation in turn and returns the final output.
+/// Each hop is checked against its entry in `minimum_receive_per_hop`, if given.
+pub(crate) fn swap_route(
+    deps: &mut DepsMut,
     env: &Env,
-    existing_shares: Uint128,
-    slippage_tolerance: Option<Decimal>,
+    operations: Vec<SwapOperation>,
+    amount: Uint128,
+    belief_price: Option<Decimal>,
+    max_spread: Option<Decimal>,
+    minimum_receive_per_hop: Option<&[Uint128]>,
 ) -> Result<Uint128, ContractError> {
-    // Implement share calculation logic based on your AMM model
-    // This is a simplified example
-    let total_deposit_value = deposits.iter().sum::<Decimal256>();
-    let new_shares = total_deposit_value
-        .to_uint(LP_TOKEN_PRECISION)?;
-    
-    // Check slippage if specified
-    if let Some(max_slippage) = slippage_tolerance {
-        // Implement slippage check
+    let mut return_amount = amount;
+
+    for (i, operation) in operations.into_iter().enumerate() {
+        let pool_key = resolve_pool_key(
+            deps.storage,
+            &[operation.offer_asset_info.clone(), operation.ask_asset_info],
+            operation.pool_id,
+        )?;
+        if !pool_status(deps.storage, &pool_key)?.allows_swaps() {
+            return Err(ContractError::SwapsDisabled(pool_key));
+        }
+
+        let offer_asset = Asset {
+            info: operation.offer_asset_info,
+            amount: return_amount,
+        };
+        return_amount = swap_internal(deps, env, pool_key, offer_asset, belief_price, max_spread)?
+            .return_amount;
+
+        if let Some(minimums) = minimum_receive_per_hop {
+            assert_minimum_receive(minimums[i], return_amount)?;
+        }
     }
-    
-    Ok(new_shares)
+
+    Ok(return_amount)
 }
 
-fn calculate_withdrawal_shares(
-    assets: &[Asset],
-    position: &Position,
-) -> Result<Uint128, ContractError> {
-    // Calculate proportional shares to withdraw based on requested assets
-    let mut max_ratio = Decimal::zero();
-    for (i, asset) in assets.iter().enumerate() {
-        let ratio = Decimal::from_ratio(asset.amount, position.assets[i].amount);
-        if ratio > max_ratio {
-            max_ratio = ratio;
+/// Checks the swap spread against `max_spread`. With a belief price the spread is measured
+/// from the expected return, otherwise from the spread reported by the pool curve.
+/// Swaps giving neither a belief price nor a max spread are not checked, and a belief price
+/// alone is checked against [`DEFAULT_SLIPPAGE`].
+fn assert_max_spread(
+    belief_price: Option<Decimal>,
+    max_spread: Option<Decimal>,
+    offer_amount: Uint128,
+    return_amount: Uint128,
+    spread_amount: Uint128,
+) -> Result<(), ContractError> {
+    if belief_price.is_none() && max_spread.is_none() {
+        return Ok(());
+    }
+    let max_spread = max_spread.map(Decimal256::from).unwrap_or(DEFAULT_SLIPPAGE);
+    if max_spread > MAX_ALLOWED_SLIPPAGE {
+        return Err(ContractError::AllowedSpreadAssertion {});
+    }
+
+    let exceeded = match belief_price {
+        Some(belief_price) => {
+            let expected_return = offer_amount
+                * belief_price.inv().ok_or_else(|| {
+                    StdError::generic_err("Invalid belief_price. Check the input values.")
+                })?;
+            let spread_amount = expected_return.saturating_sub(return_amount);
+
+            return_amount < expected_return
+                && Decimal256::from_ratio(spread_amount, expected_return) > max_spread
         }
+        None => Decimal256::from_ratio(spread_amount, return_amount + spread_amount) > max_spread,
+    };
+
+    if exceeded {
+        return Err(ContractError::MaxSpreadExceeded {
+            realised_price: Decimal::checked_from_ratio(offer_amount, return_amount)
+                .unwrap_or(Decimal::MAX),
+        });
     }
-    
-    Ok(max_ratio.to_decimal256(6u8).mul(position.total_shares.to_decimal256(6u8)).to_uint_floor())
+
+    Ok(())
 }
 
-fn calculate_rebalance_amounts(
-    current_assets: &[Asset],
-    target_assets: &[Asset],
-    config: &Config,
-    slippage_tolerance: Option<Decimal>,
-) -> Result<(Vec<Asset>, Vec<Asset>), ContractError> {
-    // Calculate which assets need to be swapped to achieve target ratios
-    // This is a simplified implementation
-    let mut remove_assets = vec![];
-    let mut add_assets = vec![];
-    
-    for (i, target) in target_assets.iter().enumerate() {
-        let current = &current_assets[i];
-        if target.amount > current.amount {
-            add_assets.push(Asset {
-                info: target.info.clone(),
-                amount: target.amount - current.amount,
-            });
-        } else if target.amount < current.amount {
-            remove_assets.push(Asset {
-                info: target.info.clone(),
-                amount: current.amount - target.amount,
-            });
+pub(crate) fn assert_minimum_receive(minimum_receive: Uint128, amount: Uint128) -> Result<(), ContractError> {
+    if amount < minimum_receive {
+        return Err(ContractError::AssertionMinimumReceive {
+            receive: minimum_receive,
+            amount,
+        });
+    }
+    Ok(())
+}
+
+/// Executes an astroport pair style swap in the pool holding the offer and ask assets and
+/// sends the return to `to`, or the sender if not set.
+#[allow(clippy::too_many_arguments)]
+pub fn execute_swap(
+    deps: &mut DepsMut,
+    env: Env,
+    sender: Addr,
+    offer_asset: Asset,
+    ask_asset_info: AssetInfo,
+    pool_id: Option<u64>,
+    belief_price: Option<Decimal>,
+    max_spread: Option<Decimal>,
+    to: Option<String>,
+    callback: Option<SwapCallback>,
+    referral_address: Option<String>,
+    referral_commission: Option<Decimal>,
+) -> Result<Response, ContractError> {
+    offer_asset.info.check(deps.api)?;
+    ask_asset_info.check(deps.api)?;
+    if offer_asset.info.equal(&ask_asset_info) {
+        return Err(ContractError::DoublingAssets {});
+    }
+
+    let pool_key = resolve_pool_key(
+        deps.storage,
+        &[offer_asset.info.clone(), ask_asset_info.clone()],
+        pool_id,
+    )?;
+    if !pool_status(deps.storage, &pool_key)?.allows_swaps() {
+        return Err(ContractError::SwapsDisabled(pool_key));
+    }
+    let referral = assert_referral(deps, referral_address, referral_commission)?;
+
+    let swap = swap_internal(
+        deps,
+        &env,
+        pool_key.clone(),
+        offer_asset.clone(),
+        belief_price,
+        max_spread,
+    )?;
+
+    let (referral_message, referral_attrs, return_amount) =
+        take_referral(referral, &ask_asset_info, swap.return_amount)?;
+    let (receiver, messages) = deliver_return(
+        deps.api,
+        &sender,
+        to,
+        callback,
+        Asset {
+            info: ask_asset_info.clone(),
+            amount: return_amount,
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_messages(referral_message)
+        .add_messages(messages)
+        .add_attributes(referral_attrs)
+        .add_attributes(vec![
+        attr("action", "swap"),
+        attr("sender", sender),
+        attr("receiver", receiver),
+        attr("pool_key", pool_key),
+        attr("offer_asset", offer_asset.info.to_string()),
+        attr("ask_asset", ask_asset_info.to_string()),
+        attr("offer_amount", offer_asset.amount),
+        attr("return_amount", swap.return_amount),
+        attr("spread_amount", swap.spread_amount),
+        attr("commission_amount", swap.commission_amount),
+    ]))
+}
+
+/// Swaps `offer_asset` along the best route returned by [`find_routes`].
+#[allow(clippy::too_many_arguments)]
+pub fn execute_swap_best_route(
+    deps: &mut DepsMut,
+    env: Env,
+    sender: Addr,
+    offer_asset: Asset,
+    ask_asset_info: AssetInfo,
+    max_hops: Option<u32>,
+    minimum_receive: Option<Uint128>,
+    to: Option<String>,
+    max_spread: Option<Decimal>,
+) -> Result<Response, ContractError> {
+    let route = find_routes(
+        deps.as_ref(),
+        env.clone(),
+        offer_asset.info.clone(),
+        ask_asset_info.clone(),
+        offer_asset.amount,
+        max_hops,
+    )?
+    .into_iter()
+    .next()
+    .ok_or_else(|| ContractError::NoRouteFound {
+        offer_asset: offer_asset.info.to_string(),
+        ask_asset: ask_asset_info.to_string(),
+    })?;
+    let hops = route.operations.len();
+
+    Ok(execute_swap_operations(
+        deps,
+        env,
+        sender,
+        route.operations,
+        offer_asset.amount,
+        minimum_receive,
+        None,
+        to,
+        max_spread,
+        None,
+        None,
+        None,
+        None,
+    )?
+    .add_attributes(vec![
+        attr("action", "swap_best_route"),
+        attr("hops", hops.to_string()),
+    ]))
+}
+
+/// Splits `input_amount` across `routes` by weight and swaps each share along its route.
+/// The outputs are summed and checked against `minimum_receive` as a whole.
+#[allow(clippy::too_many_arguments)]
+pub fn execute_split_swap(
+    deps: &mut DepsMut,
+    env: Env,
+    sender: Addr,
+    routes: Vec<SplitRoute>,
+    input_amount: Uint128,
+    minimum_receive: Option<Uint128>,
+    to: Option<String>,
+    max_spread: Option<Decimal>,
+) -> Result<Response, ContractError> {
+    let (_, ask_asset_info) = assert_split_routes(deps.api, &routes)?;
+    let recipient = addr_opt_validate(deps.api, &to)?.unwrap_or(sender);
+    let weights = routes.iter().map(|route| route.weight).collect_vec();
+    let amounts = split_amount(input_amount, &weights);
+    let route_count = routes.len();
+
+    let mut return_amount = Uint128::zero();
+    for (route, amount) in routes.into_iter().zip(amounts) {
+        if amount.is_zero() {
+            continue;
         }
+        return_amount +=
+            swap_route(deps, &env, route.operations, amount, None, max_spread, None)?;
     }
-    
-    Ok((remove_assets, add_assets))
+
+    if let Some(minimum_receive) = minimum_receive {
+        assert_minimum_receive(minimum_receive, return_amount)?;
+    }
+
+    let message = send_asset(&recipient, ask_asset_info, return_amount)?;
+
+    Ok(Response::new().add_message(message).add_attributes(vec![
+        attr("action", "split_swap"),
+        attr("routes", route_count.to_string()),
+        attr("return_amount", return_amount),
+    ]))
+}
+
+/// Checks every route of a split swap and that they all swap the same offer asset
+/// to the same ask asset. Returns the offer and ask assets.
+pub(crate) fn assert_split_routes(
+    api: &dyn Api,
+    routes: &[SplitRoute],
+) -> Result<(AssetInfo, AssetInfo), ContractError> {
+    let first = routes.first().ok_or(ContractError::MustProvideOperations {})?;
+    if routes.iter().map(|route| route.operations.len()).sum::<usize>() > MAX_SWAP_OPERATIONS {
+        return Err(ContractError::SwapLimitExceeded {});
+    }
+    if routes.iter().all(|route| route.weight == 0) {
+        return Err(ContractError::ZeroSplitWeights {});
+    }
+
+    assert_operations(api, &first.operations)?;
+    let offer_asset_info = first.operations[0].offer_asset_info.clone();
+    let ask_asset_info = first.operations[first.operations.len() - 1].ask_asset_info.clone();
+    for route in routes {
+        assert_operations(api, &route.operations)?;
+        let route_offer = &route.operations[0].offer_asset_info;
+        let route_ask = &route.operations[route.operations.len() - 1].ask_asset_info;
+        if !route_offer.equal(&offer_asset_info) || !route_ask.equal(&ask_asset_info) {
+            return Err(ContractError::SplitRouteMismatch {});
+        }
+    }
+
+    Ok((offer_asset_info, ask_asset_info))
 }
 
+/// Splits `amount` proportionally to `weights`. The rounding remainder goes to the last
+/// route with a non-zero weight so that the shares add up to `amount`.
+pub(crate) fn split_amount(amount: Uint128, weights: &[u32]) -> Vec<Uint128> {
+    let total_weight: u64 = weights.iter().map(|weight| *weight as u64).sum();
+    let mut shares = weights
+        .iter()
+        .map(|weight| amount.multiply_ratio(*weight as u64, total_weight))
+        .collect_vec();
+
+    if let Some(last) = weights.iter().rposition(|weight| *weight > 0) {
+        let assigned: Uint128 = shares.iter().sum();
+        shares[last] += amount - assigned;
+    }
+
+    shares
+}
+
+/// Swaps along `operations` for exactly `ask_amount`. The offer needed is reverse simulated
+/// and must not exceed `max_offer` or the `input_amount` received. The unused input and
+/// any output above `ask_amount` are refunded to the sender.
 #[allow(clippy::too_many_arguments)]
-pub fn execute_swap_operations(
+pub fn execute_swap_exact_out(
     deps: &mut DepsMut,
     env: Env,
     sender: Addr,
     operations: Vec<SwapOperation>,
     input_amount: Uint128,
-    _minimum_receive: Option<Uint128>,
+    ask_amount: Uint128,
+    max_offer: Option<Uint128>,
     to: Option<String>,
     max_spread: Option<Decimal>,
 ) -> Result<Response, ContractError> {
     assert_operations(deps.api, &operations)?;
+    let recipient = addr_opt_validate(deps.api, &to)?.unwrap_or_else(|| sender.clone());
+    let offer_asset_info = operations[0].offer_asset_info.clone();
+    let ask_asset_info = operations[operations.len() - 1].ask_asset_info.clone();
+
+    let offer_amount = simulate_reverse_swap_operations(
+        deps.as_ref(),
+        env.clone(),
+        ask_amount,
+        operations.clone(),
+    )?
+    .offer_amount;
+    let max_offer = max_offer.map_or(input_amount, |max_offer| max_offer.min(input_amount));
+    if offer_amount > max_offer {
+        return Err(ContractError::MaxOfferExceeded {
+            offer_amount,
+            max_offer,
+        });
+    }
 
-    let recipient = addr_opt_validate(deps.api, &to)?.unwrap_or(sender);
-    //let _target_asset_info = operations.last().unwrap().get_target_asset_info();
-    let operations_len = operations.len();
-    let mut messages = Vec::new();
-    //initialize
-    let mut return_amount = input_amount;
-
-    for operation in operations.into_iter().enumerate() {
-        let (offer_asset_info, ask_asset_info) =
-            (operation.1.offer_asset_info, operation.1.ask_asset_info);
-        if operation.0 == operations_len - 1 {
-            let pool_key = generate_key_from_asset_info(
-                [offer_asset_info.clone(), ask_asset_info.clone()].as_ref(),
-            );
-            let offer_asset = Asset {
-                info: offer_asset_info.clone(),
-                amount: return_amount,
-            };
-            //println!("{} {}", "POOOOL", pool_key);
-            let return_amount = swap_internal(
-                deps,
-                &env,
-                pool_key,
-                offer_asset,
-                Some(Decimal::MAX),
-                max_spread,
-            )
-            .unwrap();
-            //println!("{} {}", "TRANSFERRING", return_amount);
-
-            match ask_asset_info {
-                AssetInfo::Token { contract_addr } => {
-                    messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
-                        contract_addr: contract_addr.to_string(),
-                        msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
-                            recipient: recipient.to_string(),
-                            amount: return_amount,
-                        })?,
-                        funds: vec![],
-                    }))
-                }
-                AssetInfo::NativeToken { .. } => {
-                    messages.push(send_native(&recipient, return_amount).unwrap())
-                }
-            }
-        } else {
-            let pool_key = generate_key_from_asset_info(
-                [offer_asset_info.clone(), ask_asset_info.clone()].as_ref(),
-            );
-            let offer_asset = Asset {
-                info: offer_asset_info.clone(),
-                amount: return_amount,
-            };
-            //println!("{}", pool_key);
-            let result = swap_internal(
-                deps,
-                &env,
-                pool_key,
-                offer_asset,
-                Some(Decimal::MAX),
-                max_spread,
-            );
+    let return_amount = swap_route(deps, &env, operations, offer_amount, None, max_spread, None)?;
+    assert_minimum_receive(ask_amount, return_amount)?;
 
-            return_amount = result.unwrap();
-        }
+    let mut messages = vec![send_asset(&recipient, ask_asset_info.clone(), ask_amount)?];
+    let surplus = return_amount - ask_amount;
+    if !surplus.is_zero() {
+        messages.push(send_asset(&sender, ask_asset_info, surplus)?);
+    }
+    let refund = input_amount - offer_amount;
+    if !refund.is_zero() {
+        messages.push(send_asset(&sender, offer_asset_info, refund)?);
     }
 
-    Ok(Response::new().add_messages(messages))
+    Ok(Response::new().add_messages(messages).add_attributes(vec![
+        attr("action", "swap_exact_out"),
+        attr("offer_amount", offer_amount),
+        attr("return_amount", ask_amount),
+        attr("refund_amount", refund),
+    ]))
+}
+
+/// The amounts of a swap executed by [`swap_internal`], in the ask asset.
+pub(crate) struct SwapAmounts {
+    pub return_amount: Uint128,
+    pub spread_amount: Uint128,
+    pub commission_amount: Uint128,
 }
 
 /// Updates internal pools and calculated swap outputs The trader must approve the
@@ -826,48 +1511,38 @@ pub fn execute_swap_operations(
 /// * **max_spread** sets the maximum spread of the swap operation.
 ///
 /// * **to** sets the recipient of the swap operation.
-fn swap_internal(
+pub(crate) fn swap_internal(
     deps: &mut DepsMut,
     env: &Env,
     pool_key: String,
     offer_asset: Asset,
     belief_price: Option<Decimal>,
     max_spread: Option<Decimal>,
-) -> Result<Uint128, ContractError> {
+) -> Result<SwapAmounts, ContractError> {
     let precisions = Precisions::new(deps.storage)?;
     let offer_asset_prec = precisions.get_precision(&offer_asset.info)?;
     let offer_asset_dec = offer_asset.to_decimal_asset(offer_asset_prec)?;
-    let offer_ind = find_asset_index(deps, pool_key.clone(), offer_asset.clone());
+    let offer_ind = find_asset_index(deps, pool_key.clone(), offer_asset.clone())?;
     let ask_ind = 1 ^ offer_ind;
     let mut config = POOLS.load(deps.storage, pool_key.clone())?;
-    increment_asset_balance(deps, pool_key.clone(), offer_ind, offer_asset.amount);
+    let curve = pool_curve(deps.storage, &pool_key, &config)?;
+    increment_asset_balance(deps, pool_key.clone(), offer_ind, offer_asset.amount)?;
 
-    let mut pools = query_pools(deps, &config, &precisions)?;
+    let mut pools = query_pools(deps, &pool_key, &precisions)?;
 
     let ask_asset_prec = precisions.get_precision(&pools[ask_ind].info)?;
-    //println!("{},{}", pools[offer_ind].amount, "SUBTRACTION");
     pools[offer_ind].amount -= offer_asset_dec.amount;
 
     before_swap_check(&pools, offer_asset_dec.amount)?;
 
     let mut xs = pools.iter().map(|asset| asset.amount).collect_vec();
-    //println!("{:?} {}", xs, "XS!!!!!!!!!!");
 
-    let swap_result = compute_swap(
-        &xs,
-        offer_asset_dec.amount,
-        ask_ind,
-        &config,
-        env,
-        Decimal256::zero(),
-        Decimal256::zero(),
-    )?;
+    let swap_result = curve.compute_swap(&config, env, &xs, offer_ind, offer_asset_dec.amount)?;
     xs[offer_ind] += offer_asset_dec.amount;
-    xs[ask_ind] -= swap_result.dy + swap_result.maker_fee + swap_result.share_fee;
+    xs[ask_ind] -= swap_result.return_amount;
 
-    let return_amount = swap_result.dy.to_uint(ask_asset_prec)?;
-    //println!("{:?} {}", return_amount, "RT AMT!!!!!!!!!!");
-    let spread_amount = swap_result.spread_fee.to_uint(ask_asset_prec)?;
+    let return_amount = swap_result.return_amount.to_uint(ask_asset_prec)?;
+    let spread_amount = swap_result.spread_amount.to_uint(ask_asset_prec)?;
     assert_max_spread(
         belief_price,
         max_spread,
@@ -878,21 +1553,16 @@ fn swap_internal(
 
     let total_share = query_supply(&deps.querier, &config.pair_info.liquidity_token)?
         .to_decimal256(LP_TOKEN_PRECISION)?;
-    //println!("DECREASING");
-    decrease_asset_balance(deps, pool_key.clone(), ask_ind, return_amount);
-    // Skip very small trade sizes which could significantly mess up the price due to rounding errors,
-    // especially if token precisions are 18.
-    if (swap_result.dy + swap_result.maker_fee + swap_result.share_fee) >= MIN_TRADE_SIZE
-        && offer_asset_dec.amount >= MIN_TRADE_SIZE
-    {
-        let last_price = swap_result.calc_last_price(offer_asset_dec.amount, offer_ind);
-
-        // update_price() works only with internal representation
-        xs[1] *= config.pool_state.price_state.price_scale;
-        config
-            .pool_state
-            .update_price(&config.pool_params, env, total_share, &xs, last_price)?;
-    }
+    decrease_asset_balance(deps, pool_key.clone(), ask_ind, return_amount)?;
+    curve.after_swap(
+        &mut config,
+        env,
+        &xs,
+        offer_ind,
+        offer_asset_dec.amount,
+        &swap_result,
+        total_share,
+    )?;
 
     //let receiver = to.unwrap_or_else(|| sender.clone());
 
@@ -928,7 +1598,7 @@ fn swap_internal(
     // Store time series data in precommit observation.
     // Skipping small unsafe values which can seriously mess oracle price due to rounding errors.
     // This data will be reflected in observations in the next action.
-    if offer_asset_dec.amount >= MIN_TRADE_SIZE && swap_result.dy >= MIN_TRADE_SIZE {
+    if offer_asset_dec.amount >= MIN_TRADE_SIZE && swap_result.return_amount >= MIN_TRADE_SIZE {
         let (base_amount, quote_amount) = if offer_ind == 0 {
             (offer_asset.amount, return_amount)
         } else {
@@ -939,5 +1609,9 @@ fn swap_internal(
 
     POOLS.save(deps.storage, pool_key, &config)?;
 
-    Ok(return_amount)
+    Ok(SwapAmounts {
+        return_amount,
+        spread_amount,
+        commission_amount: swap_result.commission_amount.to_uint(ask_asset_prec)?,
+    })
 }
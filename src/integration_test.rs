@@ -1,28 +1,51 @@
 #![cfg(not(tarpaulin_include))]
 
 use std::error::Error;
-use std::fmt::Display;
 use std::str::FromStr;
 
-use crate::factory_helper::{instantiate_token, mint, mint_native, FactoryHelper};
+use crate::error::ContractError;
+use crate::factory_helper::{
+    instantiate_token, mint, mint_native, FactoryHelper, DENOM, GUARDIAN, IBC_DENOM,
+};
+use crate::handlers::generate_key_from_asset_info;
 use crate::msg::Cw20HookMsg;
 use crate::msg::ExecuteMsg;
-use crate::msg::SwapOperation;
+use crate::msg::{
+    DcaSchedulesResponse, DetailedSimulationResponse, FlashLoanCallback,
+    FlashLoanCallbackExecuteMsg, LimitOrdersResponse, LpTokenInfo,
+    PoolSimulation, PoolsResponse, ReverseSwapOperationsResponse, RoutesResponse, SplitResponse,
+    SplitRoute, StablePoolParams, SwapCallback, SwapCallbackExecuteMsg, SwapOperation,
+    XykPoolParams,
+};
 use crate::msg::PositionModification;  // Add this import
 use astroport::asset::{
     native_asset, native_asset_info, token_asset, token_asset_info, Asset, AssetInfo, PairInfo,
 };
 use astroport::factory::PairType;
-use astroport::pair::PoolResponse;
-use astroport::pair_concentrated::ConcentratedPoolParams;
+use astroport::pair::{ConfigResponse, PoolResponse, ReverseSimulationResponse};
+use astroport::pair_concentrated::{
+    ConcentratedPoolConfig, ConcentratedPoolParams, ConcentratedPoolUpdateParams, PromoteParams,
+    UpdatePoolParams,
+};
+use astroport_pcl_common::consts::{MAX_CHANGE, MIN_AMP_CHANGING_TIME};
 use crate::msg::QueryMsg;
-use astroport::router::InstantiateMsg;
+use crate::contract::{execute, instantiate, migrate, query, INSTANTIATE_TOKEN_REPLY_ID_START};
+use crate::msg::InstantiateMsg;
+use crate::state::{
+    asset_key, decrease_asset_balance, find_asset_index, increment_pair_balances, pool_key, CurveParams, DcaSchedule, LimitOrder, ManagerConfig, PoolStatus, ASSET_POOLS, CONFIG, PAIR_BALANCES, PAIR_POOLS, POOLS, POOL_COUNT, QUEUED_MINTS,
+};
+use crate::msg::MigrateMsg;
+use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
 
-use cosmwasm_std::{to_json_binary, Addr, Coin, Decimal, Empty, Uint128};
+use astroport::router::SimulateSwapOperationsResponse;
+use cosmwasm_std::{
+    attr, from_json, to_json_binary, wasm_execute, Addr, Binary, Coin, Decimal, Decimal256, Deps,
+    DepsMut, Empty, Env, MessageInfo, Response, StdError, StdResult, Storage, Uint128,
+};
+use astroport::token::{InstantiateMarketingInfo, Logo};
 use cw20::Cw20ExecuteMsg;
-use cw_multi_test::{App, Contract, ContractWrapper, Executor};
-
-pub static DENOM: &str = "aarch";
+use cw_utils::{Expiration, PaymentError};
+use cw_multi_test::{App, ContractWrapper, Executor};
 
 pub fn common_pcl_params() -> ConcentratedPoolParams {
     ConcentratedPoolParams {
@@ -48,21 +71,6 @@ where
     T::from_str(&val.to_string()).unwrap()
 }
 
-pub fn dec_to_f64(val: impl Display) -> f64 {
-    f64::from_str(&val.to_string()).unwrap()
-}
-
-fn router_contract() -> Box<dyn Contract<Empty>> {
-    Box::new(
-        ContractWrapper::new_with_empty(
-            crate::contract::execute,
-            crate::contract::instantiate,
-            crate::contract::query,
-        )
-        .with_reply_empty(crate::contract::reply),
-    )
-}
-
 #[test]
 fn pool_manager_works() {
     let mut app = App::default();
@@ -70,43 +78,33 @@ fn pool_manager_works() {
     let owner = Addr::unchecked("owner");
     let user = Addr::unchecked("user");
 
-    let router_code = app.store_code(router_contract());
-    let pool_manager = app
-        .instantiate_contract(
-            router_code,
-            owner.clone(),
-            &InstantiateMsg {
-                astroport_factory: String::from("Pina_Colada"),
-            },
-            &[],
-            "router",
-            None,
-        )
-        .unwrap();
-
-    let mut helper = FactoryHelper::init(&mut app, &owner, &pool_manager);
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
     let token_x = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOX", None);
     let token_y = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOY", None);
     let token_z = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOZ", None);
-    println!("{}", pool_manager);
-    println!("{}", token_x);
-    println!("{}", token_y);
-    println!("{}", token_z);
-    let params = ConcentratedPoolParams {
-        price_scale: Decimal::from_ratio(1u8, 2u8),
-        ..common_pcl_params()
-    };
-    for (a, b, _typ, liq) in [
-        (&token_x, &token_y, PairType::Xyk {}, 800_000_000000),
-        (&token_y, &token_z, PairType::Stable {}, 900_000_000000),
+    let xyk_params = to_json_binary(&XykPoolParams {
+        fee_bps: 30,
+        track_asset_balances: None,
+    })
+    .unwrap();
+    let stable_params = to_json_binary(&StablePoolParams {
+        amp: 100,
+        fee_bps: 5,
+        track_asset_balances: None,
+    })
+    .unwrap();
+    for (a, b, typ, params, liq) in [
+        (&token_x, &token_y, PairType::Xyk {}, xyk_params, 800_000_000_000),
+        (&token_y, &token_z, PairType::Stable {}, stable_params, 900_000_000_000),
     ] {
-        let params = Some(to_json_binary(&params).unwrap());
         let _pair = helper
-            .create_pair(
+            .create_pair_with_type(
                 &mut app,
                 &owner,
+                typ,
                 [token_asset_info(a.clone()), token_asset_info(b.clone())],
-                params,
+                Some(params),
             )
             .unwrap();
         mint(&mut app, &owner, a, liq, &owner).unwrap();
@@ -114,7 +112,7 @@ fn pool_manager_works() {
         mint(&mut app, &owner, a, liq, &user).unwrap();
         mint(&mut app, &owner, b, liq, &user).unwrap();
     }
-    let n = 10_000_00000u128;
+    let n = 1_000_000_000u128;
     let assets1 = [
         token_asset(token_x.clone(), n.into()),
         token_asset(token_y.clone(), n.into()),
@@ -130,12 +128,16 @@ fn pool_manager_works() {
         slippage_tolerance: Some(f64_to_dec(0.5)),
         auto_stake: None,
         receiver: None,
+        pool_id: None,
+        deadline: None,
     };
     let provide_msg2 = ExecuteMsg::ProvideLiquidity {
         assets: assets2,
         slippage_tolerance: Some(f64_to_dec(0.5)),
         auto_stake: None,
         receiver: None,
+        pool_id: None,
+        deadline: None,
     };
     let msg = Cw20ExecuteMsg::IncreaseAllowance {
         spender: pool_manager.to_string(),
@@ -153,7 +155,6 @@ fn pool_manager_works() {
 
     app.execute_contract(owner.clone(), pool_manager.clone(), &provide_msg, &[])
         .unwrap();
-    //app.execute_contract(owner.clone(), pool_manager.clone(), &provide_msg, &[]).unwrap();
     app.execute_contract(owner.clone(), pool_manager.clone(), &provide_msg2, &[])
         .unwrap();
 
@@ -169,6 +170,7 @@ fn pool_manager_works() {
                     ask_asset_info: AssetInfo::Token {
                         contract_addr: token_y.clone(),
                     },
+                    pool_id: None,
                 },
                 SwapOperation {
                     offer_asset_info: AssetInfo::Token {
@@ -177,20 +179,43 @@ fn pool_manager_works() {
                     ask_asset_info: AssetInfo::Token {
                         contract_addr: token_z,
                     },
+                    pool_id: None,
                 },
             ],
             minimum_receive: None,
+            minimum_receive_per_hop: None,
             to: None,
             max_spread: None,
+            belief_price: None,
+            deadline: None,
+            callback: None,
+            referral_address: None,
+            referral_commission: None,
         })
         .unwrap(),
     };
-    let pool_key=format!("{}{}",token_x,token_y);
-    println!("{} {}","querying pool at ",pool_key);
-    let pool_data:PoolResponse=app.wrap().query_wasm_smart(pool_manager.clone(), &QueryMsg::Pool {pool_key:pool_key.clone()}).unwrap();
-    println!("{:?}",pool_data);
-    let pair_data:PairInfo=app.wrap().query_wasm_smart(pool_manager.clone(), &QueryMsg::Pair {pool_key}).unwrap();
-    println!("{:?}",pair_data);
+    let asset_infos = vec![token_asset_info(token_x.clone()), token_asset_info(token_y.clone())];
+    let pool_data: PoolResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::PoolByAssets {
+                asset_infos: asset_infos.clone(),
+                pool_id: None,
+            },
+        )
+        .unwrap();
+    assert!(pool_data.assets.iter().all(|asset| asset.amount == Uint128::from(n)));
+    let pair_data: PairInfo = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::PairByAssets {
+                asset_infos,
+                pool_id: None,
+            },
+        )
+        .unwrap();
     let withdraw_liq_msg = Cw20HookMsg::WithdrawLiquidity {
         assets: [
             Asset {
@@ -209,19 +234,15 @@ fn pool_manager_works() {
             },
         ]
         .to_vec(),
+        deadline: None,
     };
     let withdraw_msg = Cw20ExecuteMsg::Send {
         contract: pool_manager.clone().to_string(),
         amount: Uint128::from(1000000_u128),
         msg: to_json_binary(&withdraw_liq_msg).unwrap(),
     };
-    app.execute_contract(
-        owner.clone(),
-        Addr::unchecked("contract4"),
-        &withdraw_msg,
-        &[],
-    )
-    .unwrap();
+    app.execute_contract(owner.clone(), pair_data.liquidity_token, &withdraw_msg, &[])
+        .unwrap();
 }
 
 #[test]
@@ -231,35 +252,18 @@ fn test_native_to_token_swap() {
     let owner = Addr::unchecked("owner");
     let user = Addr::unchecked("user");
 
-    let router_code = app.store_code(router_contract());
-    let pool_manager = app
-        .instantiate_contract(
-            router_code,
-            owner.clone(),
-            &InstantiateMsg {
-                astroport_factory: String::from("Pina_Colada"),
-            },
-            &[],
-            "router",
-            None,
-        )
-        .unwrap();
-
-    let mut helper = FactoryHelper::init(&mut app, &owner, &pool_manager);
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
     let token_x = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOX", None);
     let token_y = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOY", None);
     let token_z = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOZ", None);
-    println!("{}", pool_manager);
-    println!("{}", token_x);
-    println!("{}", token_y);
-    println!("{}", token_z);
     let params = ConcentratedPoolParams {
         price_scale: Decimal::from_ratio(1u8, 2u8),
         ..common_pcl_params()
     };
     for (a, b, _typ, liq) in [
-        (&token_x, &token_y, PairType::Xyk {}, 800_000_00000000),
-        (&token_y, &token_z, PairType::Stable {}, 900_000_00000000),
+        (&token_x, &token_y, PairType::Xyk {}, 80_000_000_000_000),
+        (&token_y, &token_z, PairType::Stable {}, 90_000_000_000_000),
     ] {
         let params = Some(to_json_binary(&params).unwrap());
         let _pair = helper
@@ -275,7 +279,7 @@ fn test_native_to_token_swap() {
         mint(&mut app, &owner, a, liq, &user).unwrap();
         mint(&mut app, &owner, b, liq, &user).unwrap();
     }
-    let n = 10_000_00000u128;
+    let n = 1_000_000_000u128;
     let msg = Cw20ExecuteMsg::IncreaseAllowance {
         spender: pool_manager.to_string(),
         expires: None,
@@ -284,7 +288,8 @@ fn test_native_to_token_swap() {
 
     app.execute_contract(owner.clone(), token_x.clone(), &msg, &[])
         .unwrap();
-    let arch = n * 1000000000000000;
+    // Balanced at the initial price scale of 1/2, given 18 native and 6 token decimals
+    let arch = n * 500_000_000_000;
     let _r = mint_native(&mut app, DENOM, 10 * arch, &owner);
     let _pair = helper
         .create_pair(
@@ -297,7 +302,7 @@ fn test_native_to_token_swap() {
             Some(to_json_binary(&params).unwrap()),
         )
         .unwrap();
-    let n = 10_000_00000u128;
+    let n = 1_000_000_000u128;
 
     let assets1 = [
         token_asset(token_x.clone(), n.into()),
@@ -310,6 +315,8 @@ fn test_native_to_token_swap() {
         slippage_tolerance: Some(f64_to_dec(0.5)),
         auto_stake: None,
         receiver: None,
+        pool_id: None,
+        deadline: None,
     };
     app.execute_contract(
         owner.clone(),
@@ -329,10 +336,17 @@ fn test_native_to_token_swap() {
             ask_asset_info: AssetInfo::Token {
                 contract_addr: token_x.clone(),
             },
+            pool_id: None,
         }],
         minimum_receive: None,
+        minimum_receive_per_hop: None,
         to: None,
         max_spread: None,
+        belief_price: None,
+        deadline: None,
+        callback: None,
+        referral_address: None,
+        referral_commission: None,
     };
     app.execute_contract(
         owner.clone(),
@@ -352,35 +366,18 @@ fn test_token_to_native_swap() {
     let owner = Addr::unchecked("owner");
     let user = Addr::unchecked("user");
 
-    let router_code = app.store_code(router_contract());
-    let pool_manager = app
-        .instantiate_contract(
-            router_code,
-            owner.clone(),
-            &InstantiateMsg {
-                astroport_factory: String::from("Pina_Colada"),
-            },
-            &[],
-            "router",
-            None,
-        )
-        .unwrap();
-
-    let mut helper = FactoryHelper::init(&mut app, &owner, &pool_manager);
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
     let token_x = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOX", None);
     let token_y = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOY", None);
     let token_z = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOZ", None);
-    println!("{}", pool_manager);
-    println!("{}", token_x);
-    println!("{}", token_y);
-    println!("{}", token_z);
     let params = ConcentratedPoolParams {
         price_scale: Decimal::from_ratio(1u8, 2u8),
         ..common_pcl_params()
     };
     for (a, b, _typ, liq) in [
         (&token_x, &token_y, PairType::Xyk {}, 800_000_000_000_000),
-        (&token_y, &token_z, PairType::Stable {}, 900_000_000000_000),
+        (&token_y, &token_z, PairType::Stable {}, 900_000_000_000_000),
     ] {
         let params = Some(to_json_binary(&params).unwrap());
         let _pair = helper
@@ -396,7 +393,7 @@ fn test_token_to_native_swap() {
         mint(&mut app, &owner, a, liq, &user).unwrap();
         mint(&mut app, &owner, b, liq, &user).unwrap();
     }
-    let n = 10_000_00000u128;
+    let n = 1_000_000_000u128;
     let msg = Cw20ExecuteMsg::IncreaseAllowance {
         spender: pool_manager.to_string(),
         expires: None,
@@ -405,7 +402,8 @@ fn test_token_to_native_swap() {
 
     app.execute_contract(owner.clone(), token_x.clone(), &msg, &[])
         .unwrap();
-    let arch = n * 1000000000000000;
+    // Balanced at the initial price scale of 1/2, given 18 native and 6 token decimals
+    let arch = n * 1_000_000_000_000;
     let _r = mint_native(&mut app, DENOM, 10 * arch, &owner);
     let _pair = helper
         .create_pair(
@@ -418,7 +416,7 @@ fn test_token_to_native_swap() {
             Some(to_json_binary(&params).unwrap()),
         )
         .unwrap();
-    let m = 100_000_000_000_000u128;
+    let m = 2 * n;
 
     let assets1 = [
         native_asset(String::from(DENOM), arch.into()),
@@ -431,6 +429,8 @@ fn test_token_to_native_swap() {
         slippage_tolerance: Some(f64_to_dec(0.5)),
         auto_stake: None,
         receiver: None,
+        pool_id: None,
+        deadline: None,
     };
     app.execute_contract(
         owner.clone(),
@@ -444,7 +444,7 @@ fn test_token_to_native_swap() {
     .unwrap();
     let swap_msg = Cw20ExecuteMsg::Send {
         contract: pool_manager.clone().to_string(),
-        amount: Uint128::from(1000000000u128),
+        amount: Uint128::from(10_000_000u128),
         msg: to_json_binary(&Cw20HookMsg::ExecuteSwapOperations {
             operations: vec![SwapOperation {
                 offer_asset_info: AssetInfo::Token {
@@ -453,98 +453,106 @@ fn test_token_to_native_swap() {
                 ask_asset_info: AssetInfo::NativeToken {
                     denom: String::from(DENOM),
                 },
+                pool_id: None,
             }],
             minimum_receive: None,
+            minimum_receive_per_hop: None,
             to: None,
             max_spread: None,
+            belief_price: None,
+            deadline: None,
+            callback: None,
+            referral_address: None,
+            referral_commission: None,
         })
         .unwrap(),
     };
     app.execute_contract(owner.clone(), token_x.clone(), &swap_msg, &[])
         .unwrap();
+
+    // The sent token has to be the offered asset, not just any asset of the pool
+    let wrong_offer_msg = Cw20ExecuteMsg::Send {
+        contract: pool_manager.to_string(),
+        amount: Uint128::from(10_000_000u128),
+        msg: to_json_binary(&Cw20HookMsg::ExecuteSwapOperations {
+            operations: vec![SwapOperation {
+                offer_asset_info: native_asset_info(String::from(DENOM)),
+                ask_asset_info: token_asset_info(token_x.clone()),
+                pool_id: None,
+            }],
+            minimum_receive: None,
+            minimum_receive_per_hop: None,
+            to: None,
+            max_spread: None,
+            belief_price: None,
+            deadline: None,
+            callback: None,
+            referral_address: None,
+            referral_commission: None,
+        })
+        .unwrap(),
+    };
+    let err = app
+        .execute_contract(owner.clone(), token_x.clone(), &wrong_offer_msg, &[])
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InvalidAsset(token_x.to_string())
+    );
 }
 
 #[test]
 fn test_modify_position() {
     let mut app = App::default();
     let owner = Addr::unchecked("owner");
-    let user = Addr::unchecked("user");
-
-    let router_code = app.store_code(router_contract());
-    let pool_manager = app
-        .instantiate_contract(
-            router_code,
-            owner.clone(),
-            &InstantiateMsg {
-                astroport_factory: String::from("Pina_Colada"),
-            },
-            &[],
-            "router",
-            None,
-        )
-        .unwrap();
 
-    let mut helper = FactoryHelper::init(&mut app, &owner, &pool_manager);
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
     let token_x = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOX", None);
     let token_y = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOY", None);
 
-    // Setup initial parameters
-    let initial_amount = 800_000_000000u128;
-    let params = ConcentratedPoolParams {
-        price_scale: Decimal::from_ratio(1u8, 2u8),
-        ..common_pcl_params()
-    };
-
-    // Create pair
-    let _pair = helper
-        .create_pair(
+    let initial_amount = 800_000_000_000u128;
+    let n = 1_000_000_000u128;
+    helper
+        .create_seeded_xyk_pool(
             &mut app,
             &owner,
-            [token_asset_info(token_x.clone()), token_asset_info(token_y.clone())],
-            Some(to_json_binary(&params).unwrap()),
+            [
+                token_asset(token_x.clone(), n.into()),
+                token_asset(token_y.clone(), n.into()),
+            ],
         )
         .unwrap();
 
-    // Mint tokens
-    mint(&mut app, &owner, &token_x, initial_amount, &owner).unwrap();
-    mint(&mut app, &owner, &token_y, initial_amount, &owner).unwrap();
-
-    // Increase allowance
-    let n = 10_000_00000u128;
-    let msg = Cw20ExecuteMsg::IncreaseAllowance {
-        spender: pool_manager.to_string(),
-        expires: None,
-        amount: (100 * n).into(),
-    };
-
-    app.execute_contract(owner.clone(), token_x.clone(), &msg, &[]).unwrap();
-    app.execute_contract(owner.clone(), token_y.clone(), &msg, &[]).unwrap();
-
-    // Provide initial liquidity
-    let assets = [
-        token_asset(token_x.clone(), n.into()),
-        token_asset(token_y.clone(), n.into()),
-    ].to_vec();
-
-    let provide_msg = ExecuteMsg::ProvideLiquidity {
-        assets,
-        slippage_tolerance: Some(f64_to_dec(0.5)),
-        auto_stake: None,
-        receiver: None,
-    };
-
-    app.execute_contract(owner.clone(), pool_manager.clone(), &provide_msg, &[])
+    // Fund the owner for the position
+    for token in [&token_x, &token_y] {
+        mint(&mut app, &owner, token, initial_amount, &owner).unwrap();
+        app.execute_contract(
+            owner.clone(),
+            token.clone(),
+            &Cw20ExecuteMsg::IncreaseAllowance {
+                spender: pool_manager.to_string(),
+                expires: None,
+                amount: (100 * n).into(),
+            },
+            &[],
+        )
         .unwrap();
+    }
 
     // Check initial pool state
-    let pool_key = format!("{}{}", token_x, token_y);
+    let asset_infos = vec![token_asset_info(token_x.clone()), token_asset_info(token_y.clone())];
     let initial_pool: PoolResponse = app
         .wrap()
-        .query_wasm_smart(pool_manager.clone(), &QueryMsg::Pool { pool_key: pool_key.clone() })
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::PoolByAssets {
+                asset_infos: asset_infos.clone(),
+                pool_id: None,
+            },
+        )
         .unwrap();
 
-    println!("Initial pool state: {:?}", initial_pool);
-
     // Test increasing position
     let increase_msg = ExecuteMsg::ModifyPosition {
         assets: vec![
@@ -554,6 +562,8 @@ fn test_modify_position() {
         position_id: "1".to_string(),
         modification_type: PositionModification::Increase,
         slippage_tolerance: Some(f64_to_dec(0.5)),
+        pool_id: None,
+        deadline: None,
     };
 
     app.execute_contract(owner.clone(), pool_manager.clone(), &increase_msg, &[])
@@ -568,8 +578,16 @@ fn test_modify_position() {
         position_id: "1".to_string(),
         modification_type: PositionModification::Decrease,
         slippage_tolerance: Some(f64_to_dec(0.5)),
+        pool_id: None,
+        deadline: None,
     };
 
+    // Only the owner of the position can modify it
+    let err = app
+        .execute_contract(Addr::unchecked("user"), pool_manager.clone(), &decrease_msg, &[])
+        .unwrap_err();
+    assert_eq!(err.downcast::<ContractError>().unwrap(), ContractError::Unauthorized {});
+
     app.execute_contract(owner.clone(), pool_manager.clone(), &decrease_msg, &[])
         .unwrap();
 
@@ -582,6 +600,8 @@ fn test_modify_position() {
         position_id: "1".to_string(),
         modification_type: PositionModification::Rebalance,
         slippage_tolerance: Some(f64_to_dec(0.5)),
+        pool_id: None,
+        deadline: None,
     };
 
     app.execute_contract(owner.clone(), pool_manager.clone(), &rebalance_msg, &[])
@@ -590,12 +610,3665 @@ fn test_modify_position() {
     // Verify final state
     let final_pool: PoolResponse = app
         .wrap()
-        .query_wasm_smart(pool_manager, &QueryMsg::Pool { pool_key })
+        .query_wasm_smart(pool_manager, &QueryMsg::PoolByAssets { asset_infos, pool_id: None })
+        .unwrap();
+
+    // Position funds are escrowed apart from the pool reserves
+    assert_eq!(final_pool.assets, initial_pool.assets);
+
+    // The position holds n of each token before rebalancing to 2n and n
+    for (token, spent) in [(&token_x, n * 2), (&token_y, n)] {
+        let balance: cw20::BalanceResponse = app
+            .wrap()
+            .query_wasm_smart(
+                token,
+                &cw20::Cw20QueryMsg::Balance {
+                    address: owner.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(balance.balance.u128(), initial_amount - spent);
+    }
+}
+
+#[test]
+fn test_update_config() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+    let user = Addr::unchecked("user");
+
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let token_x = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOX", None);
+    let token_y = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOY", None);
+    let asset_infos = [token_asset_info(token_x), token_asset_info(token_y)];
+
+    helper
+        .create_pair(
+            &mut app,
+            &user,
+            asset_infos.clone(),
+            Some(to_json_binary(&common_pcl_params()).unwrap()),
+        )
+        .unwrap();
+    let pool_key = pool_key(&asset_infos, 1);
+
+    let update_msg = ExecuteMsg::UpdateConfig {
+        pool_key: pool_key.clone(),
+        params: ConcentratedPoolUpdateParams::Update(UpdatePoolParams {
+            mid_fee: Some(f64_to_dec(0.002)),
+            out_fee: None,
+            fee_gamma: None,
+            repeg_profit_threshold: Some(f64_to_dec(0.00001)),
+            min_price_scale_delta: None,
+            ma_half_time: Some(300),
+        }),
+    };
+
+    // The pool creator does not own the pool, the manager owner does
+    let err = app
+        .execute_contract(user, pool_manager.clone(), &update_msg, &[])
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::Unauthorized {}
+    );
+
+    app.execute_contract(helper.owner.clone(), pool_manager.clone(), &update_msg, &[])
+        .unwrap();
+
+    let config: ConfigResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::Config {
+                pool_key: pool_key.clone(),
+            },
+        )
+        .unwrap();
+    let params: ConcentratedPoolConfig = from_json(config.params.unwrap()).unwrap();
+    assert_eq!(config.owner, owner);
+    assert_eq!(params.mid_fee, f64_to_dec::<Decimal>(0.002));
+    assert_eq!(params.repeg_profit_threshold, f64_to_dec::<Decimal>(0.00001));
+    assert_eq!(params.ma_half_time, 300);
+
+    let promote = |next_amp: f64, future_time: u64| ExecuteMsg::UpdateConfig {
+        pool_key: pool_key.clone(),
+        params: ConcentratedPoolUpdateParams::Promote(PromoteParams {
+            next_amp: f64_to_dec(next_amp),
+            next_gamma: f64_to_dec(0.000145),
+            future_time,
+        }),
+    };
+    let now = app.block_info().time.seconds();
+
+    let err = app
+        .execute_contract(owner.clone(), pool_manager.clone(), &promote(44f64, now + 100), &[])
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::MinChangingTimeAssertion {}
+    );
+
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            pool_manager.clone(),
+            &promote(4000f64, now + MIN_AMP_CHANGING_TIME),
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::MaxChangeAssertion("Amp".to_string(), MAX_CHANGE)
+    );
+
+    app.execute_contract(
+        owner.clone(),
+        pool_manager.clone(),
+        &promote(80f64, now + MIN_AMP_CHANGING_TIME),
+        &[],
+    )
+    .unwrap();
+
+    app.update_block(|block| block.time = block.time.plus_seconds(MIN_AMP_CHANGING_TIME / 2));
+    app.execute_contract(
+        owner,
+        pool_manager.clone(),
+        &ExecuteMsg::UpdateConfig {
+            pool_key: pool_key.clone(),
+            params: ConcentratedPoolUpdateParams::StopChangingAmpGamma {},
+        },
+        &[],
+    )
+    .unwrap();
+
+    let config: ConfigResponse = app
+        .wrap()
+        .query_wasm_smart(pool_manager, &QueryMsg::Config { pool_key })
+        .unwrap();
+    let params: ConcentratedPoolConfig = from_json(config.params.unwrap()).unwrap();
+    assert_eq!(params.amp, f64_to_dec::<Decimal>(60f64));
+}
+
+#[test]
+fn test_manager_config() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+
+    let config: ManagerConfig = app
+        .wrap()
+        .query_wasm_smart(pool_manager.clone(), &QueryMsg::ManagerConfig {})
+        .unwrap();
+    assert_eq!(
+        config,
+        ManagerConfig {
+            owner: owner.clone(),
+            token_code_id: helper.cw20_token_code_id,
+            allowed_denoms: vec![DENOM.to_string(), IBC_DENOM.to_string()],
+            fee_address: None,
+            guardian: Some(Addr::unchecked(GUARDIAN)),
+            flash_loan_fee_bps: 0,
+            max_referral_commission: Decimal::zero(),
+        }
+    );
+
+    let token_x = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOX", None);
+    let err = helper
+        .create_pair(
+            &mut app,
+            &owner,
+            [
+                native_asset_info("uosmo".to_string()),
+                token_asset_info(token_x.clone()),
+            ],
+            Some(to_json_binary(&common_pcl_params()).unwrap()),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::DenomNotAllowed("uosmo".to_string())
+    );
+
+    let asset_infos = [
+        native_asset_info(DENOM.to_string()),
+        token_asset_info(token_x),
+    ];
+    helper
+        .create_pair(
+            &mut app,
+            &owner,
+            asset_infos.clone(),
+            Some(to_json_binary(&common_pcl_params()).unwrap()),
+        )
+        .unwrap();
+
+    let config: ConfigResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::Config {
+                pool_key: pool_key(&asset_infos, 1),
+            },
+        )
+        .unwrap();
+    assert_eq!(config.owner, owner);
+    assert_eq!(config.factory_addr, pool_manager);
+
+    let new_owner = Addr::unchecked("new_owner");
+    let update = ExecuteMsg::UpdateManagerConfig {
+        owner: Some(new_owner.to_string()),
+        token_code_id: Some(helper.cw20_token_code_id + 1),
+        allowed_denoms: Some(vec![DENOM.to_string(), "uosmo".to_string()]),
+        fee_address: Some("treasury".to_string()),
+        guardian: Some("new_guardian".to_string()),
+        flash_loan_fee_bps: None,
+        max_referral_commission: None,
+    };
+    let err = app
+        .execute_contract(new_owner.clone(), pool_manager.clone(), &update, &[])
+        .unwrap_err();
+    assert_eq!(err.downcast::<ContractError>().unwrap(), ContractError::Unauthorized {});
+    app.execute_contract(owner.clone(), pool_manager.clone(), &update, &[])
+        .unwrap();
+
+    let config: ManagerConfig = app
+        .wrap()
+        .query_wasm_smart(pool_manager.clone(), &QueryMsg::ManagerConfig {})
+        .unwrap();
+    assert_eq!(
+        config,
+        ManagerConfig {
+            owner: new_owner,
+            token_code_id: helper.cw20_token_code_id + 1,
+            allowed_denoms: vec![DENOM.to_string(), "uosmo".to_string()],
+            fee_address: Some(Addr::unchecked("treasury")),
+            guardian: Some(Addr::unchecked("new_guardian")),
+            flash_loan_fee_bps: 0,
+            max_referral_commission: Decimal::zero(),
+        }
+    );
+    // Only the new owner can update the config from now on
+    let err = app
+        .execute_contract(owner, pool_manager, &update, &[])
+        .unwrap_err();
+    assert_eq!(err.downcast::<ContractError>().unwrap(), ContractError::Unauthorized {});
+}
+
+#[test]
+fn test_arbitrary_native_denom_swaps() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+    let user = Addr::unchecked("user");
+
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let token_x = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOX", None);
+
+    let native_liq = 1_000_000_000_000_000_000_000_000u128;
+    let token_liq = 1_000_000_000_000u128;
+    helper
+        .create_seeded_pool(
+            &mut app,
+            &owner,
+            PairType::Custom("concentrated".to_string()),
+            to_json_binary(&common_pcl_params()).unwrap(),
+            [
+                native_asset(IBC_DENOM.to_string(), native_liq.into()),
+                token_asset(token_x.clone(), token_liq.into()),
+            ],
+        )
+        .unwrap();
+    mint_native(&mut app, IBC_DENOM, native_liq, &owner).unwrap();
+    mint_native(&mut app, DENOM, native_liq, &user).unwrap();
+    mint(&mut app, &owner, &token_x, token_liq, &owner).unwrap();
+
+    let native_to_token = ExecuteMsg::ExecuteSwapOperations {
+        operations: vec![SwapOperation {
+            offer_asset_info: native_asset_info(IBC_DENOM.to_string()),
+            ask_asset_info: token_asset_info(token_x.clone()),
+            pool_id: None,
+        }],
+        minimum_receive: None,
+        minimum_receive_per_hop: None,
+        to: None,
+        max_spread: None,
+        belief_price: None,
+        deadline: None,
+        callback: None,
+        referral_address: None,
+        referral_commission: None,
+    };
+
+    // Funds must be sent in the offer asset's own denom
+    let err = app
+        .execute_contract(
+            user,
+            pool_manager.clone(),
+            &native_to_token,
+            &[Coin {
+                denom: DENOM.to_string(),
+                amount: 1_000_000_000_000_000_000u128.into(),
+            }],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::PaymentError(PaymentError::MissingDenom(IBC_DENOM.to_string()))
+    );
+
+    app.execute_contract(
+        owner.clone(),
+        pool_manager.clone(),
+        &native_to_token,
+        &[Coin {
+            denom: IBC_DENOM.to_string(),
+            amount: 1_000_000_000_000_000_000_000u128.into(),
+        }],
+    )
+    .unwrap();
+    let token_balance: cw20::BalanceResponse = app
+        .wrap()
+        .query_wasm_smart(
+            token_x.clone(),
+            &cw20::Cw20QueryMsg::Balance {
+                address: owner.to_string(),
+            },
+        )
         .unwrap();
+    assert!(token_balance.balance > Uint128::from(token_liq));
+
+    // The output of a token -> native swap is paid in the ask asset's denom
+    let native_before = app.wrap().query_balance(&owner, IBC_DENOM).unwrap().amount;
+    app.execute_contract(
+        owner.clone(),
+        token_x.clone(),
+        &Cw20ExecuteMsg::Send {
+            contract: pool_manager.to_string(),
+            amount: 1_000_000_000u128.into(),
+            msg: to_json_binary(&Cw20HookMsg::ExecuteSwapOperations {
+                operations: vec![SwapOperation {
+                    offer_asset_info: token_asset_info(token_x),
+                    ask_asset_info: native_asset_info(IBC_DENOM.to_string()),
+                    pool_id: None,
+                }],
+                minimum_receive: None,
+                minimum_receive_per_hop: None,
+                to: None,
+                max_spread: None,
+                belief_price: None,
+                deadline: None,
+                callback: None,
+                referral_address: None,
+                referral_commission: None,
+            })
+            .unwrap(),
+        },
+        &[],
+    )
+    .unwrap();
+    let native_after = app.wrap().query_balance(&owner, IBC_DENOM).unwrap().amount;
+    assert!(native_after > native_before);
+    assert!(app.wrap().query_balance(&owner, DENOM).unwrap().amount.is_zero());
+}
+
+#[test]
+fn test_lp_token_code_id_and_metadata() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let token_x = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOX", None);
+    let token_y = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOY", None);
+
+    // Store the token code once more so that it gets a code ID different from the global one
+    let custom_code_id = app.store_code(Box::new(ContractWrapper::new_with_empty(
+        astroport_token::contract::execute,
+        astroport_token::contract::instantiate,
+        astroport_token::contract::query,
+    )));
+    assert_ne!(custom_code_id, helper.cw20_token_code_id);
 
-    println!("Final pool state: {:?}", final_pool);
-    
-    // Add assertions to verify the pool state is as expected after modifications
-    assert!(final_pool.assets[0].amount > Uint128::zero());
-    assert!(final_pool.assets[1].amount > Uint128::zero());
-}
\ No newline at end of file
+    let asset_infos = [token_asset_info(token_x), token_asset_info(token_y)];
+
+    // Only the manager owner can pick another LP token code
+    let err = helper
+        .create_pair_with_lp_token(
+            &mut app,
+            &Addr::unchecked("user"),
+            asset_infos.clone(),
+            Some(to_json_binary(&common_pcl_params()).unwrap()),
+            Some(custom_code_id),
+            None,
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::Unauthorized {}
+    );
+
+    helper
+        .create_pair_with_lp_token(
+            &mut app,
+            &owner,
+            asset_infos.clone(),
+            Some(to_json_binary(&common_pcl_params()).unwrap()),
+            Some(custom_code_id),
+            Some(LpTokenInfo {
+                name: Some("Pina Colada TOX-TOY".to_string()),
+                symbol: Some("TOXTOY".to_string()),
+                marketing: Some(InstantiateMarketingInfo {
+                    project: Some("Pina Colada".to_string()),
+                    description: Some("TOX-TOY liquidity".to_string()),
+                    marketing: None,
+                    logo: Some(Logo::Url("https://example.com/logo.png".to_string())),
+                }),
+            }),
+        )
+        .unwrap();
+
+    let pair: PairInfo = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager,
+            &QueryMsg::Pair {
+                pool_key: pool_key(&asset_infos, 1),
+            },
+        )
+        .unwrap();
+    let contract_info = app
+        .wrap()
+        .query_wasm_contract_info(&pair.liquidity_token)
+        .unwrap();
+    assert_eq!(contract_info.code_id, custom_code_id);
+
+    let token_info: cw20::TokenInfoResponse = app
+        .wrap()
+        .query_wasm_smart(&pair.liquidity_token, &cw20::Cw20QueryMsg::TokenInfo {})
+        .unwrap();
+    assert_eq!(token_info.name, "Pina Colada TOX-TOY");
+    assert_eq!(token_info.symbol, "TOXTOY");
+
+    let marketing: cw20::MarketingInfoResponse = app
+        .wrap()
+        .query_wasm_smart(&pair.liquidity_token, &cw20::Cw20QueryMsg::MarketingInfo {})
+        .unwrap();
+    assert_eq!(marketing.project, Some("Pina Colada".to_string()));
+    assert_eq!(marketing.description, Some("TOX-TOY liquidity".to_string()));
+    assert_eq!(
+        marketing.logo,
+        Some(cw20::LogoInfo::Url("https://example.com/logo.png".to_string()))
+    );
+}
+
+#[test]
+fn test_create_pairs_in_one_batch() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+
+    let helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let token_x = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOX", None);
+    let token_y = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOY", None);
+    let token_z = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOZ", None);
+
+    let pairs = [
+        [token_asset_info(token_x.clone()), token_asset_info(token_y.clone())],
+        [token_asset_info(token_y), token_asset_info(token_z)],
+    ];
+    let msgs = pairs
+        .iter()
+        .map(|asset_infos| {
+            wasm_execute(
+                &pool_manager,
+                &ExecuteMsg::CreatePair {
+                    asset_infos: asset_infos.to_vec(),
+                    pair_type: None,
+                    token_code_id: None,
+                    lp_token: None,
+                    init_params: Some(to_json_binary(&common_pcl_params()).unwrap()),
+                },
+                vec![],
+            )
+            .unwrap()
+            .into()
+        })
+        .collect();
+    app.execute_multi(owner.clone(), msgs).unwrap();
+
+    let lp_tokens = pairs
+        .iter()
+        .zip(1..)
+        .map(|(asset_infos, pool_id)| {
+            let pair: PairInfo = app
+                .wrap()
+                .query_wasm_smart(
+                    pool_manager.clone(),
+                    &QueryMsg::Pair {
+                        pool_key: pool_key(asset_infos, pool_id),
+                    },
+                )
+                .unwrap();
+            pair.liquidity_token
+        })
+        .collect::<Vec<_>>();
+    assert_ne!(lp_tokens[0], Addr::unchecked(""));
+    assert_ne!(lp_tokens[1], Addr::unchecked(""));
+    assert_ne!(lp_tokens[0], lp_tokens[1]);
+}
+
+#[test]
+fn test_pool_enumeration() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let token_x = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOX", None);
+    let token_y = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOY", None);
+    let token_z = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOZ", None);
+
+    let pairs = [
+        [token_asset_info(token_x.clone()), token_asset_info(token_y.clone())],
+        [token_asset_info(token_y.clone()), token_asset_info(token_z.clone())],
+        [native_asset_info(DENOM.to_string()), token_asset_info(token_x)],
+    ];
+    for asset_infos in pairs.iter() {
+        helper
+            .create_pair(
+                &mut app,
+                &owner,
+                asset_infos.clone(),
+                Some(to_json_binary(&common_pcl_params()).unwrap()),
+            )
+            .unwrap();
+    }
+
+    let first_page: PoolsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::Pools {
+                start_after: None,
+                limit: Some(2),
+            },
+        )
+        .unwrap();
+    assert_eq!(first_page.pools.len(), 2);
+    let second_page: PoolsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::Pools {
+                start_after: Some(first_page.pools[1].pool_key.clone()),
+                limit: Some(2),
+            },
+        )
+        .unwrap();
+    assert_eq!(second_page.pools.len(), 1);
+
+    let mut keys = first_page
+        .pools
+        .iter()
+        .chain(second_page.pools.iter())
+        .map(|pool| pool.pool_key.clone())
+        .collect::<Vec<_>>();
+    keys.sort();
+    let mut expected = pairs
+        .iter()
+        .zip(1..)
+        .map(|(asset_infos, pool_id)| pool_key(asset_infos, pool_id))
+        .collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(keys, expected);
+
+    let by_asset: PoolsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::PoolsByAsset {
+                asset_info: token_asset_info(token_y.clone()),
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(by_asset.pools.len(), 2);
+    assert!(by_asset
+        .pools
+        .iter()
+        .all(|pool| pool.pair_info.asset_infos.contains(&token_asset_info(token_y.clone()))));
+
+    // Pages only count the pools that contain the asset
+    let first_by_asset: PoolsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::PoolsByAsset {
+                asset_info: token_asset_info(token_y.clone()),
+                start_after: None,
+                limit: Some(1),
+            },
+        )
+        .unwrap();
+    let second_by_asset: PoolsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::PoolsByAsset {
+                asset_info: token_asset_info(token_y.clone()),
+                start_after: Some(first_by_asset.pools[0].pool_key.clone()),
+                limit: Some(1),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        [first_by_asset.pools, second_by_asset.pools].concat(),
+        by_asset.pools
+    );
+
+    // The asset order does not matter when addressing a pool by its assets
+    let pair: PairInfo = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::PairByAssets {
+                asset_infos: vec![pairs[1][1].clone(), pairs[1][0].clone()],
+                pool_id: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(pair.asset_infos, pairs[1].to_vec());
+    let config: ConfigResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager,
+            &QueryMsg::ConfigByAssets {
+                asset_infos: pairs[1].to_vec(),
+                pool_id: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(config.owner, owner);
+}
+
+/// Creates a DENOM/IBC_DENOM pool of the given type, seeds it and swaps DENOM for IBC_DENOM.
+/// Returns the simulated and the received swap output.
+fn native_pool_swap(pair_type: PairType, init_params: Binary) -> (Uint128, Uint128) {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+    let user = Addr::unchecked("user");
+
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let asset_infos = [
+        native_asset_info(DENOM.to_string()),
+        native_asset_info(IBC_DENOM.to_string()),
+    ];
+
+    let liq = 1_000_000_000_000_000_000_000_000u128;
+    let offer = 1_000_000_000_000_000_000_000u128;
+    mint_native(&mut app, DENOM, offer, &user).unwrap();
+    helper
+        .create_seeded_pool(
+            &mut app,
+            &owner,
+            pair_type,
+            init_params,
+            [
+                native_asset(DENOM.to_string(), liq.into()),
+                native_asset(IBC_DENOM.to_string(), liq.into()),
+            ],
+        )
+        .unwrap();
+
+    let operations = vec![SwapOperation {
+        offer_asset_info: asset_infos[0].clone(),
+        ask_asset_info: asset_infos[1].clone(),
+        pool_id: None,
+    }];
+    let simulation: SimulateSwapOperationsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::SimulateSwapOperations {
+                offer_amount: offer.into(),
+                operations: operations.clone(),
+                referral_commission: None,
+            },
+        )
+        .unwrap();
+
+    app.execute_contract(
+        user.clone(),
+        pool_manager.clone(),
+        &ExecuteMsg::ExecuteSwapOperations {
+            operations,
+            minimum_receive: None,
+            minimum_receive_per_hop: None,
+            to: None,
+            max_spread: None,
+            belief_price: None,
+            deadline: None,
+            callback: None,
+            referral_address: None,
+            referral_commission: None,
+        },
+        &[Coin {
+            denom: DENOM.to_string(),
+            amount: offer.into(),
+        }],
+    )
+    .unwrap();
+    let received = app.wrap().query_balance(&user, IBC_DENOM).unwrap().amount;
+
+    // Withdrawing burns LP tokens and takes both assets out of the pool
+    let pool_key = pool_key(&asset_infos, 1);
+    let pair: PairInfo = app
+        .wrap()
+        .query_wasm_smart(pool_manager.clone(), &QueryMsg::Pair { pool_key: pool_key.clone() })
+        .unwrap();
+    let before: PoolResponse = app
+        .wrap()
+        .query_wasm_smart(pool_manager.clone(), &QueryMsg::Pool { pool_key: pool_key.clone() })
+        .unwrap();
+    app.execute_contract(
+        owner.clone(),
+        pair.liquidity_token.clone(),
+        &Cw20ExecuteMsg::Send {
+            contract: pool_manager.to_string(),
+            amount: (before.total_share.u128() / 2).into(),
+            msg: to_json_binary(&Cw20HookMsg::WithdrawLiquidity {
+                assets: asset_infos
+                    .iter()
+                    .map(|info| Asset {
+                        info: info.clone(),
+                        amount: Uint128::zero(),
+                    })
+                    .collect(),
+                    deadline: None,
+            })
+            .unwrap(),
+        },
+        &[],
+    )
+    .unwrap();
+    let after: PoolResponse = app
+        .wrap()
+        .query_wasm_smart(pool_manager, &QueryMsg::Pool { pool_key })
+        .unwrap();
+    assert_eq!(after.total_share, before.total_share - before.total_share / Uint128::new(2));
+    // The refunds go to the liquidity provider, not to the LP token contract
+    for (before, after) in before.assets.iter().zip(after.assets.iter()) {
+        assert!(after.amount < before.amount);
+        let denom = before.info.to_string();
+        assert_eq!(
+            app.wrap().query_balance(&owner, &denom).unwrap().amount,
+            before.amount - after.amount
+        );
+        assert!(app
+            .wrap()
+            .query_balance(&pair.liquidity_token, &denom)
+            .unwrap()
+            .amount
+            .is_zero());
+    }
+
+    (simulation.amount, received)
+}
+
+#[test]
+fn test_xyk_pool() {
+    let params = XykPoolParams {
+        fee_bps: 30,
+        track_asset_balances: None,
+    };
+    let (simulated, received) =
+        native_pool_swap(PairType::Xyk {}, to_json_binary(&params).unwrap());
+
+    // 1e24 - 1e48 / (1e24 + 1e21) is about 999.001e18, minus the 0.3% fee
+    assert_eq!(received, simulated);
+    assert!(received > Uint128::new(996_003_000_000_000_000_000));
+    assert!(received < Uint128::new(996_004_000_000_000_000_000));
+}
+
+#[test]
+fn test_stable_pool() {
+    let params = StablePoolParams {
+        amp: 100,
+        fee_bps: 5,
+        track_asset_balances: None,
+    };
+    let (simulated, received) =
+        native_pool_swap(PairType::Stable {}, to_json_binary(&params).unwrap());
+
+    // A balanced stableswap pool trades close to 1:1
+    assert_eq!(received, simulated);
+    assert!(received > Uint128::new(999_000_000_000_000_000_000));
+    assert!(received < Uint128::new(1_000_000_000_000_000_000_000));
+}
+
+#[test]
+fn test_stable_pool_slippage() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let n = 1_000_000_000_000_000_000_000_000u128;
+    helper
+        .create_seeded_pool(
+            &mut app,
+            &owner,
+            PairType::Stable {},
+            to_json_binary(&StablePoolParams {
+                amp: 100,
+                fee_bps: 5,
+                track_asset_balances: None,
+            })
+            .unwrap(),
+            [
+                native_asset(DENOM.to_string(), n.into()),
+                native_asset(IBC_DENOM.to_string(), n.into()),
+            ],
+        )
+        .unwrap();
+    mint_native(&mut app, DENOM, 10 * n, &owner).unwrap();
+    mint_native(&mut app, IBC_DENOM, 10 * n, &owner).unwrap();
+
+    let provide = |app: &mut App, x: u128, y: u128, slippage_tolerance: Option<Decimal>| {
+        app.execute_contract(
+            owner.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::ProvideLiquidity {
+                assets: vec![
+                    native_asset(DENOM.to_string(), x.into()),
+                    native_asset(IBC_DENOM.to_string(), y.into()),
+                ],
+                slippage_tolerance,
+                auto_stake: None,
+                receiver: None,
+                pool_id: None,
+                deadline: None,
+            },
+            &[
+                Coin {
+                    denom: DENOM.to_string(),
+                    amount: x.into(),
+                },
+                Coin {
+                    denom: IBC_DENOM.to_string(),
+                    amount: y.into(),
+                },
+            ],
+        )
+    };
+    // An imbalanced deposit moves the price more than the default tolerance allows
+    let err = provide(&mut app, n, n / 10 * 9, None).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::MaxSlippageAssertion {}
+    );
+    let err = provide(&mut app, n, n, Some(f64_to_dec(0.6))).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::AllowedSpreadAssertion {}
+    );
+    provide(&mut app, n, n / 10 * 9, Some(f64_to_dec(0.2))).unwrap();
+}
+
+#[test]
+fn test_pool_type_params() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let asset_infos = [
+        native_asset_info(DENOM.to_string()),
+        native_asset_info(IBC_DENOM.to_string()),
+    ];
+
+    let err = helper
+        .create_pair_with_type(
+            &mut app,
+            &owner,
+            PairType::Xyk {},
+            asset_infos.clone(),
+            Some(
+                to_json_binary(&XykPoolParams {
+                    fee_bps: 0,
+                    track_asset_balances: None,
+                })
+                .unwrap(),
+            ),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::IncorrectPoolParam(
+            "fee_bps".to_string(),
+            "0".to_string(),
+            "10000".to_string()
+        )
+    );
+
+    let err = helper
+        .create_pair_with_type(
+            &mut app,
+            &owner,
+            PairType::Custom("weighted".to_string()),
+            asset_infos.clone(),
+            Some(to_json_binary(&common_pcl_params()).unwrap()),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::UnsupportedPoolType("custom-weighted".to_string())
+    );
+
+    helper
+        .create_pair_with_type(
+            &mut app,
+            &owner,
+            PairType::Stable {},
+            asset_infos.clone(),
+            Some(
+                to_json_binary(&StablePoolParams {
+                    amp: 50,
+                    fee_bps: 4,
+                    track_asset_balances: None,
+                })
+                .unwrap(),
+            ),
+        )
+        .unwrap();
+
+    let pool_key = pool_key(&asset_infos, 1);
+    let config: ConfigResponse = app
+        .wrap()
+        .query_wasm_smart(pool_manager.clone(), &QueryMsg::Config { pool_key: pool_key.clone() })
+        .unwrap();
+    assert_eq!(
+        from_json::<CurveParams>(config.params.unwrap()).unwrap(),
+        CurveParams {
+            fee_bps: 4,
+            amp: Some(50),
+        }
+    );
+
+    // Concentrated-only queries and updates are rejected for other pool types
+    let err = app
+        .wrap()
+        .query_wasm_smart::<Decimal256>(pool_manager.clone(), &QueryMsg::ComputeD { pool_key: pool_key.clone() })
+        .unwrap_err();
+    assert!(err.to_string().contains("Operation is not supported for stable pools"));
+
+    let err = app
+        .execute_contract(
+            owner,
+            pool_manager,
+            &ExecuteMsg::UpdateConfig {
+                pool_key,
+                params: ConcentratedPoolUpdateParams::StopChangingAmpGamma {},
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::UnsupportedPoolType("stable".to_string())
+    );
+}
+
+#[test]
+fn test_pool_keys_do_not_collide() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+
+    let mut helper = FactoryHelper::init_with_denoms(&mut app, &owner, &["uaa", "ubbucc", "uaaubb", "ucc"]);
+    let pool_manager = helper.pool_manager.clone();
+
+    // Both pairs used to map to the key "uaaubbucc" before assets were tagged and delimited
+    let pairs = [
+        [native_asset_info("uaa".to_string()), native_asset_info("ubbucc".to_string())],
+        [native_asset_info("uaaubb".to_string()), native_asset_info("ucc".to_string())],
+    ];
+    assert_eq!(
+        generate_key_from_asset_info(&pairs[0]),
+        "native:uaa|native:ubbucc".to_string()
+    );
+    assert_ne!(
+        generate_key_from_asset_info(&pairs[0]),
+        generate_key_from_asset_info(&pairs[1])
+    );
+
+    for asset_infos in pairs.iter() {
+        helper
+            .create_pair(
+                &mut app,
+                &owner,
+                asset_infos.clone(),
+                Some(to_json_binary(&common_pcl_params()).unwrap()),
+            )
+            .unwrap();
+    }
+
+    let pools: PoolsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager,
+            &QueryMsg::Pools {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(pools.pools.len(), 2);
+}
+
+#[test]
+fn test_migrate_pool_keys() {
+    let env = mock_env();
+    let owner = "owner";
+    let manager_config = ManagerConfig {
+        owner: Addr::unchecked(owner),
+        token_code_id: 2,
+        allowed_denoms: vec![DENOM.to_string(), IBC_DENOM.to_string()],
+        fee_address: None,
+        guardian: None,
+        flash_loan_fee_bps: 0,
+        max_referral_commission: Decimal::zero(),
+    };
+    let asset_infos = vec![
+        native_asset_info(DENOM.to_string()),
+        native_asset_info(IBC_DENOM.to_string()),
+    ];
+
+    // Create a pool on a current contract to get a pool config and balances to store
+    let mut current = mock_dependencies();
+    instantiate(
+        current.as_mut(),
+        env.clone(),
+        mock_info(owner, &[]),
+        InstantiateMsg {
+            owner: owner.to_string(),
+            token_code_id: manager_config.token_code_id,
+            allowed_denoms: manager_config.allowed_denoms.clone(),
+            fee_address: None,
+            guardian: None,
+            flash_loan_fee_bps: 0,
+            max_referral_commission: Decimal::zero(),
+        },
+    )
+    .unwrap();
+    execute(
+        current.as_mut(),
+        env.clone(),
+        mock_info(owner, &[]),
+        ExecuteMsg::CreatePair {
+            asset_infos: asset_infos.clone(),
+            pair_type: None,
+            token_code_id: None,
+            lp_token: None,
+            init_params: Some(to_json_binary(&common_pcl_params()).unwrap()),
+        },
+    )
+    .unwrap();
+    let new_key = pool_key(&asset_infos, 1);
+    let config = POOLS.load(&current.storage, new_key.clone()).unwrap();
+    let balances = PAIR_BALANCES.load(&current.storage, new_key.clone()).unwrap();
+
+    // 0.1.0 storage: pools under the concatenated asset key, a single queued LP token
+    // mint and no manager config, pool ids or reply ids
+    let mut deps = mock_dependencies();
+    let old_key = format!("{DENOM}{IBC_DENOM}");
+    cw2::set_contract_version(&mut deps.storage, "pina-colada", "0.1.0").unwrap();
+    POOLS.save(&mut deps.storage, old_key.clone(), &config).unwrap();
+    PAIR_BALANCES.save(&mut deps.storage, old_key.clone(), &balances).unwrap();
+    deps.storage.set(b"pool_key", &to_json_binary(&old_key).unwrap());
+
+    // The manager config can't be made up
+    let err = migrate(deps.as_mut(), env.clone(), MigrateMsg { config: None }).unwrap_err();
+    assert_eq!(err, ContractError::MissingManagerConfig {});
+
+    migrate(
+        deps.as_mut(),
+        env.clone(),
+        MigrateMsg {
+            config: Some(manager_config.clone()),
+        },
+    )
+    .unwrap();
+
+    assert_eq!(CONFIG.load(&deps.storage).unwrap(), manager_config);
+    let queried: ManagerConfig =
+        from_json(query(deps.as_ref(), env, QueryMsg::ManagerConfig {}).unwrap()).unwrap();
+    assert_eq!(queried, manager_config);
+
+    assert!(!POOLS.has(&deps.storage, old_key.clone()));
+    assert!(!PAIR_BALANCES.has(&deps.storage, old_key));
+    assert_eq!(POOLS.load(&deps.storage, new_key.clone()).unwrap(), config);
+    assert_eq!(PAIR_BALANCES.load(&deps.storage, new_key.clone()).unwrap(), balances);
+    assert_eq!(
+        PAIR_POOLS
+            .load(&deps.storage, generate_key_from_asset_info(&asset_infos))
+            .unwrap(),
+        vec![1]
+    );
+    assert_eq!(POOL_COUNT.load(&deps.storage).unwrap(), 1);
+    for asset_info in &asset_infos {
+        assert_eq!(
+            ASSET_POOLS
+                .load(&deps.storage, (asset_key(asset_info), new_key.clone()))
+                .unwrap(),
+            1
+        );
+    }
+
+    // The pending mint moved to the reply-id keyed queue and points at the new key
+    assert!(deps.storage.get(b"pool_key").is_none());
+    assert_eq!(
+        QUEUED_MINTS
+            .load(&deps.storage, INSTANTIATE_TOKEN_REPLY_ID_START)
+            .unwrap(),
+        new_key
+    );
+    assert_eq!(
+        cw2::get_contract_version(&deps.storage).unwrap().version,
+        env!("CARGO_PKG_VERSION")
+    );
+}
+
+#[test]
+fn test_multiple_pools_per_pair() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+    let user = Addr::unchecked("user");
+
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let asset_infos = [
+        native_asset_info(DENOM.to_string()),
+        native_asset_info(IBC_DENOM.to_string()),
+    ];
+
+    // A low-fee and a high-fee pool for the same pair, in either asset order
+    for (fee_bps, asset_infos) in [
+        (5, asset_infos.clone()),
+        (100, [asset_infos[1].clone(), asset_infos[0].clone()]),
+    ] {
+        helper
+            .create_pair_with_type(
+                &mut app,
+                &owner,
+                PairType::Xyk {},
+                asset_infos,
+                Some(
+                    to_json_binary(&XykPoolParams {
+                        fee_bps,
+                        track_asset_balances: None,
+                    })
+                    .unwrap(),
+                ),
+            )
+            .unwrap();
+    }
+
+    let liq = 1_000_000_000_000_000_000_000_000u128;
+    let offer = 1_000_000_000_000_000_000_000u128;
+    mint_native(&mut app, DENOM, 2 * liq, &owner).unwrap();
+    mint_native(&mut app, IBC_DENOM, 2 * liq, &owner).unwrap();
+    mint_native(&mut app, DENOM, offer, &user).unwrap();
+    for pool_id in [1, 2] {
+        app.execute_contract(
+            owner.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::ProvideLiquidity {
+                assets: vec![
+                    native_asset(DENOM.to_string(), liq.into()),
+                    native_asset(IBC_DENOM.to_string(), liq.into()),
+                ],
+                slippage_tolerance: None,
+                auto_stake: None,
+                receiver: None,
+                pool_id: Some(pool_id),
+                deadline: None,
+            },
+            &[
+                Coin {
+                    denom: DENOM.to_string(),
+                    amount: liq.into(),
+                },
+                Coin {
+                    denom: IBC_DENOM.to_string(),
+                    amount: liq.into(),
+                },
+            ],
+        )
+        .unwrap();
+    }
+
+    let simulations: Vec<PoolSimulation> = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::SimulateSwapPools {
+                offer_asset: native_asset(DENOM.to_string(), offer.into()),
+                ask_asset_info: asset_infos[1].clone(),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        simulations.iter().map(|sim| sim.pool_id).collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+    assert!(simulations[0].simulation.return_amount > simulations[1].simulation.return_amount);
+
+    // The route picks the high-fee pool explicitly
+    app.execute_contract(
+        user.clone(),
+        pool_manager.clone(),
+        &ExecuteMsg::ExecuteSwapOperations {
+            operations: vec![SwapOperation {
+                offer_asset_info: asset_infos[0].clone(),
+                ask_asset_info: asset_infos[1].clone(),
+                pool_id: Some(2),
+            }],
+            minimum_receive: None,
+            minimum_receive_per_hop: None,
+            to: None,
+            max_spread: None,
+            belief_price: None,
+            deadline: None,
+            callback: None,
+            referral_address: None,
+            referral_commission: None,
+        },
+        &[Coin {
+            denom: DENOM.to_string(),
+            amount: offer.into(),
+        }],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&user, IBC_DENOM).unwrap().amount,
+        simulations[1].simulation.return_amount
+    );
+
+    let pools = [1, 2].map(|pool_id| -> PoolResponse {
+        app.wrap()
+            .query_wasm_smart(
+                pool_manager.clone(),
+                &QueryMsg::PoolByAssets {
+                    asset_infos: asset_infos.to_vec(),
+                    pool_id: Some(pool_id),
+                },
+            )
+            .unwrap()
+    });
+    let offer_balance = |pool: &PoolResponse| {
+        pool.assets
+            .iter()
+            .find(|asset| asset.info == asset_infos[0])
+            .unwrap()
+            .amount
+    };
+    assert_eq!(offer_balance(&pools[0]), Uint128::new(liq));
+    assert_eq!(offer_balance(&pools[1]), Uint128::new(liq + offer));
+
+    // Withdrawing goes to the pool of the LP token that was sent
+    let pair: PairInfo = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::PairByAssets {
+                asset_infos: asset_infos.to_vec(),
+                pool_id: Some(2),
+            },
+        )
+        .unwrap();
+    app.execute_contract(
+        owner,
+        pair.liquidity_token,
+        &Cw20ExecuteMsg::Send {
+            contract: pool_manager.to_string(),
+            amount: (pools[1].total_share.u128() / 2).into(),
+            msg: to_json_binary(&Cw20HookMsg::WithdrawLiquidity { assets: vec![], deadline: None }).unwrap(),
+        },
+        &[],
+    )
+    .unwrap();
+    let pool: PoolResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::Pool {
+                pool_key: pool_key(&asset_infos, 1),
+            },
+        )
+        .unwrap();
+    assert_eq!(pool, pools[0]);
+
+    let err = app
+        .wrap()
+        .query_wasm_smart::<PairInfo>(
+            pool_manager,
+            &QueryMsg::PairByAssets {
+                asset_infos: asset_infos.to_vec(),
+                pool_id: Some(3),
+            },
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains("Pool not found"));
+}
+
+#[test]
+fn test_pool_status() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+    let user = Addr::unchecked("user");
+
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let asset_infos = [
+        native_asset_info(DENOM.to_string()),
+        native_asset_info(IBC_DENOM.to_string()),
+    ];
+    helper.create_xyk_pair(&mut app, &owner, asset_infos.clone()).unwrap();
+    let pool_key = pool_key(&asset_infos, 1);
+    let pair: PairInfo = app
+        .wrap()
+        .query_wasm_smart(pool_manager.clone(), &QueryMsg::Pair { pool_key: pool_key.clone() })
+        .unwrap();
+
+    let liq = 1_000_000_000_000_000_000_000_000u128;
+    let amount = 1_000_000_000_000_000_000_000u128;
+    mint_native(&mut app, DENOM, 3 * liq, &owner).unwrap();
+    mint_native(&mut app, IBC_DENOM, 3 * liq, &owner).unwrap();
+    mint_native(&mut app, DENOM, amount, &user).unwrap();
+
+    let provide = |app: &mut App| {
+        app.execute_contract(
+            owner.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::ProvideLiquidity {
+                assets: vec![
+                    native_asset(DENOM.to_string(), liq.into()),
+                    native_asset(IBC_DENOM.to_string(), liq.into()),
+                ],
+                slippage_tolerance: None,
+                auto_stake: None,
+                receiver: None,
+                pool_id: None,
+                deadline: None,
+            },
+            &[
+                Coin {
+                    denom: DENOM.to_string(),
+                    amount: liq.into(),
+                },
+                Coin {
+                    denom: IBC_DENOM.to_string(),
+                    amount: liq.into(),
+                },
+            ],
+        )
+    };
+    let swap = |app: &mut App| {
+        app.execute_contract(
+            user.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::ExecuteSwapOperations {
+                operations: vec![SwapOperation {
+                    offer_asset_info: asset_infos[0].clone(),
+                    ask_asset_info: asset_infos[1].clone(),
+                    pool_id: None,
+                }],
+                minimum_receive: None,
+                minimum_receive_per_hop: None,
+                to: None,
+                max_spread: None,
+                belief_price: None,
+                deadline: None,
+                callback: None,
+                referral_address: None,
+                referral_commission: None,
+            },
+            &[Coin {
+                denom: DENOM.to_string(),
+                amount: amount.into(),
+            }],
+        )
+    };
+    let withdraw = |app: &mut App| {
+        app.execute_contract(
+            owner.clone(),
+            pair.liquidity_token.clone(),
+            &Cw20ExecuteMsg::Send {
+                contract: pool_manager.to_string(),
+                amount: 1_000_000u128.into(),
+                msg: to_json_binary(&Cw20HookMsg::WithdrawLiquidity { assets: vec![], deadline: None }).unwrap(),
+            },
+            &[],
+        )
+    };
+    let set_status = |app: &mut App, sender: &str, status: PoolStatus| {
+        app.execute_contract(
+            Addr::unchecked(sender),
+            pool_manager.clone(),
+            &ExecuteMsg::SetPoolStatus {
+                pool_key: pool_key.clone(),
+                status,
+            },
+            &[],
+        )
+    };
+    provide(&mut app).unwrap();
+
+    let err = set_status(&mut app, "user", PoolStatus::Frozen).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::Unauthorized {}
+    );
+
+    set_status(&mut app, GUARDIAN, PoolStatus::SwapsPaused).unwrap();
+    let err = swap(&mut app).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::SwapsDisabled(pool_key.clone())
+    );
+    provide(&mut app).unwrap();
+    withdraw(&mut app).unwrap();
+
+    set_status(&mut app, owner.as_str(), PoolStatus::WithdrawOnly).unwrap();
+    let err = provide(&mut app).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::ProvideDisabled(pool_key.clone())
+    );
+    withdraw(&mut app).unwrap();
+
+    set_status(&mut app, GUARDIAN, PoolStatus::Frozen).unwrap();
+    let err = withdraw(&mut app).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::WithdrawDisabled(pool_key.clone())
+    );
+    let status: PoolStatus = app
+        .wrap()
+        .query_wasm_smart(pool_manager.clone(), &QueryMsg::PoolStatus { pool_key: pool_key.clone() })
+        .unwrap();
+    assert_eq!(status, PoolStatus::Frozen);
+    let pools: PoolsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::Pools {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(pools.pools[0].status, PoolStatus::Frozen);
+
+    set_status(&mut app, GUARDIAN, PoolStatus::Active).unwrap();
+    swap(&mut app).unwrap();
+}
+
+#[test]
+fn test_minimum_receive() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+    let user = Addr::unchecked("user");
+
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let token_x = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOX", None);
+    let denom = native_asset_info(DENOM.to_string());
+    let ibc_denom = native_asset_info(IBC_DENOM.to_string());
+    let token = token_asset_info(token_x.clone());
+
+    let liq = 1_000_000_000_000_000_000_000_000u128;
+    let token_liq = 1_000_000_000_000u128;
+    let offer = 1_000_000_000_000_000_000_000u128;
+    mint_native(&mut app, DENOM, 3 * offer, &user).unwrap();
+
+    // DENOM -> IBC_DENOM -> token_x
+    for assets in [
+        [
+            native_asset(DENOM.to_string(), liq.into()),
+            native_asset(IBC_DENOM.to_string(), (liq / 2).into()),
+        ],
+        [
+            native_asset(IBC_DENOM.to_string(), (liq / 2).into()),
+            token_asset(token_x.clone(), token_liq.into()),
+        ],
+    ] {
+        helper.create_seeded_xyk_pool(&mut app, &owner, assets).unwrap();
+    }
+
+    let operations = vec![
+        SwapOperation {
+            offer_asset_info: denom.clone(),
+            ask_asset_info: ibc_denom.clone(),
+            pool_id: None,
+        },
+        SwapOperation {
+            offer_asset_info: ibc_denom,
+            ask_asset_info: token,
+            pool_id: None,
+        },
+    ];
+    let simulation: SimulateSwapOperationsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::SimulateSwapOperations {
+                offer_amount: offer.into(),
+                operations: vec![operations[0].clone()],
+                referral_commission: None,
+            },
+        )
+        .unwrap();
+    let first_hop = simulation.amount;
+    let simulation: SimulateSwapOperationsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::SimulateSwapOperations {
+                offer_amount: offer.into(),
+                operations: operations.clone(),
+                referral_commission: None,
+            },
+        )
+        .unwrap();
+    let expected = simulation.amount;
+
+    let swap = |app: &mut App,
+                minimum_receive: Option<Uint128>,
+                minimum_receive_per_hop: Option<Vec<Uint128>>| {
+        app.execute_contract(
+            user.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::ExecuteSwapOperations {
+                operations: operations.clone(),
+                minimum_receive,
+                minimum_receive_per_hop,
+                to: None,
+                max_spread: None,
+                belief_price: None,
+                deadline: None,
+                callback: None,
+                referral_address: None,
+                referral_commission: None,
+            },
+            &[Coin {
+                denom: DENOM.to_string(),
+                amount: offer.into(),
+            }],
+        )
+    };
+
+    let err = swap(&mut app, Some(expected + Uint128::one()), None).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::AssertionMinimumReceive {
+            receive: expected + Uint128::one(),
+            amount: expected,
+        }
+    );
+
+    let err = swap(&mut app, None, Some(vec![first_hop + Uint128::one(), Uint128::zero()])).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::AssertionMinimumReceive {
+            receive: first_hop + Uint128::one(),
+            amount: first_hop,
+        }
+    );
+
+    let err = swap(&mut app, None, Some(vec![first_hop])).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InvalidHopMinimums {
+            operations: 2,
+            minimums: 1,
+        }
+    );
+
+    swap(&mut app, Some(expected), Some(vec![first_hop, expected])).unwrap();
+    let balance: cw20::BalanceResponse = app
+        .wrap()
+        .query_wasm_smart(
+            token_x,
+            &cw20::Cw20QueryMsg::Balance {
+                address: user.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(balance.balance, expected);
+}
+
+#[test]
+fn test_belief_price() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+    let user = Addr::unchecked("user");
+    let usd = "uusd";
+
+    let mut helper = FactoryHelper::init_with_denoms(&mut app, &owner, &[DENOM, IBC_DENOM, usd]);
+    let pool_manager = helper.pool_manager.clone();
+
+    let liq = 1_000_000_000_000_000_000_000_000u128;
+    let offer = 1_000_000_000_000_000_000_000u128;
+    mint_native(&mut app, DENOM, 4 * offer, &user).unwrap();
+
+    // DENOM -> IBC_DENOM -> uusd
+    for (a, b) in [(DENOM, IBC_DENOM), (IBC_DENOM, usd)] {
+        helper
+            .create_seeded_xyk_pool(
+                &mut app,
+                &owner,
+                [
+                    native_asset(a.to_string(), liq.into()),
+                    native_asset(b.to_string(), liq.into()),
+                ],
+            )
+            .unwrap();
+    }
+
+    let direct = vec![SwapOperation {
+        offer_asset_info: native_asset_info(DENOM.to_string()),
+        ask_asset_info: native_asset_info(IBC_DENOM.to_string()),
+        pool_id: None,
+    }];
+    let route = vec![
+        direct[0].clone(),
+        SwapOperation {
+            offer_asset_info: native_asset_info(IBC_DENOM.to_string()),
+            ask_asset_info: native_asset_info(usd.to_string()),
+            pool_id: None,
+        },
+    ];
+    let simulate = |app: &App, operations: &Vec<SwapOperation>| {
+        let simulation: SimulateSwapOperationsResponse = app
+            .wrap()
+            .query_wasm_smart(
+                pool_manager.clone(),
+                &QueryMsg::SimulateSwapOperations {
+                    offer_amount: offer.into(),
+                    operations: operations.clone(),
+                    referral_commission: None,
+                },
+            )
+            .unwrap();
+        simulation.amount
+    };
+    let swap = |app: &mut App, operations: &Vec<SwapOperation>, belief_price: Decimal| {
+        app.execute_contract(
+            user.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::ExecuteSwapOperations {
+                operations: operations.clone(),
+                minimum_receive: None,
+                minimum_receive_per_hop: None,
+                to: None,
+                max_spread: Some(Decimal::percent(1)),
+                belief_price: Some(belief_price),
+                deadline: None,
+                callback: None,
+                referral_address: None,
+                referral_commission: None,
+            },
+            &[Coin {
+                denom: DENOM.to_string(),
+                amount: offer.into(),
+            }],
+        )
+    };
+
+    // A direct swap is checked in the pool
+    let return_amount = simulate(&app, &direct);
+    let err = swap(&mut app, &direct, Decimal::percent(90)).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::MaxSpreadExceeded {
+            realised_price: Decimal::from_ratio(offer, return_amount),
+        }
+    );
+    swap(&mut app, &direct, Decimal::one()).unwrap();
+
+    // A route is checked end to end
+    let return_amount = simulate(&app, &route);
+    let realised_price = Decimal::from_ratio(offer, return_amount);
+    let err = swap(&mut app, &route, realised_price * Decimal::percent(90)).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::MaxSpreadExceeded { realised_price }
+    );
+    swap(&mut app, &route, realised_price).unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&user, usd).unwrap().amount,
+        return_amount
+    );
+
+    // Without a belief price or max spread no hop is held to the default spread
+    let large = liq / 10;
+    mint_native(&mut app, DENOM, large, &user).unwrap();
+    app.execute_contract(
+        user.clone(),
+        pool_manager.clone(),
+        &ExecuteMsg::ExecuteSwapOperations {
+            operations: route,
+            minimum_receive: None,
+            minimum_receive_per_hop: None,
+            to: None,
+            max_spread: None,
+            belief_price: None,
+            deadline: None,
+            callback: None,
+            referral_address: None,
+            referral_commission: None,
+        },
+        &[Coin {
+            denom: DENOM.to_string(),
+            amount: large.into(),
+        }],
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_swap() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+    let user = Addr::unchecked("user");
+
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let token_x = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOX", None);
+
+    let liq = 1_000_000_000_000_000_000_000_000u128;
+    let token_liq = 1_000_000_000_000u128;
+    let offer = 1_000_000_000_000_000_000_000u128;
+    mint_native(&mut app, DENOM, offer, &user).unwrap();
+
+    let denom = native_asset_info(DENOM.to_string());
+    let token = token_asset_info(token_x.clone());
+    helper
+        .create_seeded_xyk_pool(
+            &mut app,
+            &owner,
+            [
+                native_asset(DENOM.to_string(), liq.into()),
+                token_asset(token_x.clone(), token_liq.into()),
+            ],
+        )
+        .unwrap();
+
+    let swap_msg = |offer_asset: Asset| ExecuteMsg::Swap {
+        offer_asset,
+        ask_asset_info: token.clone(),
+        belief_price: None,
+        max_spread: None,
+        to: None,
+        pool_id: None,
+        callback: None,
+        referral_address: None,
+        referral_commission: None,
+    };
+
+    // Tokens must be swapped through the cw20 hook
+    let err = app
+        .execute_contract(
+            user.clone(),
+            pool_manager.clone(),
+            &swap_msg(token_asset(token_x.clone(), offer.into())),
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NonNativeOffer {}
+    );
+
+    // The offer amount must match the funds sent
+    app.execute_contract(
+        user.clone(),
+        pool_manager.clone(),
+        &swap_msg(native_asset(DENOM.to_string(), (2 * offer).into())),
+        &[Coin {
+            denom: DENOM.to_string(),
+            amount: offer.into(),
+        }],
+    )
+    .unwrap_err();
+
+    let simulation: SimulateSwapOperationsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::SimulateSwapOperations {
+                offer_amount: offer.into(),
+                operations: vec![SwapOperation {
+                    offer_asset_info: denom.clone(),
+                    ask_asset_info: token.clone(),
+                    pool_id: None,
+                }],
+                referral_commission: None,
+            },
+        )
+        .unwrap();
+    let res = app
+        .execute_contract(
+            user.clone(),
+            pool_manager.clone(),
+            &swap_msg(native_asset(DENOM.to_string(), offer.into())),
+            &[Coin {
+                denom: DENOM.to_string(),
+                amount: offer.into(),
+            }],
+        )
+        .unwrap();
+    let swap_event = res
+        .events
+        .iter()
+        .find(|event| event.ty == "wasm" && event.attributes.iter().any(|a| a.value == "swap"))
+        .unwrap();
+    let attribute = |key: &str| {
+        swap_event
+            .attributes
+            .iter()
+            .find(|a| a.key == key)
+            .map(|a| a.value.clone())
+            .unwrap()
+    };
+    assert_eq!(attribute("offer_asset"), DENOM);
+    assert_eq!(attribute("ask_asset"), token_x.to_string());
+    assert_eq!(attribute("return_amount"), simulation.amount.to_string());
+    assert_ne!(attribute("commission_amount"), "0");
+    assert!(attribute("spread_amount").parse::<u128>().is_ok());
+
+    let balance: cw20::BalanceResponse = app
+        .wrap()
+        .query_wasm_smart(
+            token_x.clone(),
+            &cw20::Cw20QueryMsg::Balance {
+                address: user.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(balance.balance, simulation.amount);
+
+    // Swap the tokens back through the cw20 hook
+    app.execute_contract(
+        user.clone(),
+        token_x.clone(),
+        &Cw20ExecuteMsg::Send {
+            contract: pool_manager.to_string(),
+            amount: balance.balance,
+            msg: to_json_binary(&Cw20HookMsg::Swap {
+                ask_asset_info: denom,
+                belief_price: None,
+                max_spread: None,
+                to: None,
+                pool_id: None,
+                callback: None,
+                referral_address: None,
+                referral_commission: None,
+            })
+            .unwrap(),
+        },
+        &[],
+    )
+    .unwrap();
+    let returned = app.wrap().query_balance(&user, DENOM).unwrap().amount;
+    assert!(!returned.is_zero() && returned < Uint128::from(offer));
+}
+
+#[test]
+fn test_find_routes() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+    let user = Addr::unchecked("user");
+    let usd = "uusd";
+
+    let mut helper = FactoryHelper::init_with_denoms(&mut app, &owner, &[DENOM, IBC_DENOM, usd]);
+    let pool_manager = helper.pool_manager.clone();
+
+    let liq = 1_000_000_000_000_000_000_000_000u128;
+    let offer = 1_000_000_000_000_000_000_000u128;
+    mint_native(&mut app, DENOM, offer, &user).unwrap();
+
+    // The direct pool charges a far higher fee than the route through IBC_DENOM
+    for (a, b, fee_bps) in [(DENOM, IBC_DENOM, 30), (IBC_DENOM, usd, 30), (DENOM, usd, 1000)] {
+        helper
+            .create_seeded_pool(
+                &mut app,
+                &owner,
+                PairType::Xyk {},
+                to_json_binary(&XykPoolParams {
+                    fee_bps,
+                    track_asset_balances: None,
+                })
+                .unwrap(),
+                [
+                    native_asset(a.to_string(), liq.into()),
+                    native_asset(b.to_string(), liq.into()),
+                ],
+            )
+            .unwrap();
+    }
+
+    let find_routes = |app: &App, max_hops: Option<u32>| {
+        let res: RoutesResponse = app
+            .wrap()
+            .query_wasm_smart(
+                pool_manager.clone(),
+                &QueryMsg::FindRoutes {
+                    offer_asset_info: native_asset_info(DENOM.to_string()),
+                    ask_asset_info: native_asset_info(usd.to_string()),
+                    offer_amount: offer.into(),
+                    max_hops,
+                },
+            )
+            .unwrap();
+        res.routes
+    };
+
+    let routes = find_routes(&app, None);
+    assert_eq!(
+        routes.iter().map(|route| route.operations.len()).collect::<Vec<_>>(),
+        vec![2, 1]
+    );
+    assert!(routes[0].return_amount > routes[1].return_amount);
+    assert_eq!(
+        routes[0].operations,
+        vec![
+            SwapOperation {
+                offer_asset_info: native_asset_info(DENOM.to_string()),
+                ask_asset_info: native_asset_info(IBC_DENOM.to_string()),
+                pool_id: Some(1),
+            },
+            SwapOperation {
+                offer_asset_info: native_asset_info(IBC_DENOM.to_string()),
+                ask_asset_info: native_asset_info(usd.to_string()),
+                pool_id: Some(2),
+            },
+        ]
+    );
+    let simulation: SimulateSwapOperationsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::SimulateSwapOperations {
+                offer_amount: offer.into(),
+                operations: routes[0].operations.clone(),
+                referral_commission: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(simulation.amount, routes[0].return_amount);
+
+    let direct = find_routes(&app, Some(1));
+    assert_eq!(direct, vec![routes[1].clone()]);
+    // Searches deeper than the hop cap are clamped instead of walking the whole graph
+    assert_eq!(find_routes(&app, Some(10)), routes);
+
+    // Paused pools are left out of the graph
+    let paused_key = pool_key(
+        &[native_asset_info(IBC_DENOM.to_string()), native_asset_info(usd.to_string())],
+        2,
+    );
+    app.execute_contract(
+        owner.clone(),
+        pool_manager.clone(),
+        &ExecuteMsg::SetPoolStatus {
+            pool_key: paused_key.clone(),
+            status: PoolStatus::SwapsPaused,
+        },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(find_routes(&app, None), direct);
+    app.execute_contract(
+        owner.clone(),
+        pool_manager.clone(),
+        &ExecuteMsg::SetPoolStatus {
+            pool_key: paused_key,
+            status: PoolStatus::Active,
+        },
+        &[],
+    )
+    .unwrap();
+
+    let swap_best_route = |app: &mut App, ask_asset: &str| {
+        app.execute_contract(
+            user.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::SwapBestRoute {
+                ask_asset_info: native_asset_info(ask_asset.to_string()),
+                max_hops: None,
+                minimum_receive: None,
+                to: None,
+                max_spread: None,
+            },
+            &[Coin {
+                denom: DENOM.to_string(),
+                amount: offer.into(),
+            }],
+        )
+    };
+
+    let err = swap_best_route(&mut app, "uunknown").unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NoRouteFound {
+            offer_asset: DENOM.to_string(),
+            ask_asset: "uunknown".to_string(),
+        }
+    );
+
+    swap_best_route(&mut app, usd).unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&user, usd).unwrap().amount,
+        routes[0].return_amount
+    );
+}
+
+#[test]
+fn test_split_swap() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+    let user = Addr::unchecked("user");
+    let usd = "uusd";
+
+    let mut helper = FactoryHelper::init_with_denoms(&mut app, &owner, &[DENOM, IBC_DENOM, usd]);
+    let pool_manager = helper.pool_manager.clone();
+
+    let liq = 1_000_000_000_000_000_000_000_000u128;
+    let offer = liq / 10;
+    mint_native(&mut app, DENOM, offer, &user).unwrap();
+
+    for (a, b) in [(DENOM, IBC_DENOM), (IBC_DENOM, usd), (DENOM, usd)] {
+        helper
+            .create_seeded_xyk_pool(
+                &mut app,
+                &owner,
+                [
+                    native_asset(a.to_string(), liq.into()),
+                    native_asset(b.to_string(), liq.into()),
+                ],
+            )
+            .unwrap();
+    }
+
+    let routes: RoutesResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::FindRoutes {
+                offer_asset_info: native_asset_info(DENOM.to_string()),
+                ask_asset_info: native_asset_info(usd.to_string()),
+                offer_amount: offer.into(),
+                max_hops: None,
+            },
+        )
+        .unwrap();
+    let split: SplitResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::SuggestSplit {
+                offer_asset_info: native_asset_info(DENOM.to_string()),
+                ask_asset_info: native_asset_info(usd.to_string()),
+                offer_amount: offer.into(),
+                max_hops: None,
+            },
+        )
+        .unwrap();
+    // A large order does better split over both routes than through the best single one
+    assert_eq!(split.routes.len(), 2);
+    assert!(split.return_amount > routes.routes[0].return_amount);
+
+    let split_swap = |app: &mut App, routes: Vec<SplitRoute>| {
+        app.execute_contract(
+            user.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::ExecuteSplitSwap {
+                routes,
+                minimum_receive: Some(split.return_amount),
+                to: None,
+                max_spread: Some(Decimal::percent(50)),
+            },
+            &[Coin {
+                denom: DENOM.to_string(),
+                amount: offer.into(),
+            }],
+        )
+    };
+
+    let err = split_swap(
+        &mut app,
+        vec![
+            split.routes[0].clone(),
+            SplitRoute {
+                operations: vec![SwapOperation {
+                    offer_asset_info: native_asset_info(DENOM.to_string()),
+                    ask_asset_info: native_asset_info(IBC_DENOM.to_string()),
+                    pool_id: None,
+                }],
+                weight: 1,
+            },
+        ],
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::SplitRouteMismatch {}
+    );
+
+    let err = split_swap(
+        &mut app,
+        split
+            .routes
+            .iter()
+            .map(|route| SplitRoute {
+                weight: 0,
+                ..route.clone()
+            })
+            .collect(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::ZeroSplitWeights {}
+    );
+
+    split_swap(&mut app, split.routes.clone()).unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&user, usd).unwrap().amount,
+        split.return_amount
+    );
+}
+
+#[test]
+fn test_reverse_simulation() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+    let usd = "uusd";
+
+    let mut helper = FactoryHelper::init_with_denoms(&mut app, &owner, &[DENOM, IBC_DENOM, usd]);
+    let pool_manager = helper.pool_manager.clone();
+
+    let liq = 1_000_000_000_000_000_000_000_000u128;
+    let ask = 1_000_000_000_000_000_000_000u128;
+    // One pool of every type: DENOM -> IBC_DENOM -> uusd, and DENOM -> uusd
+    for (a, b, pair_type, params) in [
+        (
+            DENOM,
+            IBC_DENOM,
+            PairType::Xyk {},
+            to_json_binary(&XykPoolParams {
+                fee_bps: 30,
+                track_asset_balances: None,
+            })
+            .unwrap(),
+        ),
+        (
+            IBC_DENOM,
+            usd,
+            PairType::Stable {},
+            to_json_binary(&StablePoolParams {
+                amp: 100,
+                fee_bps: 5,
+                track_asset_balances: None,
+            })
+            .unwrap(),
+        ),
+        (
+            DENOM,
+            usd,
+            PairType::Custom("concentrated".to_string()),
+            to_json_binary(&common_pcl_params()).unwrap(),
+        ),
+    ] {
+        helper
+            .create_seeded_pool(
+                &mut app,
+                &owner,
+                pair_type,
+                params,
+                [
+                    native_asset(a.to_string(), liq.into()),
+                    native_asset(b.to_string(), liq.into()),
+                ],
+            )
+            .unwrap();
+    }
+
+    let simulate = |operations: Vec<SwapOperation>, offer_amount: Uint128| {
+        let res: SimulateSwapOperationsResponse = app
+            .wrap()
+            .query_wasm_smart(
+                pool_manager.clone(),
+                &QueryMsg::SimulateSwapOperations {
+                    offer_amount,
+                    operations,
+                    referral_commission: None,
+                },
+            )
+            .unwrap();
+        res.amount
+    };
+    // Concentrated pools quote the offer with their maximum fee, so they may return a little more
+    let assert_close = |amount: Uint128| {
+        assert!(
+            amount >= Uint128::from(ask * 999 / 1000) && amount <= Uint128::from(ask * 1005 / 1000),
+            "{amount} is not close to {ask}"
+        );
+    };
+    let operation = |offer: &str, ask: &str, pool_id: u64| SwapOperation {
+        offer_asset_info: native_asset_info(offer.to_string()),
+        ask_asset_info: native_asset_info(ask.to_string()),
+        pool_id: Some(pool_id),
+    };
+
+    // Swapping the reverse simulated offer returns the requested amount in every pool type
+    for (offer_denom, ask_denom, pool_id) in
+        [(DENOM, IBC_DENOM, 1), (IBC_DENOM, usd, 2), (DENOM, usd, 3)]
+    {
+        let reverse: ReverseSimulationResponse = app
+            .wrap()
+            .query_wasm_smart(
+                pool_manager.clone(),
+                &QueryMsg::ReverseSimulation {
+                    pool_key: pool_key(
+                        &[
+                            native_asset_info(offer_denom.to_string()),
+                            native_asset_info(ask_denom.to_string()),
+                        ],
+                        pool_id,
+                    ),
+                    ask_asset: native_asset(ask_denom.to_string(), ask.into()),
+                },
+            )
+            .unwrap();
+        assert!(reverse.offer_amount > Uint128::from(ask));
+        assert!(!reverse.commission_amount.is_zero());
+        assert_close(simulate(
+            vec![operation(offer_denom, ask_denom, pool_id)],
+            reverse.offer_amount,
+        ));
+    }
+
+    let route = vec![operation(DENOM, IBC_DENOM, 1), operation(IBC_DENOM, usd, 2)];
+    let reverse: ReverseSwapOperationsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::SimulateReverseSwapOperations {
+                ask_amount: ask.into(),
+                operations: route.clone(),
+            },
+        )
+        .unwrap();
+    assert_eq!(reverse.operations.len(), 2);
+    assert_eq!(reverse.operations[0].offer_amount, reverse.offer_amount);
+    assert_close(simulate(route.clone(), reverse.offer_amount));
+
+    // The pool cannot return more than it holds
+    let err = app
+        .wrap()
+        .query_wasm_smart::<ReverseSwapOperationsResponse>(
+            pool_manager.clone(),
+            &QueryMsg::SimulateReverseSwapOperations {
+                ask_amount: liq.into(),
+                operations: route,
+            },
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains("Insufficient liquidity"));
+}
+
+#[test]
+fn test_detailed_simulation() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+    let usd = "uusd";
+
+    let mut helper = FactoryHelper::init_with_denoms(&mut app, &owner, &[DENOM, IBC_DENOM, usd]);
+    let pool_manager = helper.pool_manager.clone();
+
+    let liq = 1_000_000_000_000_000_000_000_000u128;
+    let offer = 1_000_000_000_000_000_000_000u128;
+    // DENOM -> IBC_DENOM through xyk, IBC_DENOM -> uusd through a concentrated pool
+    for (a, b, pair_type, params) in [
+        (
+            DENOM,
+            IBC_DENOM,
+            PairType::Xyk {},
+            to_json_binary(&XykPoolParams {
+                fee_bps: 30,
+                track_asset_balances: None,
+            })
+            .unwrap(),
+        ),
+        (
+            IBC_DENOM,
+            usd,
+            PairType::Custom("concentrated".to_string()),
+            to_json_binary(&common_pcl_params()).unwrap(),
+        ),
+    ] {
+        helper
+            .create_seeded_pool(
+                &mut app,
+                &owner,
+                pair_type,
+                params,
+                [
+                    native_asset(a.to_string(), liq.into()),
+                    native_asset(b.to_string(), liq.into()),
+                ],
+            )
+            .unwrap();
+    }
+
+    let operations = vec![
+        SwapOperation {
+            offer_asset_info: native_asset_info(DENOM.to_string()),
+            ask_asset_info: native_asset_info(IBC_DENOM.to_string()),
+            pool_id: None,
+        },
+        SwapOperation {
+            offer_asset_info: native_asset_info(IBC_DENOM.to_string()),
+            ask_asset_info: native_asset_info(usd.to_string()),
+            pool_id: None,
+        },
+    ];
+    let detailed: DetailedSimulationResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::SimulateSwapOperationsDetailed {
+                offer_amount: offer.into(),
+                operations: operations.clone(),
+                referral_commission: None,
+            },
+        )
+        .unwrap();
+    let simulation: SimulateSwapOperationsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::SimulateSwapOperations {
+                offer_amount: offer.into(),
+                operations: operations.clone(),
+                referral_commission: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(detailed.return_amount, simulation.amount);
+    assert_eq!(detailed.hops.len(), 2);
+    assert_eq!(
+        detailed.hops[0].pool_key,
+        pool_key(
+            &[native_asset_info(DENOM.to_string()), native_asset_info(IBC_DENOM.to_string())],
+            1
+        )
+    );
+    assert_eq!(detailed.hops[0].offer_amount, Uint128::from(offer));
+    assert_eq!(detailed.hops[1].offer_amount, detailed.hops[0].return_amount);
+    assert_eq!(detailed.hops[1].return_amount, detailed.return_amount);
+
+    for hop in &detailed.hops {
+        // Balanced pools start at a mid price of 1 which the swap pushes down
+        assert!(hop.mid_price_before.abs_diff(Decimal256::one()) < Decimal256::permille(1));
+        assert!(hop.mid_price_after < hop.mid_price_before);
+        assert!(!hop.commission_amount.is_zero());
+        assert!(hop.price_impact < Decimal256::percent(1));
+    }
+    // Swapping 0.1% of an xyk pool moves the price by about as much
+    assert!(!detailed.hops[0].price_impact.is_zero());
+
+    // Bad paths return an error instead of crashing the query
+    let err = app
+        .wrap()
+        .query_wasm_smart::<SimulateSwapOperationsResponse>(
+            pool_manager.clone(),
+            &QueryMsg::SimulateSwapOperations {
+                offer_amount: offer.into(),
+                operations: vec![SwapOperation {
+                    offer_asset_info: native_asset_info(DENOM.to_string()),
+                    ask_asset_info: native_asset_info(usd.to_string()),
+                    pool_id: None,
+                }],
+                referral_commission: None,
+            },
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains("Pool not found"));
+}
+
+#[test]
+fn test_swap_exact_out() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+    let user = Addr::unchecked("user");
+    let receiver = Addr::unchecked("receiver");
+
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let token_x = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOX", None);
+
+    let liq = 1_000_000_000_000_000_000_000_000u128;
+    let token_liq = 1_000_000_000_000u128;
+    let budget = 10_000_000_000_000_000_000_000u128;
+    mint_native(&mut app, DENOM, budget, &user).unwrap();
+    mint(&mut app, &owner, &token_x, token_liq, &user).unwrap();
+
+    // DENOM -> IBC_DENOM -> token_x
+    for assets in [
+        [
+            native_asset(DENOM.to_string(), liq.into()),
+            native_asset(IBC_DENOM.to_string(), liq.into()),
+        ],
+        [
+            native_asset(IBC_DENOM.to_string(), liq.into()),
+            token_asset(token_x.clone(), token_liq.into()),
+        ],
+    ] {
+        helper.create_seeded_xyk_pool(&mut app, &owner, assets).unwrap();
+    }
+
+    let operations = vec![
+        SwapOperation {
+            offer_asset_info: native_asset_info(DENOM.to_string()),
+            ask_asset_info: native_asset_info(IBC_DENOM.to_string()),
+            pool_id: None,
+        },
+        SwapOperation {
+            offer_asset_info: native_asset_info(IBC_DENOM.to_string()),
+            ask_asset_info: token_asset_info(token_x.clone()),
+            pool_id: None,
+        },
+    ];
+    let ask_amount = Uint128::from(1_000_000_000u128);
+    let reverse: ReverseSwapOperationsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::SimulateReverseSwapOperations {
+                ask_amount,
+                operations: operations.clone(),
+            },
+        )
+        .unwrap();
+
+    let swap_exact_out = |app: &mut App, max_offer: Option<Uint128>| {
+        app.execute_contract(
+            user.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::SwapExactOut {
+                operations: operations.clone(),
+                ask_amount,
+                max_offer,
+                to: Some(receiver.to_string()),
+                max_spread: None,
+            },
+            &[Coin {
+                denom: DENOM.to_string(),
+                amount: budget.into(),
+            }],
+        )
+    };
+
+    let max_offer = reverse.offer_amount - Uint128::one();
+    let err = swap_exact_out(&mut app, Some(max_offer)).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::MaxOfferExceeded {
+            offer_amount: reverse.offer_amount,
+            max_offer,
+        }
+    );
+
+    swap_exact_out(&mut app, Some(reverse.offer_amount)).unwrap();
+    let token_balance = |app: &App, address: &Addr| {
+        let res: cw20::BalanceResponse = app
+            .wrap()
+            .query_wasm_smart(
+                token_x.clone(),
+                &cw20::Cw20QueryMsg::Balance {
+                    address: address.to_string(),
+                },
+            )
+            .unwrap();
+        res.balance
+    };
+    assert_eq!(token_balance(&app, &receiver), ask_amount);
+    // Only the reverse simulated offer is spent, the rest of the budget is refunded
+    assert_eq!(
+        app.wrap().query_balance(&user, DENOM).unwrap().amount,
+        Uint128::from(budget) - reverse.offer_amount
+    );
+
+    // Exact output through the cw20 hook
+    let ask_amount = Uint128::from(1_000_000_000_000_000_000u128);
+    let user_tokens = token_balance(&app, &user);
+    app.execute_contract(
+        user.clone(),
+        token_x.clone(),
+        &Cw20ExecuteMsg::Send {
+            contract: pool_manager.to_string(),
+            amount: 10_000_000u128.into(),
+            msg: to_json_binary(&Cw20HookMsg::SwapExactOut {
+                operations: vec![SwapOperation {
+                    offer_asset_info: token_asset_info(token_x.clone()),
+                    ask_asset_info: native_asset_info(IBC_DENOM.to_string()),
+                    pool_id: None,
+                }],
+                ask_amount,
+                max_offer: None,
+                to: Some(receiver.to_string()),
+                max_spread: None,
+            })
+            .unwrap(),
+        },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(app.wrap().query_balance(&receiver, IBC_DENOM).unwrap().amount, ask_amount);
+    let spent = user_tokens - token_balance(&app, &user);
+    assert!(!spent.is_zero() && spent < Uint128::from(10_000_000u128));
+}
+
+#[test]
+fn test_deadlines() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let asset_infos = [
+        native_asset_info(DENOM.to_string()),
+        native_asset_info(IBC_DENOM.to_string()),
+    ];
+    helper.create_xyk_pair(&mut app, &owner, asset_infos.clone()).unwrap();
+
+    let liq = 1_000_000_000_000_000_000_000_000u128;
+    let offer = 1_000_000_000_000_000_000_000u128;
+    mint_native(&mut app, DENOM, 2 * liq, &owner).unwrap();
+    mint_native(&mut app, IBC_DENOM, 2 * liq, &owner).unwrap();
+
+    let block = app.block_info();
+    let expired = [
+        Expiration::AtHeight(block.height),
+        Expiration::AtTime(block.time.minus_seconds(1)),
+    ];
+    let pending = Expiration::AtHeight(block.height + 1);
+    let assert_expired = |err: anyhow::Error, deadline: Expiration| {
+        assert_eq!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::DeadlinePassed(deadline)
+        );
+    };
+
+    let provide = |app: &mut App, deadline: Option<Expiration>| {
+        app.execute_contract(
+            owner.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::ProvideLiquidity {
+                assets: vec![
+                    native_asset(DENOM.to_string(), liq.into()),
+                    native_asset(IBC_DENOM.to_string(), liq.into()),
+                ],
+                slippage_tolerance: None,
+                auto_stake: None,
+                receiver: None,
+                pool_id: None,
+                deadline,
+            },
+            &[
+                Coin {
+                    denom: DENOM.to_string(),
+                    amount: liq.into(),
+                },
+                Coin {
+                    denom: IBC_DENOM.to_string(),
+                    amount: liq.into(),
+                },
+            ],
+        )
+    };
+    for deadline in expired {
+        assert_expired(provide(&mut app, Some(deadline)).unwrap_err(), deadline);
+    }
+    provide(&mut app, Some(pending)).unwrap();
+
+    let swap = |app: &mut App, deadline: Option<Expiration>| {
+        app.execute_contract(
+            owner.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::ExecuteSwapOperations {
+                operations: vec![SwapOperation {
+                    offer_asset_info: asset_infos[0].clone(),
+                    ask_asset_info: asset_infos[1].clone(),
+                    pool_id: None,
+                }],
+                minimum_receive: None,
+                minimum_receive_per_hop: None,
+                to: None,
+                max_spread: None,
+                belief_price: None,
+                deadline,
+                callback: None,
+                referral_address: None,
+                referral_commission: None,
+            },
+            &[Coin {
+                denom: DENOM.to_string(),
+                amount: offer.into(),
+            }],
+        )
+    };
+    for deadline in expired {
+        assert_expired(swap(&mut app, Some(deadline)).unwrap_err(), deadline);
+    }
+    swap(&mut app, Some(pending)).unwrap();
+
+    let modify = |app: &mut App, deadline: Option<Expiration>| {
+        app.execute_contract(
+            owner.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::ModifyPosition {
+                assets: vec![
+                    native_asset(DENOM.to_string(), offer.into()),
+                    native_asset(IBC_DENOM.to_string(), offer.into()),
+                ],
+                position_id: "1".to_string(),
+                modification_type: PositionModification::Increase,
+                slippage_tolerance: Some(Decimal::percent(50)),
+                pool_id: None,
+                deadline,
+            },
+            &[
+                Coin {
+                    denom: DENOM.to_string(),
+                    amount: offer.into(),
+                },
+                Coin {
+                    denom: IBC_DENOM.to_string(),
+                    amount: offer.into(),
+                },
+            ],
+        )
+    };
+    assert_expired(modify(&mut app, Some(expired[0])).unwrap_err(), expired[0]);
+
+    let pair_info: PairInfo = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::PairByAssets {
+                asset_infos: asset_infos.to_vec(),
+                pool_id: None,
+            },
+        )
+        .unwrap();
+    let withdraw = |app: &mut App, deadline: Option<Expiration>| {
+        app.execute_contract(
+            owner.clone(),
+            pair_info.liquidity_token.clone(),
+            &Cw20ExecuteMsg::Send {
+                contract: pool_manager.to_string(),
+                amount: 1_000_000u128.into(),
+                msg: to_json_binary(&Cw20HookMsg::WithdrawLiquidity {
+                    assets: vec![],
+                    deadline,
+                })
+                .unwrap(),
+            },
+            &[],
+        )
+    };
+    assert_expired(withdraw(&mut app, Some(expired[1])).unwrap_err(), expired[1]);
+    withdraw(&mut app, Some(pending)).unwrap();
+}
+
+#[test]
+fn test_limit_orders() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+    let user = Addr::unchecked("user");
+    let keeper = Addr::unchecked("keeper");
+
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let asset_infos = [
+        native_asset_info(DENOM.to_string()),
+        native_asset_info(IBC_DENOM.to_string()),
+    ];
+
+    let liq = 1_000_000_000_000_000_000_000_000u128;
+    let small = 1_000_000_000_000_000_000_000u128;
+    let large = 100_000_000_000_000_000_000_000u128;
+    mint_native(&mut app, DENOM, small * 3 + large, &user).unwrap();
+    helper
+        .create_seeded_xyk_pool(
+            &mut app,
+            &owner,
+            [
+                native_asset(DENOM.to_string(), liq.into()),
+                native_asset(IBC_DENOM.to_string(), liq.into()),
+            ],
+        )
+        .unwrap();
+
+    let place = |app: &mut App, amount: u128, limit_price: Decimal, expiry: Option<Expiration>| {
+        app.execute_contract(
+            user.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::PlaceLimitOrder {
+                ask_asset_info: asset_infos[1].clone(),
+                limit_price,
+                pool_id: None,
+                expiry,
+            },
+            &[Coin {
+                denom: DENOM.to_string(),
+                amount: amount.into(),
+            }],
+        )
+    };
+    let err = place(&mut app, small, Decimal::zero(), None).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InvalidLimitPrice {}
+    );
+
+    // 1: below the pool price after fees, 2: fills in full, 3: fills in part,
+    // 4: below the pool price and expires
+    let expiry = Expiration::AtHeight(app.block_info().height + 10);
+    place(&mut app, small, Decimal::one(), None).unwrap();
+    place(&mut app, small, Decimal::percent(101), None).unwrap();
+    place(&mut app, large, Decimal::percent(105), None).unwrap();
+    place(&mut app, small, Decimal::one(), Some(expiry)).unwrap();
+
+    let order = |app: &App, order_id: u64| {
+        app.wrap()
+            .query_wasm_smart::<LimitOrder>(pool_manager.clone(), &QueryMsg::LimitOrder { order_id })
+    };
+    let pool_key = order(&app, 1).unwrap().pool_key;
+
+    let by_owner: LimitOrdersResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::LimitOrdersByOwner {
+                owner: user.to_string(),
+                start_after: Some(1),
+                limit: Some(2),
+            },
+        )
+        .unwrap();
+    assert_eq!(by_owner.orders.iter().map(|o| o.id).collect::<Vec<_>>(), vec![2, 3]);
+
+    let execute_orders = |app: &mut App, order_ids: Vec<u64>| {
+        app.execute_contract(
+            keeper.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::ExecuteLimitOrders {
+                pool_key: pool_key.clone(),
+                order_ids,
+            },
+            &[],
+        )
+    };
+    let err = execute_orders(&mut app, vec![1, 4]).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NoFillableOrders {}
+    );
+    let err = execute_orders(&mut app, vec![5]).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::LimitOrderNotFound(5)
+    );
+
+    execute_orders(&mut app, vec![1, 2, 3]).unwrap();
+
+    // Order 2 is filled in full and removed
+    assert!(order(&app, 2).is_err());
+    // Order 3 is filled up to the limit price and keeps the rest in escrow
+    let partial = order(&app, 3).unwrap();
+    assert!(!partial.filled_amount.is_zero());
+    assert!(!partial.offer_asset.amount.is_zero());
+    assert_eq!(partial.filled_amount + partial.offer_asset.amount, Uint128::from(large));
+    assert!(partial.received_amount * Decimal::percent(105) >= partial.filled_amount);
+    // Order 1 is untouched
+    assert!(order(&app, 1).unwrap().filled_amount.is_zero());
+
+    let user_ibc = app.wrap().query_balance(&user, IBC_DENOM).unwrap().amount;
+    let keeper_ibc = app.wrap().query_balance(&keeper, IBC_DENOM).unwrap().amount;
+    assert!(user_ibc > partial.received_amount);
+    // The keeper earns 10 bps of every fill
+    let tip = keeper_ibc.u128() as f64 / (user_ibc + keeper_ibc).u128() as f64;
+    assert!((tip - 0.001).abs() < 1e-9);
+
+    // Only the owner can cancel
+    let cancel = |app: &mut App, sender: &Addr, order_id: u64| {
+        app.execute_contract(
+            sender.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::CancelLimitOrder { order_id },
+            &[],
+        )
+    };
+    let err = cancel(&mut app, &keeper, 3).unwrap_err();
+    assert_eq!(err.downcast::<ContractError>().unwrap(), ContractError::Unauthorized {});
+    let user_denom = app.wrap().query_balance(&user, DENOM).unwrap().amount;
+    cancel(&mut app, &user, 3).unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&user, DENOM).unwrap().amount,
+        user_denom + partial.offer_asset.amount
+    );
+
+    // Expired orders are refunded by the keeper
+    app.update_block(|block| block.height += 10);
+    let user_denom = app.wrap().query_balance(&user, DENOM).unwrap().amount;
+    execute_orders(&mut app, vec![4]).unwrap();
+    assert!(order(&app, 4).is_err());
+    assert_eq!(
+        app.wrap().query_balance(&user, DENOM).unwrap().amount,
+        user_denom + Uint128::from(small)
+    );
+
+    let by_pool: LimitOrdersResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::LimitOrdersByPool {
+                pool_key: pool_key.clone(),
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(by_pool.orders.iter().map(|o| o.id).collect::<Vec<_>>(), vec![1]);
+}
+
+#[test]
+fn test_dca() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+    let user = Addr::unchecked("user");
+    let keeper = Addr::unchecked("keeper");
+    let treasury = Addr::unchecked("treasury");
+
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let asset_infos = [
+        native_asset_info(DENOM.to_string()),
+        native_asset_info(IBC_DENOM.to_string()),
+    ];
+
+    let liq = 1_000_000_000_000_000_000_000_000u128;
+    let budget = 3_000_000_000_000_000_000_001u128;
+    mint_native(&mut app, DENOM, 3 * budget, &user).unwrap();
+    helper
+        .create_seeded_xyk_pool(
+            &mut app,
+            &owner,
+            [
+                native_asset(DENOM.to_string(), liq.into()),
+                native_asset(IBC_DENOM.to_string(), liq.into()),
+            ],
+        )
+        .unwrap();
+
+    let interval = 3_600u64;
+    let create = |app: &mut App, max_price: Decimal, keeper_tip_bps: u16| {
+        app.execute_contract(
+            user.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::CreateDca {
+                operations: vec![SwapOperation {
+                    offer_asset_info: asset_infos[0].clone(),
+                    ask_asset_info: asset_infos[1].clone(),
+                    pool_id: None,
+                }],
+                tranches: 3,
+                interval,
+                max_price,
+                max_spread: None,
+                keeper_tip_bps,
+            },
+            &[Coin {
+                denom: DENOM.to_string(),
+                amount: budget.into(),
+            }],
+        )
+    };
+    let err = create(&mut app, Decimal::percent(105), 101).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::KeeperTipTooHigh {
+            tip_bps: 101,
+            max_bps: 100,
+        }
+    );
+    // 1: bounded above the pool price, 2: bounded below the pool price after fees
+    create(&mut app, Decimal::percent(105), 10).unwrap();
+    create(&mut app, Decimal::one(), 10).unwrap();
+
+    let execute_tranche = |app: &mut App, schedule_id: u64| {
+        app.execute_contract(
+            keeper.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::ExecuteDcaTranche { schedule_id },
+            &[],
+        )
+    };
+    let schedule = |app: &App, schedule_id: u64| {
+        app.wrap().query_wasm_smart::<DcaSchedule>(
+            pool_manager.clone(),
+            &QueryMsg::DcaSchedule { schedule_id },
+        )
+    };
+
+    let err = execute_tranche(&mut app, 2).unwrap_err();
+    assert!(matches!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::AssertionMinimumReceive { .. }
+    ));
+
+    execute_tranche(&mut app, 1).unwrap();
+    let next_execution = app.block_info().time.plus_seconds(interval);
+    let err = execute_tranche(&mut app, 1).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::DcaTrancheNotDue(next_execution)
+    );
+    let pending = schedule(&app, 1).unwrap();
+    assert_eq!(pending.tranches_left, 2);
+    assert_eq!(pending.offer_asset.amount, Uint128::from(budget) - pending.tranche_amount);
+    assert_eq!(
+        app.wrap().query_balance(&user, IBC_DENOM).unwrap().amount,
+        pending.received_amount
+    );
+    // The keeper earns its tip in the ask asset
+    let keeper_ibc = app.wrap().query_balance(&keeper, IBC_DENOM).unwrap().amount;
+    assert_eq!(
+        keeper_ibc,
+        (keeper_ibc + pending.received_amount).multiply_ratio(10u128, 10_000u128)
+    );
+
+    let by_owner: DcaSchedulesResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::DcaSchedulesByOwner {
+                owner: user.to_string(),
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(by_owner.schedules.len(), 2);
+
+    // Only the owner can cancel, and the rest can be withdrawn to another address
+    let cancel = |app: &mut App, sender: &Addr| {
+        app.execute_contract(
+            sender.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::CancelDca {
+                schedule_id: 2,
+                to: Some(treasury.to_string()),
+            },
+            &[],
+        )
+    };
+    let err = cancel(&mut app, &keeper).unwrap_err();
+    assert_eq!(err.downcast::<ContractError>().unwrap(), ContractError::Unauthorized {});
+    cancel(&mut app, &user).unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&treasury, DENOM).unwrap().amount,
+        Uint128::from(budget)
+    );
+
+    for _ in 0..2 {
+        app.update_block(|block| block.time = block.time.plus_seconds(interval));
+        execute_tranche(&mut app, 1).unwrap();
+    }
+    // The last tranche swaps the remainder and removes the schedule
+    assert!(schedule(&app, 1).is_err());
+    assert_eq!(
+        app.wrap().query_balance(&user, DENOM).unwrap().amount,
+        Uint128::from(budget)
+    );
+    let pending: DcaSchedulesResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::DcaSchedules {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert!(pending.schedules.is_empty());
+}
+
+/// A callback contract that checks it holds the swap return and fails on a "fail" payload.
+fn callback_execute(
+    deps: DepsMut,
+    env: Env,
+    _info: MessageInfo,
+    msg: SwapCallbackExecuteMsg,
+) -> StdResult<Response> {
+    let SwapCallbackExecuteMsg::SwapCallback(callback) = msg;
+    if callback.msg.as_slice() == b"fail" {
+        return Err(StdError::generic_err("callback failed"));
+    }
+    let balance = callback
+        .asset
+        .info
+        .query_pool(&deps.querier, env.contract.address)?;
+    if balance < callback.asset.amount {
+        return Err(StdError::generic_err("return not received"));
+    }
+
+    Ok(Response::new().add_attributes(vec![
+        attr("callback_sender", callback.sender),
+        attr("callback_amount", callback.asset.amount),
+        attr("callback_msg", callback.msg.to_base64()),
+    ]))
+}
+
+fn callback_instantiate(
+    _deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    _msg: Empty,
+) -> StdResult<Response> {
+    Ok(Response::new())
+}
+
+fn callback_query(_deps: Deps, _env: Env, _msg: Empty) -> StdResult<Binary> {
+    Ok(Binary::default())
+}
+
+#[test]
+fn test_swap_callback() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+    let user = Addr::unchecked("user");
+
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let callback_code_id = app.store_code(Box::new(ContractWrapper::new_with_empty(
+        callback_execute,
+        callback_instantiate,
+        callback_query,
+    )));
+    let vault = app
+        .instantiate_contract(callback_code_id, owner.clone(), &Empty {}, &[], "vault", None)
+        .unwrap();
+
+    let asset_infos = [
+        native_asset_info(DENOM.to_string()),
+        native_asset_info(IBC_DENOM.to_string()),
+    ];
+
+    let liq = 1_000_000_000_000_000_000_000_000u128;
+    let offer = 1_000_000_000_000_000_000_000u128;
+    mint_native(&mut app, DENOM, 3 * offer, &user).unwrap();
+    helper
+        .create_seeded_xyk_pool(
+            &mut app,
+            &owner,
+            [
+                native_asset(DENOM.to_string(), liq.into()),
+                native_asset(IBC_DENOM.to_string(), liq.into()),
+            ],
+        )
+        .unwrap();
+
+    let funds = [Coin {
+        denom: DENOM.to_string(),
+        amount: offer.into(),
+    }];
+    let callback = |msg: &[u8]| {
+        Some(SwapCallback {
+            contract: vault.to_string(),
+            msg: Binary::from(msg),
+        })
+    };
+
+    let res = app
+        .execute_contract(
+            user.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::Swap {
+                offer_asset: native_asset(DENOM.to_string(), offer.into()),
+                ask_asset_info: asset_infos[1].clone(),
+                belief_price: None,
+                max_spread: None,
+                to: None,
+                pool_id: None,
+                callback: callback(b"deposit"),
+                referral_address: None,
+                referral_commission: None,
+            },
+            &funds,
+        )
+        .unwrap();
+    let received = app.wrap().query_balance(&vault, IBC_DENOM).unwrap().amount;
+    assert!(!received.is_zero());
+    assert!(app.wrap().query_balance(&user, IBC_DENOM).unwrap().amount.is_zero());
+    let callback_event = res
+        .events
+        .iter()
+        .find(|event| {
+            event.ty == "wasm" && event.attributes.iter().any(|a| a.key == "callback_amount")
+        })
+        .unwrap();
+    assert!(callback_event.attributes.contains(&attr("callback_sender", user.to_string())));
+    assert!(callback_event.attributes.contains(&attr("callback_amount", received)));
+    assert!(callback_event
+        .attributes
+        .contains(&attr("callback_msg", Binary::from(b"deposit").to_base64())));
+
+    let swap_operations = |app: &mut App, to: Option<String>, msg: &[u8]| {
+        app.execute_contract(
+            user.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::ExecuteSwapOperations {
+                operations: vec![SwapOperation {
+                    offer_asset_info: asset_infos[0].clone(),
+                    ask_asset_info: asset_infos[1].clone(),
+                    pool_id: None,
+                }],
+                minimum_receive: None,
+                minimum_receive_per_hop: None,
+                to,
+                max_spread: None,
+                belief_price: None,
+                deadline: None,
+                callback: callback(msg),
+                referral_address: None,
+                referral_commission: None,
+            },
+            &funds,
+        )
+    };
+
+    let err = swap_operations(&mut app, Some(user.to_string()), b"deposit").unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::CallbackWithRecipient {}
+    );
+
+    // A failing callback reverts the whole swap
+    let user_balance = app.wrap().query_balance(&user, DENOM).unwrap().amount;
+    swap_operations(&mut app, None, b"fail").unwrap_err();
+    assert_eq!(app.wrap().query_balance(&user, DENOM).unwrap().amount, user_balance);
+    assert_eq!(app.wrap().query_balance(&vault, IBC_DENOM).unwrap().amount, received);
+
+    swap_operations(&mut app, None, b"deposit").unwrap();
+    assert!(app.wrap().query_balance(&vault, IBC_DENOM).unwrap().amount > received);
+}
+
+/// A flash loan borrower that repays, repays without the fee or borrows again depending on
+/// the payload.
+fn borrower_execute(
+    _deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    msg: FlashLoanCallbackExecuteMsg,
+) -> StdResult<Response> {
+    let FlashLoanCallbackExecuteMsg::FlashLoanCallback(loan) = msg;
+    let mut messages = vec![];
+    for (asset, fee) in loan.assets.iter().zip(&loan.fees) {
+        let amount = match loan.msg.as_slice() {
+            b"short" => asset.amount,
+            _ => asset.amount + fee.amount,
+        };
+        messages.push(
+            Asset {
+                info: asset.info.clone(),
+                amount,
+            }
+            .into_msg(&info.sender)?,
+        );
+    }
+    if loan.msg.as_slice() == b"reenter" {
+        messages.push(
+            wasm_execute(
+                &info.sender,
+                &ExecuteMsg::UpdateManagerConfig {
+                    owner: None,
+                    token_code_id: None,
+                    allowed_denoms: None,
+                    fee_address: None,
+                    guardian: None,
+                    flash_loan_fee_bps: Some(0),
+                    max_referral_commission: None,
+                },
+                vec![],
+            )?
+            .into(),
+        );
+    }
+
+    Ok(Response::new().add_messages(messages))
+}
+
+#[test]
+fn test_flash_loan() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+    let user = Addr::unchecked("user");
+
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let borrower_code_id = app.store_code(Box::new(ContractWrapper::new_with_empty(
+        borrower_execute,
+        callback_instantiate,
+        callback_query,
+    )));
+    let borrower = app
+        .instantiate_contract(borrower_code_id, owner.clone(), &Empty {}, &[], "borrower", None)
+        .unwrap();
+    let token_x = instantiate_token(&mut app, helper.cw20_token_code_id, &owner, "TOX", None);
+
+    let asset_infos = [
+        native_asset_info(DENOM.to_string()),
+        token_asset_info(token_x.clone()),
+    ];
+
+    let liq = 1_000_000_000_000_000_000_000_000u128;
+    let token_liq = 1_000_000_000_000u128;
+    // The borrower pays the fees out of its own funds
+    mint_native(&mut app, DENOM, liq, &borrower).unwrap();
+    mint(&mut app, &owner, &token_x, token_liq, &borrower).unwrap();
+    helper
+        .create_seeded_xyk_pool(
+            &mut app,
+            &owner,
+            [
+                native_asset(DENOM.to_string(), liq.into()),
+                token_asset(token_x.clone(), token_liq.into()),
+            ],
+        )
+        .unwrap();
+
+    let update_fee = |app: &mut App, sender: &Addr, fee_bps: u16| {
+        app.execute_contract(
+            sender.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::UpdateManagerConfig {
+                owner: None,
+                token_code_id: None,
+                allowed_denoms: None,
+                fee_address: None,
+                guardian: None,
+                flash_loan_fee_bps: Some(fee_bps),
+                max_referral_commission: None,
+            },
+            &[],
+        )
+    };
+    let err = update_fee(&mut app, &user, 9).unwrap_err();
+    assert_eq!(err.downcast::<ContractError>().unwrap(), ContractError::Unauthorized {});
+    let err = update_fee(&mut app, &owner, 1_001).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::FlashLoanFeeTooHigh {
+            fee_bps: 1_001,
+            max_bps: 1_000,
+        }
+    );
+    update_fee(&mut app, &owner, 9).unwrap();
+    let config: ManagerConfig = app
+        .wrap()
+        .query_wasm_smart(pool_manager.clone(), &QueryMsg::ManagerConfig {})
+        .unwrap();
+    assert_eq!(config.flash_loan_fee_bps, 9);
+
+    let pool_key = pool_key(&asset_infos, 1);
+    let pool = |app: &App| {
+        app.wrap()
+            .query_wasm_smart::<PoolResponse>(
+                pool_manager.clone(),
+                &QueryMsg::Pool {
+                    pool_key: pool_key.clone(),
+                },
+            )
+            .unwrap()
+            .assets
+    };
+    let flash_loan = |app: &mut App, amounts: [u128; 2], msg: &[u8]| {
+        app.execute_contract(
+            user.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::FlashLoan {
+                pool_key: pool_key.clone(),
+                assets: vec![
+                    native_asset(DENOM.to_string(), amounts[0].into()),
+                    token_asset(token_x.clone(), amounts[1].into()),
+                ],
+                callback: FlashLoanCallback {
+                    contract: borrower.to_string(),
+                    msg: Binary::from(msg),
+                },
+            },
+            &[],
+        )
+    };
+    let loan = [liq / 100, 1_000_000_000u128];
+
+    let err = flash_loan(&mut app, [liq + 1, 1], b"repay").unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InsufficientLiquidity {}
+    );
+
+    let reserves = pool(&app);
+    let err = flash_loan(&mut app, loan, b"short").unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::FlashLoanNotRepaid {
+            asset: DENOM.to_string(),
+            expected: Uint128::from(liq + liq / 100 * 9 / 10_000),
+            actual: liq.into(),
+        }
+    );
+    // The borrower cannot call back into the manager while it holds the loan
+    let err = flash_loan(&mut app, loan, b"reenter").unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::FlashLoanInProgress {}
+    );
+    assert_eq!(pool(&app), reserves);
+
+    flash_loan(&mut app, loan, b"repay").unwrap();
+    // The fees stay in the pool reserves
+    let fees = [liq / 100 * 9 / 10_000, 900_000u128];
+    assert_eq!(
+        pool(&app).iter().map(|asset| asset.amount).collect::<Vec<_>>(),
+        vec![Uint128::from(liq + fees[0]), Uint128::from(token_liq + fees[1])]
+    );
+    assert_eq!(
+        app.wrap().query_balance(&borrower, DENOM).unwrap().amount,
+        Uint128::from(liq - fees[0])
+    );
+
+    // Once the owner sets a fee address, the fees go there instead of the pool reserves
+    let treasury = Addr::unchecked("treasury");
+    app.execute_contract(
+        owner.clone(),
+        pool_manager.clone(),
+        &ExecuteMsg::UpdateManagerConfig {
+            owner: None,
+            token_code_id: None,
+            allowed_denoms: None,
+            fee_address: Some(treasury.to_string()),
+            guardian: None,
+            flash_loan_fee_bps: None,
+            max_referral_commission: None,
+        },
+        &[],
+    )
+    .unwrap();
+    let reserves = pool(&app);
+    flash_loan(&mut app, loan, b"repay").unwrap();
+    assert_eq!(pool(&app), reserves);
+    assert_eq!(
+        app.wrap().query_balance(&treasury, DENOM).unwrap().amount,
+        Uint128::from(fees[0])
+    );
+    let treasury_tokens: cw20::BalanceResponse = app
+        .wrap()
+        .query_wasm_smart(
+            token_x.clone(),
+            &cw20::Cw20QueryMsg::Balance {
+                address: treasury.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(treasury_tokens.balance, Uint128::from(fees[1]));
+
+    // The manager accepts other messages again once the loan is repaid
+    update_fee(&mut app, &owner, 0).unwrap();
+}
+
+#[test]
+fn test_referral_commission() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+    let user = Addr::unchecked("user");
+    let frontend = Addr::unchecked("frontend");
+
+    let mut helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let asset_infos = [
+        native_asset_info(DENOM.to_string()),
+        native_asset_info(IBC_DENOM.to_string()),
+    ];
+
+    let liq = 1_000_000_000_000_000_000_000_000u128;
+    let offer = 1_000_000_000_000_000_000_000u128;
+    mint_native(&mut app, DENOM, 10 * offer, &user).unwrap();
+    helper
+        .create_seeded_xyk_pool(
+            &mut app,
+            &owner,
+            [
+                native_asset(DENOM.to_string(), liq.into()),
+                native_asset(IBC_DENOM.to_string(), liq.into()),
+            ],
+        )
+        .unwrap();
+
+    let operations = vec![SwapOperation {
+        offer_asset_info: asset_infos[0].clone(),
+        ask_asset_info: asset_infos[1].clone(),
+        pool_id: None,
+    }];
+    let swap_operations =
+        |app: &mut App, referral_address: Option<String>, commission: Decimal| {
+            app.execute_contract(
+                user.clone(),
+                pool_manager.clone(),
+                &ExecuteMsg::ExecuteSwapOperations {
+                    operations: operations.clone(),
+                    minimum_receive: None,
+                    minimum_receive_per_hop: None,
+                    to: None,
+                    max_spread: None,
+                    belief_price: None,
+                    deadline: None,
+                    callback: None,
+                    referral_address,
+                    referral_commission: Some(commission),
+                },
+                &[Coin {
+                    denom: DENOM.to_string(),
+                    amount: offer.into(),
+                }],
+            )
+        };
+    let update_max = |app: &mut App, commission: Decimal| {
+        app.execute_contract(
+            owner.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::UpdateManagerConfig {
+                owner: None,
+                token_code_id: None,
+                allowed_denoms: None,
+                fee_address: None,
+                guardian: None,
+                flash_loan_fee_bps: None,
+                max_referral_commission: Some(commission),
+            },
+            &[],
+        )
+    };
+    let commission = Decimal::percent(1);
+
+    // Referrals are disabled until the owner sets a maximum
+    let err = swap_operations(&mut app, Some(frontend.to_string()), commission).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::ReferralCommissionTooHigh {
+            commission,
+            max_commission: Decimal::zero(),
+        }
+    );
+    let err = update_max(&mut app, Decimal::percent(11)).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::ReferralCommissionTooHigh {
+            commission: Decimal::percent(11),
+            max_commission: Decimal::percent(10),
+        }
+    );
+    update_max(&mut app, Decimal::percent(2)).unwrap();
+
+    let err = swap_operations(&mut app, None, commission).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::ReferralAddressMissing {}
+    );
+
+    // Quotes are net of the commission and match the execution
+    let quote: SimulateSwapOperationsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::SimulateSwapOperations {
+                offer_amount: offer.into(),
+                operations: operations.clone(),
+                referral_commission: Some(commission),
+            },
+        )
+        .unwrap();
+    let detailed: DetailedSimulationResponse = app
+        .wrap()
+        .query_wasm_smart(
+            pool_manager.clone(),
+            &QueryMsg::SimulateSwapOperationsDetailed {
+                offer_amount: offer.into(),
+                operations: operations.clone(),
+                referral_commission: Some(commission),
+            },
+        )
+        .unwrap();
+    assert_eq!(detailed.return_amount, quote.amount);
+    assert_eq!(
+        detailed.referral_amount,
+        detailed.hops[0].return_amount * commission
+    );
+
+    let res = swap_operations(&mut app, Some(frontend.to_string()), commission).unwrap();
+    assert_eq!(app.wrap().query_balance(&user, IBC_DENOM).unwrap().amount, quote.amount);
+    assert_eq!(
+        app.wrap().query_balance(&frontend, IBC_DENOM).unwrap().amount,
+        detailed.referral_amount
+    );
+    let wasm = res.events.iter().find(|event| event.ty == "wasm").unwrap();
+    assert!(wasm
+        .attributes
+        .contains(&attr("referral_address", frontend.to_string())));
+    assert!(wasm
+        .attributes
+        .contains(&attr("referral_amount", detailed.referral_amount)));
+
+    // Direct swaps pay the referrer out of the return as well
+    let referred = app.wrap().query_balance(&frontend, IBC_DENOM).unwrap().amount;
+    let user_ibc = app.wrap().query_balance(&user, IBC_DENOM).unwrap().amount;
+    app.execute_contract(
+        user.clone(),
+        pool_manager.clone(),
+        &ExecuteMsg::Swap {
+            offer_asset: native_asset(DENOM.to_string(), offer.into()),
+            ask_asset_info: asset_infos[1].clone(),
+            belief_price: None,
+            max_spread: None,
+            to: None,
+            pool_id: None,
+            callback: None,
+            referral_address: Some(frontend.to_string()),
+            referral_commission: Some(Decimal::percent(2)),
+        },
+        &[Coin {
+            denom: DENOM.to_string(),
+            amount: offer.into(),
+        }],
+    )
+    .unwrap();
+    let referral = app.wrap().query_balance(&frontend, IBC_DENOM).unwrap().amount - referred;
+    let received = app.wrap().query_balance(&user, IBC_DENOM).unwrap().amount - user_ibc;
+    assert_eq!(referral, (referral + received) * Decimal::percent(2));
+}
+
+#[test]
+fn test_typed_errors() {
+    let mut app = App::default();
+    let owner = Addr::unchecked("owner");
+
+    let helper = FactoryHelper::init(&mut app, &owner);
+    let pool_manager = helper.pool_manager.clone();
+    let unknown = "unknown".to_string();
+
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            pool_manager.clone(),
+            &ExecuteMsg::UpdateConfig {
+                pool_key: unknown.clone(),
+                params: ConcentratedPoolUpdateParams::StopChangingAmpGamma {},
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::PoolNotFound(unknown.clone())
+    );
+
+    let err = app
+        .wrap()
+        .query_wasm_smart::<PairInfo>(
+            pool_manager.clone(),
+            &QueryMsg::Pair {
+                pool_key: unknown.clone(),
+            },
+        )
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains(&ContractError::PoolNotFound(unknown.clone()).to_string()));
+
+    let err = app
+        .wrap()
+        .query_wasm_smart::<SimulateSwapOperationsResponse>(
+            pool_manager.clone(),
+            &QueryMsg::SimulateSwapOperations {
+                offer_amount: Uint128::one(),
+                operations: vec![],
+                referral_commission: None,
+            },
+        )
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains(&ContractError::MustProvideOperations {}.to_string()));
+
+    // The pool balance helpers report missing pools, foreign assets and underflows
+    let mut deps = mock_dependencies();
+    let asset_infos = [
+        native_asset_info(DENOM.to_string()),
+        native_asset_info(IBC_DENOM.to_string()),
+    ];
+    let key = pool_key(&asset_infos, 1);
+    let mut deps_mut = deps.as_mut();
+    assert_eq!(
+        increment_pair_balances(&mut deps_mut, key.clone(), vec![Uint128::one(); 2]).unwrap_err(),
+        ContractError::PoolNotFound(key.clone())
+    );
+    PAIR_BALANCES
+        .save(
+            deps_mut.storage,
+            key.clone(),
+            &asset_infos
+                .iter()
+                .map(|info| Asset {
+                    info: info.clone(),
+                    amount: Uint128::one(),
+                })
+                .collect(),
+        )
+        .unwrap();
+    let foreign_asset = native_asset("uusd".to_string(), Uint128::one());
+    assert_eq!(
+        find_asset_index(&mut deps_mut, key.clone(), foreign_asset).unwrap_err(),
+        ContractError::InvalidAsset("uusd".to_string())
+    );
+    assert!(matches!(
+        decrease_asset_balance(&mut deps_mut, key.clone(), 0, 2u128.into()).unwrap_err(),
+        ContractError::OverflowError(_)
+    ));
+    assert_eq!(
+        increment_pair_balances(&mut deps_mut, key, vec![Uint128::one()]).unwrap_err(),
+        ContractError::InvalidNumberOfAssets(2)
+    );
+}
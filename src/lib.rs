@@ -1,4 +1,5 @@
 pub mod contract;
+pub mod curves;
 pub mod error;
 #[cfg(test)]
 mod integration_test;
@@ -8,5 +9,8 @@ pub mod msg;
 
 pub mod state;
 pub mod utils;
+pub mod dca;
+pub mod flash_loan;
 pub mod handlers;
+pub mod limit_orders;
 pub mod query;
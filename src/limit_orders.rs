@@ -0,0 +1,233 @@
+use astroport::asset::{Asset, AssetInfo};
+use cosmwasm_std::{
+    attr, Addr, Decimal, Deps, DepsMut, Env, Fraction, MessageInfo, Response, StdResult, Storage,
+    Uint128,
+};
+use cw_utils::Expiration;
+
+use crate::error::ContractError;
+use crate::handlers::{pool_status, resolve_pool_key, send_asset, swap_internal};
+use crate::query::query_simulation;
+use crate::state::{limit_orders, LimitOrder, LIMIT_ORDER_COUNT};
+
+/// The share of every fill paid to the keeper executing it, in basis points.
+pub const LIMIT_ORDER_TIP_BPS: u16 = 10;
+/// Partial fills are searched down to this fraction of the unfilled amount.
+const FILL_SEARCH_STEPS: u128 = 1_000;
+
+/// Escrows `offer_asset` in a new limit order against the pool holding the offer and
+/// ask assets. The order fills once the pool returns at least `offer / limit_price`
+/// after the keeper tip.
+#[allow(clippy::too_many_arguments)]
+pub fn execute_place_limit_order(
+    deps: &mut DepsMut,
+    env: Env,
+    owner: Addr,
+    offer_asset: Asset,
+    ask_asset_info: AssetInfo,
+    limit_price: Decimal,
+    pool_id: Option<u64>,
+    expiry: Option<Expiration>,
+) -> Result<Response, ContractError> {
+    if offer_asset.amount.is_zero() {
+        return Err(ContractError::InvalidZeroAmount {});
+    }
+    if limit_price.is_zero() {
+        return Err(ContractError::InvalidLimitPrice {});
+    }
+    if let Some(expiry) = expiry {
+        if expiry.is_expired(&env.block) {
+            return Err(ContractError::DeadlinePassed(expiry));
+        }
+    }
+    if offer_asset.info.equal(&ask_asset_info) {
+        return Err(ContractError::DoublingAssets {});
+    }
+    let pool_key = resolve_pool_key(
+        deps.storage,
+        &[offer_asset.info.clone(), ask_asset_info.clone()],
+        pool_id,
+    )?;
+
+    let id = LIMIT_ORDER_COUNT.may_load(deps.storage)?.unwrap_or_default() + 1;
+    LIMIT_ORDER_COUNT.save(deps.storage, &id)?;
+    limit_orders().save(
+        deps.storage,
+        id,
+        &LimitOrder {
+            id,
+            owner: owner.clone(),
+            pool_key: pool_key.clone(),
+            offer_asset,
+            ask_asset_info,
+            limit_price,
+            filled_amount: Uint128::zero(),
+            received_amount: Uint128::zero(),
+            expiry,
+        },
+    )?;
+
+    Ok(Response::new().add_attributes(vec![
+        attr("action", "place_limit_order"),
+        attr("order_id", id.to_string()),
+        attr("owner", owner),
+        attr("pool_key", pool_key),
+    ]))
+}
+
+/// Cancels a limit order and refunds its unfilled offer asset. Only the order owner can
+/// execute this.
+pub fn execute_cancel_limit_order(
+    deps: &mut DepsMut,
+    info: MessageInfo,
+    order_id: u64,
+) -> Result<Response, ContractError> {
+    let order = load_order(deps.storage, order_id)?;
+    if order.owner != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    limit_orders().remove(deps.storage, order_id)?;
+
+    Ok(Response::new()
+        .add_message(send_asset(
+            &order.owner,
+            order.offer_asset.info,
+            order.offer_asset.amount,
+        )?)
+        .add_attributes(vec![
+            attr("action", "cancel_limit_order"),
+            attr("order_id", order_id.to_string()),
+            attr("refund_amount", order.offer_asset.amount),
+        ]))
+}
+
+/// Fills the given orders of a pool as far as the pool price allows. Expired orders are
+/// refunded to their owners. The keeper receives [`LIMIT_ORDER_TIP_BPS`] of every fill.
+pub fn execute_limit_orders(
+    deps: &mut DepsMut,
+    env: Env,
+    info: MessageInfo,
+    pool_key: String,
+    order_ids: Vec<u64>,
+) -> Result<Response, ContractError> {
+    if !pool_status(deps.storage, &pool_key)?.allows_swaps() {
+        return Err(ContractError::SwapsDisabled(pool_key));
+    }
+
+    let mut messages = vec![];
+    let (mut filled, mut expired) = (0u32, 0u32);
+    for order_id in order_ids {
+        let mut order = load_order(deps.storage, order_id)?;
+        if order.pool_key != pool_key {
+            return Err(ContractError::LimitOrderNotInPool {
+                order_id,
+                pool_key,
+            });
+        }
+
+        if order.expiry.is_some_and(|expiry| expiry.is_expired(&env.block)) {
+            limit_orders().remove(deps.storage, order_id)?;
+            messages.push(send_asset(
+                &order.owner,
+                order.offer_asset.info,
+                order.offer_asset.amount,
+            )?);
+            expired += 1;
+            continue;
+        }
+
+        let amount = fillable_amount(deps.as_ref(), &env, &order)?;
+        if amount.is_zero() {
+            continue;
+        }
+        let swap = swap_internal(
+            deps,
+            &env,
+            pool_key.clone(),
+            Asset {
+                info: order.offer_asset.info.clone(),
+                amount,
+            },
+            Some(order.limit_price),
+            Some(Decimal::zero()),
+        )?;
+        let tip = keeper_tip(swap.return_amount);
+        let owner_amount = swap.return_amount - tip;
+
+        messages.push(send_asset(
+            &order.owner,
+            order.ask_asset_info.clone(),
+            owner_amount,
+        )?);
+        if !tip.is_zero() {
+            messages.push(send_asset(&info.sender, order.ask_asset_info.clone(), tip)?);
+        }
+
+        order.offer_asset.amount -= amount;
+        order.filled_amount += amount;
+        order.received_amount += owner_amount;
+        if order.offer_asset.amount.is_zero() {
+            limit_orders().remove(deps.storage, order_id)?;
+        } else {
+            limit_orders().save(deps.storage, order_id, &order)?;
+        }
+        filled += 1;
+    }
+
+    if filled == 0 && expired == 0 {
+        return Err(ContractError::NoFillableOrders {});
+    }
+
+    Ok(Response::new().add_messages(messages).add_attributes(vec![
+        attr("action", "execute_limit_orders"),
+        attr("pool_key", pool_key),
+        attr("filled", filled.to_string()),
+        attr("expired", expired.to_string()),
+    ]))
+}
+
+fn load_order(storage: &dyn Storage, order_id: u64) -> Result<LimitOrder, ContractError> {
+    limit_orders()
+        .may_load(storage, order_id)?
+        .ok_or(ContractError::LimitOrderNotFound(order_id))
+}
+
+fn keeper_tip(amount: Uint128) -> Uint128 {
+    amount.multiply_ratio(LIMIT_ORDER_TIP_BPS, 10_000u16)
+}
+
+/// Returns the largest part of the unfilled amount the pool can fill at the limit price.
+/// The average price gets worse as the swap grows, so a partial fill is found by
+/// bisection down to 1/[`FILL_SEARCH_STEPS`] of the unfilled amount.
+fn fillable_amount(deps: Deps, env: &Env, order: &LimitOrder) -> StdResult<Uint128> {
+    let Some(price_inv) = order.limit_price.inv() else {
+        return Ok(Uint128::zero());
+    };
+    let fills = |amount: Uint128| {
+        let offer_asset = Asset {
+            info: order.offer_asset.info.clone(),
+            amount,
+        };
+        query_simulation(deps, env.clone(), offer_asset, order.pool_key.clone())
+            .map(|sim| sim.return_amount - keeper_tip(sim.return_amount) >= amount * price_inv)
+            .unwrap_or(false)
+    };
+
+    let remaining = order.offer_asset.amount;
+    if fills(remaining) {
+        return Ok(remaining);
+    }
+
+    let step = std::cmp::max(remaining.u128() / FILL_SEARCH_STEPS, 1);
+    let (mut low, mut high) = (0u128, remaining.u128());
+    while high - low > step {
+        let mid = low + (high - low) / 2;
+        if fills(mid.into()) {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+
+    Ok(low.into())
+}
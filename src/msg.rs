@@ -1,13 +1,44 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use astroport::{asset::{Asset, AssetInfo, PairInfo}, pair::{ConfigResponse, PoolResponse}, router::SimulateSwapOperationsResponse};
+use astroport::{asset::{Asset, AssetInfo, PairInfo}, factory::PairType, pair::{ConfigResponse, PoolResponse, ReverseSimulationResponse, SimulationResponse}, pair_concentrated::ConcentratedPoolUpdateParams, router::SimulateSwapOperationsResponse, token::InstantiateMarketingInfo};
 use cosmwasm_std::{Binary, Decimal, Decimal256, Uint128};
 
 use cw20::Cw20ReceiveMsg;
+use cw_utils::Expiration;
+
+use crate::state::{DcaSchedule, LimitOrder, ManagerConfig, PoolStatus};
 
 
 
 pub const MAX_SWAP_OPERATIONS: usize = 50;
 
+/// This structure holds the parameters used for creating the pool manager.
+#[cw_serde]
+pub struct InstantiateMsg {
+    /// The manager owner
+    pub owner: String,
+    /// The default cw20 code ID used for LP tokens
+    pub token_code_id: u64,
+    /// Native denoms which are allowed in pools
+    pub allowed_denoms: Vec<String>,
+    /// The address that receives flash loan fees. They stay in the lending pool if unset
+    pub fee_address: Option<String>,
+    /// The address allowed to change pool statuses alongside the owner
+    pub guardian: Option<String>,
+    /// The fee charged on flash loans, in basis points of the lent amount
+    #[serde(default)]
+    pub flash_loan_fee_bps: u16,
+    /// The highest share of a swap return a referrer can be paid
+    #[serde(default)]
+    pub max_referral_commission: Decimal,
+}
+
+/// This structure describes a migration message.
+#[cw_serde]
+pub struct MigrateMsg {
+    /// The manager configuration stored when migrating from 0.1.0, which had none
+    pub config: Option<ManagerConfig>,
+}
+
 /// This structure holds the parameters used for creating a contract.
 #[cw_serde]
 
@@ -20,7 +51,9 @@ pub struct SwapOperation {
     pub offer_asset_info: AssetInfo,
         /// Information about the asset we swap to
     pub ask_asset_info: AssetInfo,
-    
+    /// The pool to swap in. The first pool created for the pair is used if omitted
+    #[serde(default)]
+    pub pool_id: Option<u64>,
 }
 
 
@@ -44,12 +77,120 @@ pub enum ExecuteMsg {
     ExecuteSwapOperations {
         operations: Vec<SwapOperation>,
         minimum_receive: Option<Uint128>,
+        /// The minimum amount to receive from each hop, one entry per operation
+        #[serde(default)]
+        minimum_receive_per_hop: Option<Vec<Uint128>>,
         to: Option<String>,
         max_spread: Option<Decimal>,
+        /// The expected price of the offer asset in the ask asset. For single-hop swaps it
+        /// is checked in the pool, for routes against the end-to-end return
+        belief_price: Option<Decimal>,
+        /// The block time or height after which the swap fails
+        #[serde(default)]
+        deadline: Option<Expiration>,
+        /// A contract to send the return to and call with `msg` in the same transaction
+        #[serde(default)]
+        callback: Option<SwapCallback>,
+        /// The address paid `referral_commission` of the swap return
+        #[serde(default)]
+        referral_address: Option<String>,
+        /// The share of the swap return paid to the referrer, up to the manager maximum
+        #[serde(default)]
+        referral_commission: Option<Decimal>,
+    },
+
+    /// Swap performs a swap in the pool holding the offer and ask assets, like an astroport pair
+    Swap {
+        offer_asset: Asset,
+        ask_asset_info: AssetInfo,
+        belief_price: Option<Decimal>,
+        max_spread: Option<Decimal>,
+        to: Option<String>,
+        /// The pool to swap in. The first pool created for the pair is used if omitted
+        #[serde(default)]
+        pool_id: Option<u64>,
+        /// A contract to send the return to and call with `msg` in the same transaction
+        #[serde(default)]
+        callback: Option<SwapCallback>,
+        /// The address paid `referral_commission` of the swap return
+        #[serde(default)]
+        referral_address: Option<String>,
+        /// The share of the swap return paid to the referrer, up to the manager maximum
+        #[serde(default)]
+        referral_commission: Option<Decimal>,
+    },
+
+    /// Swaps the sent native tokens along the best route found by [`QueryMsg::FindRoutes`]
+    SwapBestRoute {
+        /// The asset to swap to
+        ask_asset_info: AssetInfo,
+        /// The maximum number of hops to search, 3 if omitted and at most 4
+        max_hops: Option<u32>,
+        minimum_receive: Option<Uint128>,
+        to: Option<String>,
+        max_spread: Option<Decimal>,
+    },
+
+    /// Splits the sent native tokens across several routes to the same ask asset
+    ExecuteSplitSwap {
+        /// The routes and the weight of the input each of them gets
+        routes: Vec<SplitRoute>,
+        /// The minimum amount to receive from all routes together
+        minimum_receive: Option<Uint128>,
+        to: Option<String>,
+        max_spread: Option<Decimal>,
+    },
+
+    /// Swaps the sent native tokens for exactly `ask_amount` and refunds the unused input
+    SwapExactOut {
+        operations: Vec<SwapOperation>,
+        /// The exact amount to deliver to the recipient
+        ask_amount: Uint128,
+        /// The maximum amount of the sent tokens to spend. All sent tokens may be spent if omitted
+        max_offer: Option<Uint128>,
+        to: Option<String>,
+        max_spread: Option<Decimal>,
+    },
+
+    /// Escrows the sent native tokens in a limit order filled by keepers
+    PlaceLimitOrder {
+        ask_asset_info: AssetInfo,
+        /// The highest price to pay for the ask asset, in offer asset units
+        limit_price: Decimal,
+        /// The pool to fill in. The first pool created for the pair is used if omitted
+        pool_id: Option<u64>,
+        /// The block time or height after which the order is refunded
+        expiry: Option<Expiration>,
+    },
+    /// Escrows the sent native tokens and swaps them along a route in equal tranches
+    CreateDca {
+        operations: Vec<SwapOperation>,
+        /// The number of equal swaps to split the sent tokens into
+        tranches: u32,
+        /// The number of seconds between two swaps
+        interval: u64,
+        /// The highest price to pay for the ask asset, in offer asset units
+        max_price: Decimal,
+        max_spread: Option<Decimal>,
+        /// The share of every swap output paid to the keeper executing it, in basis points
+        keeper_tip_bps: u16,
+    },
+    /// Swaps the next due tranche of a DCA schedule. Anyone can execute this
+    ExecuteDcaTranche { schedule_id: u64 },
+    /// Cancels a DCA schedule and withdraws the tokens not swapped yet
+    CancelDca {
+        schedule_id: u64,
+        /// The recipient of the withdrawn tokens, the owner if omitted
+        to: Option<String>,
+    },
+    /// Cancels a limit order and refunds its unfilled part
+    CancelLimitOrder { order_id: u64 },
+    /// Fills the given limit orders of a pool as far as the pool price allows
+    ExecuteLimitOrders {
+        pool_key: String,
+        order_ids: Vec<u64>,
     },
 
-    /// Internal use
-    /// ExecuteSwapOperation executes a single swap operation
    
     ProvideLiquidity {
         /// The assets available in the pool
@@ -60,15 +201,25 @@ pub enum ExecuteMsg {
         auto_stake: Option<bool>,
         /// The receiver of LP tokens
         receiver: Option<String>,
+        /// The pool to provide to. The first pool created for the pair is used if omitted
+        pool_id: Option<u64>,
+        /// The block time or height after which the provision fails
+        #[serde(default)]
+        deadline: Option<Expiration>,
     },
     
     CreatePair {
         /// Information about assets in the pool
         asset_infos: Vec<AssetInfo>,
-        /// The token contract code ID used for the tokens in the pool
-        token_code_id: u64,
-        
-        /// Optional binary serialised parameters for custom pool types
+        /// The pool type. Concentrated pools are created if omitted
+        pair_type: Option<PairType>,
+        /// The token contract code ID used for the LP token. Falls back to the
+        /// globally configured code ID if omitted. Only the manager owner can set another one
+        token_code_id: Option<u64>,
+        /// Optional LP token name, symbol and marketing info
+        lp_token: Option<LpTokenInfo>,
+        /// Binary serialised pool parameters: [`XykPoolParams`], [`StablePoolParams`]
+        /// or [`ConcentratedPoolParams`](astroport::pair_concentrated::ConcentratedPoolParams)
         init_params: Option<Binary>,
     },
 
@@ -81,7 +232,96 @@ pub enum ExecuteMsg {
         modification_type: PositionModification,
         /// Optional slippage tolerance
         slippage_tolerance: Option<Decimal>,
+        /// The pool holding the position. The first pool created for the pair is used if omitted
+        pool_id: Option<u64>,
+        /// The block time or height after which the modification fails
+        #[serde(default)]
+        deadline: Option<Expiration>,
     },
+
+    /// Changes the status of a pool. Only the manager owner or the guardian can execute this.
+    SetPoolStatus {
+        /// The key of the pool to update
+        pool_key: String,
+        /// The new pool status
+        status: PoolStatus,
+    },
+
+    /// Updates the parameters of a pool. Only the pool owner, or the manager owner for pools
+    /// without one, can execute this.
+    UpdateConfig {
+        /// The key of the pool to update
+        pool_key: String,
+        /// The new pool parameters
+        params: ConcentratedPoolUpdateParams,
+    },
+
+    /// Updates the global manager configuration. Only the owner can execute this.
+    UpdateManagerConfig {
+        /// The new manager owner
+        #[serde(default)]
+        owner: Option<String>,
+        /// The new default cw20 code ID used for LP tokens
+        #[serde(default)]
+        token_code_id: Option<u64>,
+        /// The new native denoms which are allowed in pools
+        #[serde(default)]
+        allowed_denoms: Option<Vec<String>>,
+        /// The new address that receives flash loan fees
+        #[serde(default)]
+        fee_address: Option<String>,
+        /// The new address allowed to change pool statuses alongside the owner
+        #[serde(default)]
+        guardian: Option<String>,
+        /// The new flash loan fee, in basis points of the lent amount
+        flash_loan_fee_bps: Option<u16>,
+        /// The new highest referral commission on swaps
+        #[serde(default)]
+        max_referral_commission: Option<Decimal>,
+    },
+
+    /// Lends assets out of a pool's reserves to `callback.contract` and calls it. The
+    /// contract must send back every asset plus the flash loan fee before the call ends
+    FlashLoan {
+        /// The key of the pool to borrow from
+        pool_key: String,
+        assets: Vec<Asset>,
+        callback: FlashLoanCallback,
+    },
+
+    /// Internal use. Checks that the flash loan in progress was repaid
+    CompleteFlashLoan {},
+}
+
+/// This structure holds the parameters of a constant product pool.
+#[cw_serde]
+pub struct XykPoolParams {
+    /// The swap fee in basis points
+    pub fee_bps: u16,
+    /// Whether asset balances are tracked over blocks or not
+    pub track_asset_balances: Option<bool>,
+}
+
+/// This structure holds the parameters of a stableswap pool.
+#[cw_serde]
+pub struct StablePoolParams {
+    /// The amplification coefficient
+    pub amp: u64,
+    /// The swap fee in basis points
+    pub fee_bps: u16,
+    /// Whether asset balances are tracked over blocks or not
+    pub track_asset_balances: Option<bool>,
+}
+
+/// This structure holds the optional LP token metadata used in [`ExecuteMsg::CreatePair`].
+#[cw_serde]
+pub struct LpTokenInfo {
+    /// The LP token name. Derived from the pool assets if omitted
+    pub name: Option<String>,
+    /// The LP token symbol. Defaults to `pcLP`
+    pub symbol: Option<String>,
+    /// The LP token marketing info (project, description, logo)
+    pub marketing: Option<InstantiateMarketingInfo>,
 }
 
 #[cw_serde]
@@ -106,6 +346,29 @@ pub enum QueryMsg {
         offer_amount: Uint128,
         /// The swap operations to perform, each swap involving a specific pool
         operations: Vec<SwapOperation>,
+        /// The share of the return paid to a referrer. The simulated return is net of it
+        #[serde(default)]
+        referral_commission: Option<Decimal>,
+    },
+    /// Simulates multi-hop swap operations and returns the details of every hop
+    #[returns(DetailedSimulationResponse)]
+    SimulateSwapOperationsDetailed {
+        /// The amount of tokens to swap
+        offer_amount: Uint128,
+        operations: Vec<SwapOperation>,
+        /// The share of the return paid to a referrer. The simulated return is net of it
+        #[serde(default)]
+        referral_commission: Option<Decimal>,
+    },
+    /// Simulates a swap in reverse, returning the offer needed to receive `ask_asset`
+    #[returns(ReverseSimulationResponse)]
+    ReverseSimulation { pool_key: String, ask_asset: Asset },
+    /// Simulates multi-hop swap operations in reverse from the amount to receive
+    #[returns(ReverseSwapOperationsResponse)]
+    SimulateReverseSwapOperations {
+        /// The amount of tokens to receive from the last operation
+        ask_amount: Uint128,
+        operations: Vec<SwapOperation>,
     },
     #[returns(ConfigResponse)]
     Config {pool_key:String},
@@ -118,23 +381,369 @@ pub enum QueryMsg {
     /// Query LP token virtual price
     #[returns(Decimal256)]
     LpPrice {pool_key:String},
-    
+    /// Returns the global pool manager configuration
+    #[returns(ManagerConfig)]
+    ManagerConfig {},
+    /// The `*ByAssets` queries address the pool holding the given assets with the given id,
+    /// or the first pool created for the pair if no id is set.
+    /// Returns pool parameters
+    #[returns(ConfigResponse)]
+    ConfigByAssets {
+        asset_infos: Vec<AssetInfo>,
+        pool_id: Option<u64>,
+    },
+    /// Returns the pool balances and total share
+    #[returns(PoolResponse)]
+    PoolByAssets {
+        asset_infos: Vec<AssetInfo>,
+        pool_id: Option<u64>,
+    },
+    /// Returns information about the pool
+    #[returns(PairInfo)]
+    PairByAssets {
+        asset_infos: Vec<AssetInfo>,
+        pool_id: Option<u64>,
+    },
+    /// Returns all pools in key order, paginated
+    #[returns(PoolsResponse)]
+    Pools {
+        /// The pool key to start reading after
+        start_after: Option<String>,
+        /// The maximum number of pools to return
+        limit: Option<u32>,
+    },
+    /// Returns the pools containing the given asset in key order, paginated
+    #[returns(PoolsResponse)]
+    PoolsByAsset {
+        asset_info: AssetInfo,
+        /// The pool key to start reading after
+        start_after: Option<String>,
+        /// The maximum number of pools to return
+        limit: Option<u32>,
+    },
+    /// Returns the status of a pool
+    #[returns(PoolStatus)]
+    PoolStatus { pool_key: String },
+    /// Simulates a swap in every pool of the pair so the results can be compared
+    #[returns(Vec<PoolSimulation>)]
+    SimulateSwapPools {
+        offer_asset: Asset,
+        ask_asset_info: AssetInfo,
+    },
+    /// Returns the best routes between two assets through the managed pools, ranked by output
+    #[returns(RoutesResponse)]
+    FindRoutes {
+        offer_asset_info: AssetInfo,
+        ask_asset_info: AssetInfo,
+        offer_amount: Uint128,
+        /// The maximum number of hops to search, 3 if omitted and at most 4
+        max_hops: Option<u32>,
+    },
+    /// Suggests how to split a swap across routes that share no pool
+    #[returns(SplitResponse)]
+    SuggestSplit {
+        offer_asset_info: AssetInfo,
+        ask_asset_info: AssetInfo,
+        offer_amount: Uint128,
+        /// The maximum number of hops to search, 3 if omitted and at most 4
+        max_hops: Option<u32>,
+    },
+    /// Returns a limit order by id
+    #[returns(LimitOrder)]
+    LimitOrder { order_id: u64 },
+    /// Returns the limit orders of an owner, paginated
+    #[returns(LimitOrdersResponse)]
+    LimitOrdersByOwner {
+        owner: String,
+        /// The order id to start reading after
+        start_after: Option<u64>,
+        /// The maximum number of orders to return
+        limit: Option<u32>,
+    },
+    /// Returns the limit orders resting on a pool, paginated
+    #[returns(LimitOrdersResponse)]
+    LimitOrdersByPool {
+        pool_key: String,
+        /// The order id to start reading after
+        start_after: Option<u64>,
+        /// The maximum number of orders to return
+        limit: Option<u32>,
+    },
+    /// Returns a pending DCA schedule by id
+    #[returns(DcaSchedule)]
+    DcaSchedule { schedule_id: u64 },
+    /// Returns the pending DCA schedules of an owner, paginated
+    #[returns(DcaSchedulesResponse)]
+    DcaSchedulesByOwner {
+        owner: String,
+        /// The schedule id to start reading after
+        start_after: Option<u64>,
+        /// The maximum number of schedules to return
+        limit: Option<u32>,
+    },
+    /// Returns all pending DCA schedules, paginated
+    #[returns(DcaSchedulesResponse)]
+    DcaSchedules {
+        /// The schedule id to start reading after
+        start_after: Option<u64>,
+        /// The maximum number of schedules to return
+        limit: Option<u32>,
+    },
+}
+
+/// A contract called with the return of a swap. The return is sent to `contract` first,
+/// then `contract` is executed with a [`SwapCallbackExecuteMsg`] carrying `msg`.
+#[cw_serde]
+pub struct SwapCallback {
+    pub contract: String,
+    pub msg: Binary,
+}
+
+/// The payload a callback contract receives after a swap.
+#[cw_serde]
+pub struct SwapCallbackMsg {
+    /// The address that executed the swap
+    pub sender: String,
+    /// The swap return, already sent to the callback contract
+    pub asset: Asset,
+    pub msg: Binary,
+}
+
+/// The execute message callback contracts must accept.
+#[cw_serde]
+pub enum SwapCallbackExecuteMsg {
+    SwapCallback(SwapCallbackMsg),
+}
+
+/// The borrower of a flash loan, called with `msg` once the assets are sent to it.
+#[cw_serde]
+pub struct FlashLoanCallback {
+    pub contract: String,
+    pub msg: Binary,
+}
+
+/// The payload a flash loan borrower receives with the lent assets.
+#[cw_serde]
+pub struct FlashLoanCallbackMsg {
+    /// The address that requested the loan
+    pub sender: String,
+    /// The lent assets, already sent to the borrower
+    pub assets: Vec<Asset>,
+    /// The fee owed on every lent asset on top of the principal
+    pub fees: Vec<Asset>,
+    pub msg: Binary,
+}
+
+/// The execute message flash loan borrowers must accept.
+#[cw_serde]
+pub enum FlashLoanCallbackExecuteMsg {
+    FlashLoanCallback(FlashLoanCallbackMsg),
+}
+
+/// A list of DCA schedules.
+#[cw_serde]
+pub struct DcaSchedulesResponse {
+    pub schedules: Vec<DcaSchedule>,
+}
+
+/// A list of limit orders.
+#[cw_serde]
+pub struct LimitOrdersResponse {
+    pub orders: Vec<LimitOrder>,
+}
+
+/// A swap route and the share of the input it gets in a split swap.
+#[cw_serde]
+pub struct SplitRoute {
+    pub operations: Vec<SwapOperation>,
+    /// The weight of the route relative to the weights of the other routes
+    pub weight: u32,
+}
+
+/// A suggested split and its simulated output.
+#[cw_serde]
+pub struct SplitResponse {
+    pub routes: Vec<SplitRoute>,
+    pub return_amount: Uint128,
+}
+
+/// The simulation of a single hop of a route.
+#[cw_serde]
+pub struct HopSimulation {
+    pub pool_key: String,
+    pub offer_asset_info: AssetInfo,
+    pub ask_asset_info: AssetInfo,
+    pub offer_amount: Uint128,
+    pub return_amount: Uint128,
+    pub spread_amount: Uint128,
+    pub commission_amount: Uint128,
+    /// The price of the offer asset in the ask asset before the swap, without fees
+    pub mid_price_before: Decimal256,
+    /// The price of the offer asset in the ask asset after the swap, without fees
+    pub mid_price_after: Decimal256,
+    /// How far the execution price before fees is below the reference price of the pool
+    pub price_impact: Decimal256,
+}
+
+/// The output of a detailed route simulation.
+#[cw_serde]
+pub struct DetailedSimulationResponse {
+    /// The route return, net of the referral commission
+    pub return_amount: Uint128,
+    /// The share of the last hop return paid to a referrer
+    pub referral_amount: Uint128,
+    pub hops: Vec<HopSimulation>,
+}
+
+/// The offer needed for a reverse simulated route, with every hop in operation order.
+#[cw_serde]
+pub struct ReverseSwapOperationsResponse {
+    /// The amount of tokens to offer to the first operation
+    pub offer_amount: Uint128,
+    pub operations: Vec<ReverseSimulationResponse>,
+}
+
+/// A swap route and its simulated output.
+#[cw_serde]
+pub struct Route {
+    pub operations: Vec<SwapOperation>,
+    pub return_amount: Uint128,
+}
+
+/// A custom struct for each query response that returns a list of routes.
+#[cw_serde]
+pub struct RoutesResponse {
+    pub routes: Vec<Route>,
+}
+
+/// The result of a swap simulation in a single pool.
+#[cw_serde]
+pub struct PoolSimulation {
+    pub pool_id: u64,
+    pub pool_key: String,
+    pub simulation: SimulationResponse,
+}
+
+/// A pool and the key it is stored under.
+#[cw_serde]
+pub struct PoolInfo {
+    pub pool_id: u64,
+    pub pool_key: String,
+    pub status: PoolStatus,
+    pub pair_info: PairInfo,
+}
+
+/// A custom struct for each query response that returns a list of pools.
+#[cw_serde]
+pub struct PoolsResponse {
+    pub pools: Vec<PoolInfo>,
 }
 #[cw_serde]
 pub enum Cw20HookMsg {
+    /// Swap a given amount of the received token, like an astroport pair
+    Swap {
+        ask_asset_info: AssetInfo,
+        belief_price: Option<Decimal>,
+        max_spread: Option<Decimal>,
+        to: Option<String>,
+        /// The pool to swap in. The first pool created for the pair is used if omitted
+        #[serde(default)]
+        pool_id: Option<u64>,
+        /// A contract to send the return to and call with `msg` in the same transaction
+        #[serde(default)]
+        callback: Option<SwapCallback>,
+        /// The address paid `referral_commission` of the swap return
+        #[serde(default)]
+        referral_address: Option<String>,
+        /// The share of the swap return paid to the referrer, up to the manager maximum
+        #[serde(default)]
+        referral_commission: Option<Decimal>,
+    },
     ExecuteSwapOperations {
         /// A vector of swap operations
         operations: Vec<SwapOperation>,
         /// The minimum amount of tokens to get from a swap
         minimum_receive: Option<Uint128>,
+        /// The minimum amount to receive from each hop, one entry per operation
+        #[serde(default)]
+        minimum_receive_per_hop: Option<Vec<Uint128>>,
         /// The recipient
         to: Option<String>,
         /// Max spread
         max_spread: Option<Decimal>,
+        /// The expected price of the offer asset in the ask asset
+        belief_price: Option<Decimal>,
+        /// The block time or height after which the swap fails
+        #[serde(default)]
+        deadline: Option<Expiration>,
+        /// A contract to send the return to and call with `msg` in the same transaction
+        #[serde(default)]
+        callback: Option<SwapCallback>,
+        /// The address paid `referral_commission` of the swap return
+        #[serde(default)]
+        referral_address: Option<String>,
+        /// The share of the swap return paid to the referrer, up to the manager maximum
+        #[serde(default)]
+        referral_commission: Option<Decimal>,
+    },
+    /// Swap the received tokens along the best route found by [`QueryMsg::FindRoutes`]
+    SwapBestRoute {
+        /// The asset to swap to
+        ask_asset_info: AssetInfo,
+        /// The maximum number of hops to search, 3 if omitted and at most 4
+        max_hops: Option<u32>,
+        minimum_receive: Option<Uint128>,
+        to: Option<String>,
+        max_spread: Option<Decimal>,
+    },
+    /// Split the received tokens across several routes to the same ask asset
+    ExecuteSplitSwap {
+        /// The routes and the weight of the input each of them gets
+        routes: Vec<SplitRoute>,
+        /// The minimum amount to receive from all routes together
+        minimum_receive: Option<Uint128>,
+        to: Option<String>,
+        max_spread: Option<Decimal>,
+    },
+    /// Swap the received tokens for exactly `ask_amount` and refund the unused input
+    SwapExactOut {
+        operations: Vec<SwapOperation>,
+        /// The exact amount to deliver to the recipient
+        ask_amount: Uint128,
+        /// The maximum amount of the received tokens to spend. All of them may be spent if omitted
+        max_offer: Option<Uint128>,
+        to: Option<String>,
+        max_spread: Option<Decimal>,
+    },
+    /// Escrow the received tokens in a limit order filled by keepers
+    PlaceLimitOrder {
+        ask_asset_info: AssetInfo,
+        /// The highest price to pay for the ask asset, in offer asset units
+        limit_price: Decimal,
+        /// The pool to fill in. The first pool created for the pair is used if omitted
+        pool_id: Option<u64>,
+        /// The block time or height after which the order is refunded
+        expiry: Option<Expiration>,
+    },
+    /// Escrow the received tokens and swap them along a route in equal tranches
+    CreateDca {
+        operations: Vec<SwapOperation>,
+        /// The number of equal swaps to split the received tokens into
+        tranches: u32,
+        /// The number of seconds between two swaps
+        interval: u64,
+        /// The highest price to pay for the ask asset, in offer asset units
+        max_price: Decimal,
+        max_spread: Option<Decimal>,
+        /// The share of every swap output paid to the keeper executing it, in basis points
+        keeper_tip_bps: u16,
     },
     /// Withdraw liquidity from the pool
     WithdrawLiquidity {
         #[serde(default)]
         assets: Vec<Asset>,
+        /// The block time or height after which the withdrawal fails
+        #[serde(default)]
+        deadline: Option<Expiration>,
     },
 }
@@ -3,45 +3,67 @@ use std::convert::TryFrom;
 use astroport::asset::Asset;
 use astroport::cosmwasm_ext::{DecimalToInteger, IntegerToDecimal};
 use astroport::pair::ConfigResponse;
-use astroport::pair::SimulationResponse;
+use astroport::pair::{ReverseSimulationResponse, SimulationResponse};
 use astroport::querier::query_supply;
 use astroport::router::{SimulateSwapOperationsResponse};
 use astroport_pcl_common::{calc_d, get_xcp};
-use astroport_pcl_common::utils::compute_swap;
 use crate::state::Precisions;
+use astroport_pcl_common::state::Config;
 use astroport_pcl_common::utils::before_swap_check;
-use cosmwasm_std::{to_json_binary, Addr, Decimal, Decimal256, Deps, Env, StdError, Uint128};
+use astroport::asset::AssetInfo;
+use cosmwasm_std::{
+    to_json_binary, Decimal, Decimal256, Deps, Env, Order, StdError, StdResult, Uint128,
+};
+use cw_storage_plus::Bound;
 use itertools::Itertools;
 use astroport::pair_concentrated::ConcentratedPoolConfig;
 use crate::error::ContractError;
-use crate::handlers::{generate_key_from_asset_info, LP_TOKEN_PRECISION};
-use crate::msg::SwapOperation;
-use crate::state::{ POOLS};
+use crate::handlers::{
+    pool_status, referral_amount, resolve_pool_key, split_amount, LP_TOKEN_PRECISION,
+};
+use crate::msg::{
+    DcaSchedulesResponse, DetailedSimulationResponse, HopSimulation, LimitOrdersResponse,
+    PoolInfo, PoolSimulation, PoolsResponse, ReverseSwapOperationsResponse, Route, SplitResponse,
+    SplitRoute, SwapOperation,
+};
+use crate::curves::{assert_concentrated, pool_curve, PoolCurve};
+use crate::state::{
+    asset_key, dca_schedules, limit_orders, load_pool, pair_key, pool_id_from_key, pool_key,
+    ASSET_POOLS, CONFIG, CURVE_PARAMS, PAIR_POOLS, POOLS,
+};
 use crate::utils::{query_pools_sim};
 pub fn simulate_swap_operations(
     deps: Deps,
     env:Env,
     offer_amount: Uint128,
     operations: Vec<SwapOperation>,
+    referral_commission: Option<Decimal>,
 ) -> Result<SimulateSwapOperationsResponse, ContractError> {
-    //assert_operations(deps.api, &operations)?;
-
+    if operations.is_empty() {
+        return Err(ContractError::MustProvideOperations {});
+    }
 
     let mut return_amount = offer_amount;
 
     for operation in operations.into_iter() {
         let (offer_asset_info,ask_asset_info)= (operation.offer_asset_info,operation.ask_asset_info);
-        let pool_key=generate_key_from_asset_info([offer_asset_info.clone(),ask_asset_info.clone()].as_ref());
+        let pool_key = resolve_pool_key(
+            deps.storage,
+            &[offer_asset_info.clone(), ask_asset_info.clone()],
+            operation.pool_id,
+        )?;
         let offer_asset=  Asset {
             info: offer_asset_info.clone(),
             amount:return_amount,
         };
-        let subresult=query_simulation(deps,env.clone(),offer_asset,pool_key).unwrap();
+        let subresult=query_simulation(deps,env.clone(),offer_asset,pool_key)?;
         return_amount=subresult.return_amount;
     }
 
+    let referral = referral_amount(return_amount, referral_commission.unwrap_or_default());
+
     Ok(SimulateSwapOperationsResponse {
-        amount: return_amount,
+        amount: return_amount - referral,
     })
 }
 
@@ -51,12 +73,30 @@ pub fn query_simulation(
     offer_asset: Asset,
     pool_key:String
 ) -> Result<SimulationResponse, ContractError> {
-    let config = POOLS.load(deps.storage,pool_key.clone())?;
+    let hop = query_hop_simulation(deps, &env, offer_asset, pool_key)?;
+
+    Ok(SimulationResponse {
+        return_amount: hop.return_amount,
+        spread_amount: hop.spread_amount,
+        commission_amount: hop.commission_amount,
+    })
+}
+
+/// Simulates a swap in the pool and reports the pool mid price before and after it.
+/// Concentrated pools measure the price impact against their oracle price, other pools
+/// against the mid price before the swap.
+pub fn query_hop_simulation(
+    deps: Deps,
+    env: &Env,
+    offer_asset: Asset,
+    pool_key: String,
+) -> Result<HopSimulation, ContractError> {
+    let config = load_pool(deps.storage, &pool_key)?;
     let precisions = Precisions::new(deps.storage)?;
     let offer_asset_prec = precisions.get_precision(&offer_asset.info)?;
     let offer_asset_dec = offer_asset.to_decimal_asset(offer_asset_prec)?;
 
-    let pools = query_pools_sim(deps, &config, &precisions)?;
+    let pools = query_pools_sim(deps, &pool_key, &precisions)?;
 
     let (offer_ind, _) = pools
         .iter()
@@ -67,38 +107,192 @@ pub fn query_simulation(
 
     before_swap_check(&pools, offer_asset_dec.amount)?;
 
+    let mut xs = pools.iter().map(|asset| asset.amount).collect_vec();
+
+    let curve = pool_curve(deps.storage, &pool_key, &config)?;
+    let swap_result = curve.compute_swap(&config, env, &xs, offer_ind, offer_asset_dec.amount)?;
+
+    let mid_price_before = mid_price(curve.as_ref(), &config, env, &xs, offer_ind)?;
+    xs[offer_ind] += offer_asset_dec.amount;
+    xs[ask_ind] -= swap_result.return_amount;
+    let mid_price_after = mid_price(curve.as_ref(), &config, env, &xs, offer_ind)?;
+
+    // Oracle prices are quoted in the first asset of the pool
+    let oracle_price = config.pool_state.price_state.oracle_price;
+    let reference_price = if assert_concentrated(&config).is_err() || oracle_price.is_zero() {
+        mid_price_before
+    } else if offer_ind == 0 {
+        Decimal256::one() / oracle_price
+    } else {
+        oracle_price
+    };
+    let execution_price =
+        (swap_result.return_amount + swap_result.commission_amount) / offer_asset_dec.amount;
+    let price_impact = if execution_price < reference_price {
+        Decimal256::one() - execution_price / reference_price
+    } else {
+        Decimal256::zero()
+    };
+
+    Ok(HopSimulation {
+        pool_key,
+        offer_asset_info: offer_asset.info,
+        ask_asset_info: pools[ask_ind].info.clone(),
+        offer_amount: offer_asset.amount,
+        return_amount: swap_result.return_amount.to_uint(ask_asset_prec)?,
+        spread_amount: swap_result.spread_amount.to_uint(ask_asset_prec)?,
+        commission_amount: swap_result.commission_amount.to_uint(ask_asset_prec)?,
+        mid_price_before,
+        mid_price_after,
+        price_impact,
+    })
+}
+
+/// Fraction of the offer pool used to probe the mid price of a curve.
+const MID_PRICE_PROBE: Decimal256 = Decimal256::raw(1_000_000_000_000);
+
+/// Returns the price of the offer asset in the ask asset for an infinitely small trade,
+/// before fees. It is approximated by swapping a tiny fraction of the offer pool.
+fn mid_price(
+    curve: &dyn PoolCurve,
+    config: &Config,
+    env: &Env,
+    xs: &[Decimal256],
+    offer_ind: usize,
+) -> Result<Decimal256, ContractError> {
+    let probe = xs[offer_ind] * MID_PRICE_PROBE;
+    if probe.is_zero() {
+        return Err(ContractError::InsufficientLiquidity {});
+    }
+    let outcome = curve.compute_swap(config, env, xs, offer_ind, probe)?;
+
+    Ok((outcome.return_amount + outcome.commission_amount) / probe)
+}
+
+/// Simulates multi-hop swap operations and returns the details of every hop.
+pub fn simulate_swap_operations_detailed(
+    deps: Deps,
+    env: Env,
+    offer_amount: Uint128,
+    operations: Vec<SwapOperation>,
+    referral_commission: Option<Decimal>,
+) -> Result<DetailedSimulationResponse, ContractError> {
+    if operations.is_empty() {
+        return Err(ContractError::MustProvideOperations {});
+    }
+
+    let mut hops = Vec::with_capacity(operations.len());
+    let mut return_amount = offer_amount;
+    for operation in operations {
+        let pool_key = resolve_pool_key(
+            deps.storage,
+            &[operation.offer_asset_info.clone(), operation.ask_asset_info],
+            operation.pool_id,
+        )?;
+        let offer_asset = Asset {
+            info: operation.offer_asset_info,
+            amount: return_amount,
+        };
+        let hop = query_hop_simulation(deps, &env, offer_asset, pool_key)?;
+        return_amount = hop.return_amount;
+        hops.push(hop);
+    }
+
+    let referral_amount = referral_amount(return_amount, referral_commission.unwrap_or_default());
+
+    Ok(DetailedSimulationResponse {
+        return_amount: return_amount - referral_amount,
+        referral_amount,
+        hops,
+    })
+}
+
+/// Returns the offer needed to receive `ask_asset` from the pool, fees included.
+pub fn query_reverse_simulation(
+    deps: Deps,
+    env: Env,
+    ask_asset: Asset,
+    pool_key: String,
+) -> Result<ReverseSimulationResponse, ContractError> {
+    let config = load_pool(deps.storage, &pool_key)?;
+    let precisions = Precisions::new(deps.storage)?;
+    let ask_asset_prec = precisions.get_precision(&ask_asset.info)?;
+    let ask_asset_dec = ask_asset.to_decimal_asset(ask_asset_prec)?;
+
+    let pools = query_pools_sim(deps, &pool_key, &precisions)?;
+
+    let (ask_ind, _) = pools
+        .iter()
+        .find_position(|asset| asset.info == ask_asset.info)
+        .ok_or_else(|| ContractError::InvalidAsset(ask_asset_dec.info.to_string()))?;
+    let offer_ind = 1 ^ ask_ind;
+    let offer_asset_prec = precisions.get_precision(&pools[offer_ind].info)?;
+
+    before_swap_check(&pools, ask_asset_dec.amount)?;
+
     let xs = pools.iter().map(|asset| asset.amount).collect_vec();
 
-   
-    let maker_fee_share = Decimal256::zero();
-    
-    // If this pool is configured to share fees
-    let share_fee_share = Decimal256::zero();
-   
-    let swap_result = compute_swap(
-        &xs,
-        offer_asset_dec.amount,
-        ask_ind,
-        &config,
-        &env,
-        maker_fee_share,
-        share_fee_share,
-    )?;
+    let curve = pool_curve(deps.storage, &pool_key, &config)?;
+    let swap_result = curve.compute_offer(&config, &env, &xs, ask_ind, ask_asset_dec.amount)?;
 
-    Ok(SimulationResponse {
-        return_amount: swap_result.dy.to_uint(ask_asset_prec)?,
-        spread_amount: swap_result.spread_fee.to_uint(ask_asset_prec)?,
-        commission_amount: swap_result.total_fee.to_uint(ask_asset_prec)?,
+    // Round the offer up, so that swapping it returns at least the requested amount
+    let mut offer_amount = swap_result.offer_amount.to_uint(offer_asset_prec)?;
+    if offer_amount.to_decimal256(offer_asset_prec)? < swap_result.offer_amount {
+        offer_amount += Uint128::one();
+    }
+
+    Ok(ReverseSimulationResponse {
+        offer_amount,
+        spread_amount: swap_result.spread_amount.to_uint(ask_asset_prec)?,
+        commission_amount: swap_result.commission_amount.to_uint(ask_asset_prec)?,
     })
 }
+
+/// Works back from `ask_amount` through the operations in reverse and returns the offer
+/// needed for the first hop, along with the reverse simulation of every hop in order.
+pub fn simulate_reverse_swap_operations(
+    deps: Deps,
+    env: Env,
+    ask_amount: Uint128,
+    operations: Vec<SwapOperation>,
+) -> Result<ReverseSwapOperationsResponse, ContractError> {
+    if operations.is_empty() {
+        return Err(ContractError::MustProvideOperations {});
+    }
+
+    let mut hops = Vec::with_capacity(operations.len());
+    let mut amount = ask_amount;
+    for operation in operations.into_iter().rev() {
+        let pool_key = resolve_pool_key(
+            deps.storage,
+            &[operation.offer_asset_info, operation.ask_asset_info.clone()],
+            operation.pool_id,
+        )?;
+        let ask_asset = Asset {
+            info: operation.ask_asset_info,
+            amount,
+        };
+        let hop = query_reverse_simulation(deps, env.clone(), ask_asset, pool_key)?;
+        amount = hop.offer_amount;
+        hops.push(hop);
+    }
+    hops.reverse();
+
+    Ok(ReverseSwapOperationsResponse {
+        offer_amount: amount,
+        operations: hops,
+    })
+}
+
 /// Compute the current LP token virtual price.
 pub fn query_lp_price(deps: Deps, env: Env, pool_key:String) -> Result<Decimal256,ContractError> {
-    let config = POOLS.load(deps.storage,pool_key.clone())?;
+    let config = load_pool(deps.storage, &pool_key)?;
+    assert_concentrated(&config)?;
     let total_lp = query_supply(&deps.querier, &config.pair_info.liquidity_token)?
         .to_decimal256(LP_TOKEN_PRECISION)?;
     if !total_lp.is_zero() {
         let precisions = Precisions::new(deps.storage)?;
-        let mut ixs = query_pools_sim(deps, &config, &precisions)
+        let mut ixs = query_pools_sim(deps, &pool_key, &precisions)
             .map_err(|err| ContractError::Std(StdError::generic_err(err.to_string())))?
             .into_iter()
             .map(|asset| asset.amount)
@@ -116,7 +310,20 @@ pub fn query_lp_price(deps: Deps, env: Env, pool_key:String) -> Result<Decimal25
 
 /// Returns the pair contract configuration.
 pub fn query_config(deps: Deps, env: Env,pool_key:String) -> Result<ConfigResponse,ContractError> {
-    let config = POOLS.load(deps.storage,pool_key)?;
+    let config = load_pool(deps.storage, &pool_key)?;
+    let manager_config = CONFIG.load(deps.storage)?;
+    let owner = config.owner.clone().unwrap_or(manager_config.owner);
+
+    // Xyk and stableswap pools only carry their fee and amp
+    if assert_concentrated(&config).is_err() {
+        return Ok(ConfigResponse {
+            block_time_last: 0,
+            params: Some(to_json_binary(&CURVE_PARAMS.load(deps.storage, pool_key)?)?),
+            owner,
+            factory_addr: config.factory_addr,
+        });
+    }
+
     let amp_gamma = config.pool_state.get_amp_gamma(&env);
     let dec256_price_scale = config.pool_state.price_state.price_scale;
     let price_scale = Decimal::from_atomics(
@@ -141,17 +348,18 @@ pub fn query_config(deps: Deps, env: Env,pool_key:String) -> Result<ConfigRespon
             track_asset_balances: config.track_asset_balances,
             fee_share: config.fee_share,
         })?),
-        owner: config.owner.unwrap_or(Addr::unchecked("Pina Colada")),
-        factory_addr: Addr::unchecked("Pina Colada"),
+        owner,
+        factory_addr: config.factory_addr,
     })
 }
 
 /// Compute the current pool D value.
 pub fn query_compute_d(deps: Deps, env: Env,pool_key:String) -> Result<Decimal256,ContractError> {
-    let config = POOLS.load(deps.storage,pool_key)?;
+    let config = load_pool(deps.storage, &pool_key)?;
+    assert_concentrated(&config)?;
     let precisions = Precisions::new(deps.storage)?;
 
-    let mut xs= query_pools_sim(deps, &config, &precisions)
+    let mut xs= query_pools_sim(deps, &pool_key, &precisions)
         .map_err(|e| StdError::generic_err(e.to_string()))?
         .into_iter()
         .map(|a| a.amount)
@@ -166,3 +374,435 @@ pub fn query_compute_d(deps: Deps, env: Env,pool_key:String) -> Result<Decimal25
     let amp_gamma = config.pool_state.get_amp_gamma(&env);
     Ok(calc_d(&xs, &amp_gamma)?)
 }
+
+/// Default number of pools returned by [`query_pools_paginated`].
+const DEFAULT_LIMIT: u32 = 10;
+/// Maximum number of pools returned by [`query_pools_paginated`].
+const MAX_LIMIT: u32 = 30;
+
+/// Returns pools in key order starting after `start_after`.
+pub fn query_pools_paginated(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<PoolsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let pools = POOLS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (pool_key, config) = item?;
+            Ok(PoolInfo {
+                pool_id: pool_id_from_key(&pool_key).unwrap_or_default(),
+                status: pool_status(deps.storage, &pool_key)?,
+                pool_key,
+                pair_info: config.pair_info,
+            })
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(PoolsResponse { pools })
+}
+
+/// Returns the pools that contain `asset_info` in key order.
+pub fn query_pools_by_asset(
+    deps: Deps,
+    asset_info: AssetInfo,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<PoolsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let pools = POOLS
+        .range(deps.storage, start, None, Order::Ascending)
+        .filter_map(|item| match item {
+            Ok((pool_key, config)) if config.pair_info.asset_infos.contains(&asset_info) => {
+                Some(pool_status(deps.storage, &pool_key).map(|status| PoolInfo {
+                    pool_id: pool_id_from_key(&pool_key).unwrap_or_default(),
+                    status,
+                    pool_key,
+                    pair_info: config.pair_info,
+                }))
+            }
+            Ok(_) => None,
+            Err(err) => Some(Err(err)),
+        })
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(PoolsResponse { pools })
+}
+
+/// Returns the limit orders of `owner` in id order.
+pub fn query_limit_orders_by_owner(
+    deps: Deps,
+    owner: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<LimitOrdersResponse> {
+    let owner = deps.api.addr_validate(&owner)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let orders = limit_orders()
+        .idx
+        .owner
+        .prefix(owner)
+        .range(deps.storage, start_after.map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, order)| order))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(LimitOrdersResponse { orders })
+}
+
+/// Returns the limit orders resting on `pool_key` in id order.
+pub fn query_limit_orders_by_pool(
+    deps: Deps,
+    pool_key: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<LimitOrdersResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let orders = limit_orders()
+        .idx
+        .pool
+        .prefix(pool_key)
+        .range(deps.storage, start_after.map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, order)| order))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(LimitOrdersResponse { orders })
+}
+
+/// Returns the pending DCA schedules of `owner` in id order.
+pub fn query_dca_schedules_by_owner(
+    deps: Deps,
+    owner: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<DcaSchedulesResponse> {
+    let owner = deps.api.addr_validate(&owner)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let schedules = dca_schedules()
+        .idx
+        .owner
+        .prefix(owner)
+        .range(deps.storage, start_after.map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, schedule)| schedule))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(DcaSchedulesResponse { schedules })
+}
+
+/// Returns all pending DCA schedules in id order, so keepers can find the due tranches.
+pub fn query_dca_schedules(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<DcaSchedulesResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let schedules = dca_schedules()
+        .range(deps.storage, start_after.map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, schedule)| schedule))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(DcaSchedulesResponse { schedules })
+}
+
+/// Simulates a swap in every pool of the pair. Pools that cannot fill the swap,
+/// e.g. empty ones, are left out.
+pub fn query_simulate_swap_pools(
+    deps: Deps,
+    env: Env,
+    offer_asset: Asset,
+    ask_asset_info: AssetInfo,
+) -> Result<Vec<PoolSimulation>, ContractError> {
+    let asset_infos = [offer_asset.info.clone(), ask_asset_info];
+    let pool_ids = PAIR_POOLS
+        .may_load(deps.storage, pair_key(&asset_infos))?
+        .unwrap_or_default();
+
+    Ok(pool_ids
+        .into_iter()
+        .filter_map(|pool_id| {
+            let pool_key = pool_key(&asset_infos, pool_id);
+            query_simulation(deps, env.clone(), offer_asset.clone(), pool_key.clone())
+                .ok()
+                .map(|simulation| PoolSimulation {
+                    pool_id,
+                    pool_key,
+                    simulation,
+                })
+        })
+        .collect())
+}
+
+/// Default number of hops searched by [`find_routes`].
+const DEFAULT_MAX_HOPS: u32 = 3;
+/// Maximum number of hops searched by [`find_routes`], whatever the caller asks for.
+const MAX_ROUTE_HOPS: usize = 4;
+/// Maximum number of pools [`find_routes`] looks at in one search. Pool creation is
+/// permissionless, so this bounds the gas a search can use however many pools exist.
+const MAX_ROUTE_EDGES: usize = 100;
+/// Maximum number of routes returned by [`find_routes`].
+const MAX_ROUTES: usize = 10;
+
+/// Finds the routes from `offer_asset_info` to `ask_asset_info` with at most `max_hops`
+/// swaps through pools that allow swaps. Only pools holding the asset reached so far are
+/// walked, up to [`MAX_ROUTE_EDGES`] of them. Routes that cannot be filled are left out and
+/// the best [`MAX_ROUTES`] are returned, ordered by output and then by number of hops.
+pub fn find_routes(
+    deps: Deps,
+    env: Env,
+    offer_asset_info: AssetInfo,
+    ask_asset_info: AssetInfo,
+    offer_amount: Uint128,
+    max_hops: Option<u32>,
+) -> Result<Vec<Route>, ContractError> {
+    if offer_asset_info.equal(&ask_asset_info) {
+        return Err(ContractError::DoublingAssets {});
+    }
+    let max_hops = (max_hops.unwrap_or(DEFAULT_MAX_HOPS) as usize).min(MAX_ROUTE_HOPS);
+
+    let mut routes = vec![];
+    let mut path = vec![];
+    let mut visited = vec![offer_asset_info.clone()];
+    let mut edges_left = MAX_ROUTE_EDGES;
+    walk_routes(
+        deps,
+        &env,
+        &ask_asset_info,
+        max_hops,
+        Asset {
+            info: offer_asset_info,
+            amount: offer_amount,
+        },
+        &mut path,
+        &mut visited,
+        &mut routes,
+        &mut edges_left,
+    )?;
+
+    routes.sort_by(|a, b| {
+        b.return_amount
+            .cmp(&a.return_amount)
+            .then(a.operations.len().cmp(&b.operations.len()))
+    });
+    routes.truncate(MAX_ROUTES);
+
+    Ok(routes)
+}
+
+/// Extends `path` depth first through the pools indexed by the current offer asset,
+/// never visiting an asset twice, and records the paths ending in the ask asset.
+/// Every pool looked at uses up one of `edges_left`.
+#[allow(clippy::too_many_arguments)]
+fn walk_routes(
+    deps: Deps,
+    env: &Env,
+    ask_asset_info: &AssetInfo,
+    max_hops: usize,
+    offer_asset: Asset,
+    path: &mut Vec<SwapOperation>,
+    visited: &mut Vec<AssetInfo>,
+    routes: &mut Vec<Route>,
+    edges_left: &mut usize,
+) -> StdResult<()> {
+    if path.len() >= max_hops {
+        return Ok(());
+    }
+
+    let pools = ASSET_POOLS
+        .prefix(asset_key(&offer_asset.info))
+        .range(deps.storage, None, None, Order::Ascending)
+        .take(*edges_left)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (pool_key, pool_id) in pools {
+        if *edges_left == 0 {
+            break;
+        }
+        *edges_left -= 1;
+
+        if !pool_status(deps.storage, &pool_key)?.allows_swaps() {
+            continue;
+        }
+        let config = POOLS.load(deps.storage, pool_key.clone())?;
+        let Some(next_info) = config
+            .pair_info
+            .asset_infos
+            .iter()
+            .find(|info| !info.equal(&offer_asset.info))
+        else {
+            continue;
+        };
+        if visited.contains(next_info) {
+            continue;
+        }
+        let Ok(simulation) =
+            query_simulation(deps, env.clone(), offer_asset.clone(), pool_key.clone())
+        else {
+            continue;
+        };
+        if simulation.return_amount.is_zero() {
+            continue;
+        }
+
+        path.push(SwapOperation {
+            offer_asset_info: offer_asset.info.clone(),
+            ask_asset_info: next_info.clone(),
+            pool_id: Some(pool_id),
+        });
+        if next_info.equal(ask_asset_info) {
+            routes.push(Route {
+                operations: path.clone(),
+                return_amount: simulation.return_amount,
+            });
+        } else {
+            visited.push(next_info.clone());
+            walk_routes(
+                deps,
+                env,
+                ask_asset_info,
+                max_hops,
+                Asset {
+                    info: next_info.clone(),
+                    amount: simulation.return_amount,
+                },
+                path,
+                visited,
+                routes,
+                edges_left,
+            )?;
+            visited.pop();
+        }
+        path.pop();
+    }
+
+    Ok(())
+}
+
+/// Number of equal steps the input is divided into by [`query_suggest_split`].
+const SPLIT_STEPS: u32 = 10;
+/// Maximum number of routes combined by [`query_suggest_split`].
+const MAX_SPLIT_ROUTES: usize = 4;
+
+/// Suggests a split of `offer_amount` across the best routes from [`find_routes`] that
+/// share no pool, since routes through the same pool would move each other's price.
+/// The input is handed out in [`SPLIT_STEPS`] steps, each to the route with the largest
+/// marginal return.
+pub fn query_suggest_split(
+    deps: Deps,
+    env: Env,
+    offer_asset_info: AssetInfo,
+    ask_asset_info: AssetInfo,
+    offer_amount: Uint128,
+    max_hops: Option<u32>,
+) -> Result<SplitResponse, ContractError> {
+    let mut candidates: Vec<Vec<SwapOperation>> = vec![];
+    let mut used_pools = vec![];
+    for route in find_routes(
+        deps,
+        env.clone(),
+        offer_asset_info.clone(),
+        ask_asset_info.clone(),
+        offer_amount,
+        max_hops,
+    )? {
+        let route_pools = route
+            .operations
+            .iter()
+            .map(|op| {
+                resolve_pool_key(
+                    deps.storage,
+                    &[op.offer_asset_info.clone(), op.ask_asset_info.clone()],
+                    op.pool_id,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if route_pools.iter().any(|pool| used_pools.contains(pool)) {
+            continue;
+        }
+        used_pools.extend(route_pools);
+        candidates.push(route.operations);
+        if candidates.len() == MAX_SPLIT_ROUTES {
+            break;
+        }
+    }
+    if candidates.is_empty() {
+        return Err(ContractError::NoRouteFound {
+            offer_asset: offer_asset_info.to_string(),
+            ask_asset: ask_asset_info.to_string(),
+        });
+    }
+
+    let mut weights = vec![0u32; candidates.len()];
+    let mut outputs = vec![Uint128::zero(); candidates.len()];
+    for _ in 0..SPLIT_STEPS {
+        // (route index, output with one more step, marginal return of that step)
+        let mut best: Option<(usize, Uint128, Uint128)> = None;
+        for (i, operations) in candidates.iter().enumerate() {
+            let amount = offer_amount.multiply_ratio(weights[i] + 1, SPLIT_STEPS);
+            let Ok(output) = simulate_route(deps, &env, operations, amount) else {
+                continue;
+            };
+            let gain = output.saturating_sub(outputs[i]);
+            if best.map_or(true, |(_, _, best_gain)| gain > best_gain) {
+                best = Some((i, output, gain));
+            }
+        }
+        let Some((i, output, _)) = best else {
+            break;
+        };
+        weights[i] += 1;
+        outputs[i] = output;
+    }
+
+    let routes = candidates
+        .into_iter()
+        .zip(weights)
+        .filter(|(_, weight)| *weight > 0)
+        .map(|(operations, weight)| SplitRoute { operations, weight })
+        .collect_vec();
+    let amounts = split_amount(
+        offer_amount,
+        &routes.iter().map(|route| route.weight).collect_vec(),
+    );
+    let mut return_amount = Uint128::zero();
+    for (route, amount) in routes.iter().zip(amounts) {
+        return_amount += simulate_route(deps, &env, &route.operations, amount)?;
+    }
+
+    Ok(SplitResponse {
+        routes,
+        return_amount,
+    })
+}
+
+/// Simulates swapping `amount` through every operation in turn.
+fn simulate_route(
+    deps: Deps,
+    env: &Env,
+    operations: &[SwapOperation],
+    amount: Uint128,
+) -> Result<Uint128, ContractError> {
+    let mut return_amount = amount;
+    for operation in operations {
+        let pool_key = resolve_pool_key(
+            deps.storage,
+            &[operation.offer_asset_info.clone(), operation.ask_asset_info.clone()],
+            operation.pool_id,
+        )?;
+        let offer_asset = Asset {
+            info: operation.offer_asset_info.clone(),
+            amount: return_amount,
+        };
+        return_amount = query_simulation(deps, env.clone(), offer_asset, pool_key)?.return_amount;
+    }
+
+    Ok(return_amount)
+}
@@ -1,16 +1,19 @@
 use astroport::asset::{Asset, AssetInfo};
-use cosmwasm_std::{CustomQuery, Order, StdResult, Storage, Uint128};
+use cosmwasm_std::{CustomQuery, Decimal, Order, StdResult, Storage, Timestamp, Uint128};
 use cw20::{Cw20QueryMsg, TokenInfoResponse};
-use cw_storage_plus::{Item, Map, SnapshotMap};
+use cw_utils::Expiration;
+use cw_storage_plus::{Index, IndexList, IndexedMap, Item, Map, MultiIndex, SnapshotMap};
 use itertools::Itertools;
 use cosmwasm_std::Addr;
 
 use astroport_pcl_common::{error::PclError, state::Config};
+
+use crate::error::ContractError;
+use crate::msg::SwapOperation;
 use cosmwasm_std::DepsMut;
 use cosmwasm_schema::cw_serde;
 
 /// Stores pool parameters and state.
-
 pub struct Precisions(Vec<(String, u8)>);
 
 impl<'a> Precisions {
@@ -64,7 +67,85 @@ impl<'a> Precisions {
     }
 }
 
-pub const QUEUED_MINT: Item<String> = Item::new("pool_key");
+/// This structure stores the global pool manager configuration.
+#[cw_serde]
+pub struct ManagerConfig {
+    /// The manager owner
+    pub owner: Addr,
+    /// The default cw20 code ID used for LP tokens
+    pub token_code_id: u64,
+    /// Native denoms which are allowed in pools
+    pub allowed_denoms: Vec<String>,
+    /// The address that receives flash loan fees. They stay in the lending pool if unset
+    pub fee_address: Option<Addr>,
+    /// The address allowed to change pool statuses alongside the owner
+    pub guardian: Option<Addr>,
+    /// The fee charged on flash loans, in basis points of the lent amount
+    #[serde(default)]
+    pub flash_loan_fee_bps: u16,
+    /// The highest share of a swap return a referrer can be paid
+    #[serde(default)]
+    pub max_referral_commission: Decimal,
+}
+
+pub const CONFIG: Item<ManagerConfig> = Item::new("config");
+
+/// The lifecycle status of a pool.
+#[cw_serde]
+#[derive(Default, Copy)]
+pub enum PoolStatus {
+    /// All operations are allowed
+    #[default]
+    Active,
+    /// Liquidity can be provided and withdrawn, but not swapped
+    SwapsPaused,
+    /// Liquidity can only be withdrawn
+    WithdrawOnly,
+    /// No operation is allowed
+    Frozen,
+}
+
+impl PoolStatus {
+    pub fn allows_swaps(&self) -> bool {
+        matches!(self, PoolStatus::Active)
+    }
+
+    pub fn allows_provide(&self) -> bool {
+        matches!(self, PoolStatus::Active | PoolStatus::SwapsPaused)
+    }
+
+    pub fn allows_withdraw(&self) -> bool {
+        !matches!(self, PoolStatus::Frozen)
+    }
+}
+
+/// This structure stores the parameters of xyk and stableswap pools.
+/// Concentrated pools keep their parameters in the pool [`Config`].
+#[cw_serde]
+pub struct CurveParams {
+    /// The swap fee in basis points
+    pub fee_bps: u16,
+    /// The amplification coefficient, only set for stableswap pools
+    pub amp: Option<u64>,
+}
+
+pub const CURVE_PARAMS: Map<String, CurveParams> = Map::new("curve_params");
+/// Pool keys waiting for their LP token, keyed by the instantiation reply id
+pub const QUEUED_MINTS: Map<u64, String> = Map::new("queued_mints");
+/// The single pending LP token instantiation of 0.1.0, moved to [`QUEUED_MINTS`] on migration
+pub const LEGACY_QUEUED_MINT: Item<String> = Item::new("pool_key");
+/// The last reply id assigned to an LP token instantiation
+pub const LAST_REPLY_ID: Item<u64> = Item::new("last_reply_id");
+/// The number of pools created so far. Pool ids start from 1
+pub const POOL_COUNT: Item<u64> = Item::new("pool_count");
+/// Ids of all pools holding an asset pair in creation order, keyed by [`pair_key`]
+pub const PAIR_POOLS: Map<String, Vec<u64>> = Map::new("pair_pools");
+/// Ids of the pools holding an asset, keyed by the [`asset_key`] of the asset and the pool key
+pub const ASSET_POOLS: Map<(String, String), u64> = Map::new("asset_pools");
+/// Pool statuses by pool key. Pools without an entry are active
+pub const POOL_STATUS: Map<String, PoolStatus> = Map::new("pool_status");
+/// Pool keys by LP token address
+pub const LP_TOKEN_POOLS: Map<&Addr, String> = Map::new("lp_token_pools");
 pub const POOLS: Map<String, Config> = Map::new("pools");
 pub const PAIR_BALANCES: Map<String, Vec<Asset>> = Map::new("pair_balances");
 /// Stores asset balances to query them later at any block height
@@ -74,62 +155,271 @@ pub const BALANCES: SnapshotMap<&AssetInfo, Uint128> = SnapshotMap::new(
     "balances_change",
     cw_storage_plus::Strategy::EveryBlock,
 );
-pub fn find_asset_index(deps: &mut DepsMut, key: String, asset: Asset) -> usize {
-    let balances = PAIR_BALANCES.load(deps.storage, key.clone()).unwrap();
+/// Loads the configuration of a pool.
+pub fn load_pool(storage: &dyn Storage, key: &str) -> Result<Config, ContractError> {
+    POOLS
+        .may_load(storage, key.to_string())?
+        .ok_or_else(|| ContractError::PoolNotFound(key.to_string()))
+}
 
-    balances
+/// Loads the reserves of a pool.
+pub fn load_pair_balances(storage: &dyn Storage, key: &str) -> Result<Vec<Asset>, ContractError> {
+    PAIR_BALANCES
+        .may_load(storage, key.to_string())?
+        .ok_or_else(|| ContractError::PoolNotFound(key.to_string()))
+}
+
+/// Returns the index of `asset` in the reserves of a pool.
+pub fn find_asset_index(
+    deps: &mut DepsMut,
+    key: String,
+    asset: Asset,
+) -> Result<usize, ContractError> {
+    load_pair_balances(deps.storage, &key)?
         .iter()
-        .enumerate()
-        .find(|&r| r.1.info == asset.info)
-        .unwrap()
-        .0
+        .position(|balance| balance.info == asset.info)
+        .ok_or_else(|| ContractError::InvalidAsset(asset.info.to_string()))
 }
 
-pub fn increment_asset_balance(deps: &mut DepsMut, key: String, index: usize, amount: Uint128) {
-    let mut balances = PAIR_BALANCES.load(deps.storage, key.clone()).unwrap();
+pub fn increment_asset_balance(
+    deps: &mut DepsMut,
+    key: String,
+    index: usize,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    update_pair_balances(deps, key, |balances| {
+        let balance = balances.get_mut(index).ok_or(ContractError::InvalidNumberOfAssets(2))?;
+        balance.amount = balance.amount.checked_add(amount)?;
+        Ok(())
+    })
+}
 
-    balances[index].amount += amount;
-    let _ = PAIR_BALANCES.save(deps.storage, key, &balances);
+pub fn decrease_asset_balance(
+    deps: &mut DepsMut,
+    key: String,
+    index: usize,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    update_pair_balances(deps, key, |balances| {
+        let balance = balances.get_mut(index).ok_or(ContractError::InvalidNumberOfAssets(2))?;
+        balance.amount = balance.amount.checked_sub(amount)?;
+        Ok(())
+    })
 }
-pub fn decrease_asset_balance(deps: &mut DepsMut, key: String, index: usize, amount: Uint128) {
-    let mut balances = PAIR_BALANCES.load(deps.storage, key.clone()).unwrap();
 
-    balances[index].amount -= amount;
-    let _ = PAIR_BALANCES.save(deps.storage, key, &balances);
+pub fn increment_pair_balances(
+    deps: &mut DepsMut,
+    key: String,
+    amounts: Vec<Uint128>,
+) -> Result<(), ContractError> {
+    update_pair_balances(deps, key, |balances| {
+        if amounts.len() != balances.len() {
+            return Err(ContractError::InvalidNumberOfAssets(balances.len()));
+        }
+        for (balance, amount) in balances.iter_mut().zip(amounts) {
+            balance.amount = balance.amount.checked_add(amount)?;
+        }
+        Ok(())
+    })
 }
-pub fn increment_pair_balances(deps: &mut DepsMut, key: String, amounts: Vec<Uint128>) {
-    let mut curr = PAIR_BALANCES.load(deps.storage, key.clone()).unwrap();
-    for (i, v) in amounts.into_iter().enumerate() {
-        curr[i].amount += v;
-    }
-    let _ = PAIR_BALANCES.save(deps.storage, key, &curr);
+
+pub fn decrease_pair_balances(
+    deps: &mut DepsMut,
+    key: String,
+    amounts: Vec<Uint128>,
+) -> Result<(), ContractError> {
+    update_pair_balances(deps, key, |balances| {
+        if amounts.len() != balances.len() {
+            return Err(ContractError::InvalidNumberOfAssets(balances.len()));
+        }
+        for (balance, amount) in balances.iter_mut().zip(amounts) {
+            balance.amount = balance.amount.checked_sub(amount)?;
+        }
+        Ok(())
+    })
 }
 
-pub fn decrease_pair_balances(deps: &mut DepsMut, key: String, amounts: Vec<Uint128>) {
-    let mut curr = PAIR_BALANCES.load(deps.storage, key.clone()).unwrap();
-    for (i, v) in amounts.into_iter().enumerate() {
-        println!("{} {} amounts", curr[i], v);
-        curr[i].amount -= v;
-    }
-    let _ = PAIR_BALANCES.save(deps.storage, key, &curr);
+fn update_pair_balances(
+    deps: &mut DepsMut,
+    key: String,
+    update: impl FnOnce(&mut Vec<Asset>) -> Result<(), ContractError>,
+) -> Result<(), ContractError> {
+    let mut balances = load_pair_balances(deps.storage, &key)?;
+    update(&mut balances)?;
+    PAIR_BALANCES.save(deps.storage, key, &balances)?;
+    Ok(())
 }
 
 #[cw_serde]
 pub struct Position {
     pub owner: Addr,
+    /// The key of the pool the position belongs to
+    pub pool_key: String,
+    /// The escrowed amount of every pool asset, kept apart from the pool reserves
     pub assets: Vec<Asset>,
-    pub total_shares: Uint128,
     pub last_modified_block: u64,
 }
 
 pub const POSITIONS: Map<String, Position> = Map::new("positions");
 
-pub fn pair_key(asset_infos: &[AssetInfo]) -> Vec<u8> {
+/// A resting limit order. The manager escrows the unfilled offer asset.
+#[cw_serde]
+pub struct LimitOrder {
+    pub id: u64,
+    pub owner: Addr,
+    /// The key of the pool the order is filled against
+    pub pool_key: String,
+    /// The offer asset left to fill
+    pub offer_asset: Asset,
+    pub ask_asset_info: AssetInfo,
+    /// The highest price paid for the ask asset, in units of the offer asset
+    pub limit_price: Decimal,
+    /// The amount of offer asset filled so far
+    pub filled_amount: Uint128,
+    /// The amount of ask asset sent to the owner so far
+    pub received_amount: Uint128,
+    /// The block time or height after which the order can no longer be filled
+    pub expiry: Option<Expiration>,
+}
+
+pub struct LimitOrderIndexes<'a> {
+    pub owner: MultiIndex<'a, Addr, LimitOrder, u64>,
+    pub pool: MultiIndex<'a, String, LimitOrder, u64>,
+}
+
+impl<'a> IndexList<LimitOrder> for LimitOrderIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<LimitOrder>> + '_> {
+        let v: Vec<&dyn Index<LimitOrder>> = vec![&self.owner, &self.pool];
+        Box::new(v.into_iter())
+    }
+}
+
+/// Limit orders by id, indexed by owner and by pool key
+pub fn limit_orders<'a>() -> IndexedMap<'a, u64, LimitOrder, LimitOrderIndexes<'a>> {
+    let indexes = LimitOrderIndexes {
+        owner: MultiIndex::new(
+            |_, order| order.owner.clone(),
+            "limit_orders",
+            "limit_orders__owner",
+        ),
+        pool: MultiIndex::new(
+            |_, order| order.pool_key.clone(),
+            "limit_orders",
+            "limit_orders__pool",
+        ),
+    };
+    IndexedMap::new("limit_orders", indexes)
+}
+
+/// The number of limit orders placed so far. Order ids start from 1
+pub const LIMIT_ORDER_COUNT: Item<u64> = Item::new("limit_order_count");
+
+/// A schedule of equal swaps along a route, executed by keepers. The manager escrows
+/// the offer asset of the tranches left.
+#[cw_serde]
+pub struct DcaSchedule {
+    pub id: u64,
+    pub owner: Addr,
+    pub operations: Vec<SwapOperation>,
+    /// The offer asset left to swap
+    pub offer_asset: Asset,
+    /// The amount swapped by every tranche but the last, which swaps the rest
+    pub tranche_amount: Uint128,
+    pub tranches_left: u32,
+    /// The number of seconds between two tranches
+    pub interval: u64,
+    /// The block time from which the next tranche can be executed
+    pub next_execution: Timestamp,
+    /// The highest price paid for the ask asset, in units of the offer asset
+    pub max_price: Decimal,
+    pub max_spread: Option<Decimal>,
+    /// The share of every tranche output paid to the keeper, in basis points
+    pub keeper_tip_bps: u16,
+    /// The amount of ask asset sent to the owner so far
+    pub received_amount: Uint128,
+}
+
+pub struct DcaScheduleIndexes<'a> {
+    pub owner: MultiIndex<'a, Addr, DcaSchedule, u64>,
+}
+
+impl<'a> IndexList<DcaSchedule> for DcaScheduleIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<DcaSchedule>> + '_> {
+        let v: Vec<&dyn Index<DcaSchedule>> = vec![&self.owner];
+        Box::new(v.into_iter())
+    }
+}
+
+/// Pending DCA schedules by id, indexed by owner
+pub fn dca_schedules<'a>() -> IndexedMap<'a, u64, DcaSchedule, DcaScheduleIndexes<'a>> {
+    let indexes = DcaScheduleIndexes {
+        owner: MultiIndex::new(
+            |_, schedule| schedule.owner.clone(),
+            "dca_schedules",
+            "dca_schedules__owner",
+        ),
+    };
+    IndexedMap::new("dca_schedules", indexes)
+}
+
+/// The number of DCA schedules created so far. Schedule ids start from 1
+pub const DCA_SCHEDULE_COUNT: Item<u64> = Item::new("dca_schedule_count");
+
+/// A flash loan in progress. Only exists between lending and the repayment check.
+#[cw_serde]
+pub struct FlashLoan {
+    /// The key of the pool the assets are lent from
+    pub pool_key: String,
+    /// The fee owed on every lent asset
+    pub fees: Vec<Asset>,
+    /// The manager balance of every lent asset required once the loan is repaid
+    pub min_balances: Vec<Asset>,
+}
+
+pub const FLASH_LOAN: Item<FlashLoan> = Item::new("flash_loan");
+
+/// Separates the parts of a pool key. Neither native denoms nor addresses may contain it.
+pub const POOL_KEY_SEPARATOR: char = '|';
+
+/// Builds the key of an asset pair. Every asset is tagged with its kind so a native
+/// denom can never clash with a cw20 address, and assets are sorted so the order does not matter.
+pub fn pair_key(asset_infos: &[AssetInfo]) -> String {
     asset_infos
         .iter()
-        .map(AssetInfo::as_bytes)
+        .map(asset_key)
         .sorted()
-        .flatten()
-        .copied()
-        .collect()
+        .join(&POOL_KEY_SEPARATOR.to_string())
+}
+
+/// Builds the key of a single asset, tagged with its kind.
+pub fn asset_key(asset_info: &AssetInfo) -> String {
+    match asset_info {
+        AssetInfo::NativeToken { denom } => format!("native:{denom}"),
+        AssetInfo::Token { contract_addr } => format!("cw20:{contract_addr}"),
+    }
+}
+
+/// Indexes the pool stored under `pool_key` by each of its assets in [`ASSET_POOLS`].
+pub fn index_pool_assets(
+    storage: &mut dyn Storage,
+    asset_infos: &[AssetInfo],
+    pool_key: &str,
+    pool_id: u64,
+) -> StdResult<()> {
+    for asset_info in asset_infos {
+        ASSET_POOLS.save(storage, (asset_key(asset_info), pool_key.to_string()), &pool_id)?;
+    }
+    Ok(())
+}
+
+/// Builds the key a pool is stored under from its assets and pool id.
+pub fn pool_key(asset_infos: &[AssetInfo], pool_id: u64) -> String {
+    format!("{}{POOL_KEY_SEPARATOR}{pool_id}", pair_key(asset_infos))
+}
+
+/// Extracts the pool id from a key built by [`pool_key`].
+pub fn pool_id_from_key(pool_key: &str) -> Option<u64> {
+    pool_key
+        .rsplit_once(POOL_KEY_SEPARATOR)
+        .and_then(|(_, pool_id)| pool_id.parse().ok())
 }
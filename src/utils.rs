@@ -1,21 +1,16 @@
 use cosmwasm_std::{Addr, CosmosMsg, StdResult, DepsMut, Deps};
-use astroport::asset::{Asset, AssetInfo, DecimalAsset};
+use astroport::asset::{Asset, DecimalAsset};
 use astroport_pcl_common::state::Config;
 use crate::error::ContractError;
-use crate::state::{PAIR_BALANCES, Precisions};
-use std::str;
-use itertools::Itertools;
+use crate::state::{load_pair_balances, Precisions, PAIR_BALANCES};
 
 pub(crate) fn query_pools(
     deps: &DepsMut,     
-    config: &Config,
+    pool_key: &str,
     precisions: &Precisions,
 ) -> Result<Vec<DecimalAsset>, ContractError> {
-    let key = generate_key_from_asset_info([config.pair_info.asset_infos[0].clone(),config.pair_info.asset_infos[1].clone()].as_ref());
-    println!("{}",key);
-    let pairs = PAIR_BALANCES.load(deps.storage,key).unwrap();
-    println!("{:?}",pairs);
-    pairs.into_iter()
+    load_pair_balances(deps.storage, pool_key)?
+        .into_iter()
         .map(|asset| {
             asset
                 .to_decimal_asset(precisions.get_precision(&asset.info)?)
@@ -26,14 +21,11 @@ pub(crate) fn query_pools(
 
 pub(crate) fn query_pools_sim(
     deps: Deps,     
-    config: &Config,
+    pool_key: &str,
     precisions: &Precisions,
 ) -> Result<Vec<DecimalAsset>, ContractError> {
-    let key = generate_key_from_asset_info([config.pair_info.asset_infos[0].clone(),config.pair_info.asset_infos[1].clone()].as_ref());
-    println!("{}",key);
-    let pairs = PAIR_BALANCES.load(deps.storage,key).unwrap();
-    println!("{:?}",pairs);
-    pairs.into_iter()
+    load_pair_balances(deps.storage, pool_key)?
+        .into_iter()
         .map(|asset| {
             asset
                 .to_decimal_asset(precisions.get_precision(&asset.info)?)
@@ -67,24 +59,8 @@ pub fn update_pool_balances(
     assets: &[Asset],
 ) -> StdResult<()> {
     let mut pair_balances = PAIR_BALANCES.load(deps.storage, pool_key.clone())?;
-    for (i, asset) in assets.iter().enumerate() {
-        pair_balances[i].amount = asset.amount;
+    for (balance, asset) in pair_balances.iter_mut().zip(assets) {
+        balance.amount = asset.amount;
     }
     PAIR_BALANCES.save(deps.storage, pool_key, &pair_balances)
 }
-
-pub fn generate_key_from_asset_info(assets: &[AssetInfo]) -> String {
-    std::str::from_utf8(&pair_key(&[assets[0].clone(), assets[1].clone()]))  // Added std::
-        .unwrap()
-        .to_string()
-}
-
-pub fn pair_key(asset_infos: &[AssetInfo]) -> Vec<u8> {
-    asset_infos
-        .iter()
-        .map(AssetInfo::as_bytes)
-        .sorted()
-        .flatten()
-        .copied()
-        .collect()
-}
\ No newline at end of file